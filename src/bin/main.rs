@@ -1,25 +1,71 @@
 use clap::Parser;
 use corge::{cli, command};
-use corge::cli::{CommandCli, CompilationDatabaseArgs};
+use corge::cli::{BuildArgs, CommandCli, CompilationDatabaseArgs, WatchArgs};
+use corge::error::ErrorKind;
 
 fn main() {
-    simple_logger::SimpleLogger::new().env().init().unwrap();
-
     let args = cli::CLI::parse();
+    let color = args.color.enabled();
+
+    simple_logger::SimpleLogger::new()
+        .with_level(args.log_level())
+        .with_colors(color)
+        .init()
+        .expect("Logger is initialized once");
+
+    let result = corge::std_command_ext::handle_interrupts()
+        .and_then(|_| execute(args.command, color));
+
+    if let Err(error) = result {
+        report_error(&error, color);
 
-    match args.command {
+        let exit_code = ErrorKind::of(&error).map_or(1, |kind| kind.exit_code());
+        std::process::exit(exit_code);
+    }
+}
+
+fn execute(command: CommandCli, color: bool) -> anyhow::Result<()> {
+    match command {
         CommandCli::Init(init_args) => {
-            let compdb_args = CompilationDatabaseArgs {
-                path: init_args.path.clone()
-            };
+            let path = init_args.path.clone();
+
+            command::init::init(init_args)?;
 
-            command::init::init(init_args).unwrap();
-            command::compilation_database::compilation_database(compdb_args).unwrap();
+            /* the project is usable without a database, e.g. before gcc is installed or its dependencies are reachable */
+            if let Err(error) = command::compilation_database::compilation_database(CompilationDatabaseArgs::new(path)) {
+                log::warn!("Failed to generate the compilation database, run 'corge-rs compdb' later: {:#}", error);
+            }
+
+            Ok(())
         },
-        CommandCli::Clean(clean_args) => command::clean::clean(clean_args).unwrap(),
-        CommandCli::Build(build_args) => { let _ = command::build::build(build_args).unwrap(); },
-        CommandCli::Run(build_args) => command::run::run(build_args),
-        CommandCli::Compdb(compdb_args) => command::compilation_database::compilation_database(compdb_args).unwrap(),
-        CommandCli::Clone(clone_args) => command::clone::clone(clone_args).unwrap(),
+        CommandCli::Clean(clean_args) => command::clean::clean(clean_args),
+        CommandCli::Build(build_args) => command::build::build(BuildArgs { color, ..build_args }).map(|_| ()),
+        CommandCli::Run(build_args) => command::run::run(BuildArgs { color, ..build_args }),
+        CommandCli::Watch(watch_args) => command::watch::watch(WatchArgs { build: BuildArgs { color, ..watch_args.build }, ..watch_args }),
+        CommandCli::Compdb(compdb_args) => command::compilation_database::compilation_database(compdb_args),
+        CommandCli::Cache(cache_args) => command::cache::cache(cache_args),
+        CommandCli::Clone(clone_args) => command::clone::clone(clone_args),
+        CommandCli::Map(map_args) => command::map::map(map_args),
+    }
+}
+
+/* error: <outermost context>
+     caused by: <next cause> ... */
+fn report_error(error: &anyhow::Error, color: bool) {
+    let (red, bold, reset) = if color { ("\x1b[1;31m", "\x1b[1m", "\x1b[0m") } else { ("", "", "") };
+
+    eprintln!("{}error{}: {}{}{}", red, reset, bold, error, reset);
+
+    for cause in error.chain().skip(1) {
+        let cause = cause.to_string();
+        let mut lines = cause.trim_end().lines();
+
+        if let Some(first_line) = lines.next() {
+            eprintln!("  caused by: {}", first_line);
+        }
+
+        for line in lines {
+            eprintln!("    {}", line);
+        }
     }
 }
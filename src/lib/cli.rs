@@ -1,9 +1,12 @@
 use crate::config::LinkStrategy;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::io::IsTerminal;
 use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Debug, Default, Clone)]
+#[derive(ValueEnum, Debug, Default, Clone, PartialEq)]
 pub enum BuildModeCli {
     #[default]
     Development,
@@ -19,6 +22,15 @@ impl Display for BuildModeCli {
     }
 }
 
+#[derive(ValueEnum, Debug, Default, Clone, PartialEq)]
+pub enum MessageFormat {
+    /// Progress messages through the logger.
+    #[default]
+    Human,
+    /// Newline-delimited JSON build events on stdout.
+    Json,
+}
+
 #[derive(Parser, Debug, Clone)]
 pub struct InitArgs {
     #[arg(default_value = "./", value_name = "PATH")]
@@ -27,6 +39,72 @@ pub struct InitArgs {
     /// Initializes the project without a git repository.
     #[arg(long, default_value = "false", value_name = "NO_GIT")]
     pub no_git: bool,
+
+    /// Initializes an executable project (default).
+    #[arg(long, group = "link_strategy")]
+    pub executable: bool,
+
+    /// Initializes a static library project.
+    #[arg(long, visible_alias = "s-lib", group = "link_strategy")]
+    pub static_library: bool,
+
+    /// Initializes a dynamic library project.
+    #[arg(long, visible_alias = "d-lib", group = "link_strategy")]
+    pub dynamic_library: bool,
+
+    /// Initializes the project from a built-in template, a user template, a template directory or a git URL.
+    #[arg(long, group = "link_strategy", value_name = "TEMPLATE")]
+    pub template: Option<String>,
+
+    /// Sets a template placeholder, e.g. --set author="Jane Doe"; prompts of the template aren't asked for it.
+    #[arg(long = "set", value_parser = parse_key_value, value_name = "KEY=VALUE")]
+    pub values: Vec<(String, String)>,
+
+    /// Adopts the C sources already in the directory, writing a build.yaml for their layout.
+    #[arg(long, conflicts_with_all = ["template", "values"])]
+    pub existing: bool,
+
+    /// Imports defines and flags from a compile_commands.json when adopting existing sources.
+    #[arg(long, requires = "existing", value_name = "PATH")]
+    pub compile_commands: Option<PathBuf>,
+}
+impl InitArgs {
+    pub fn link_strategy(&self) -> LinkStrategy {
+        self.declared_link_strategy().unwrap_or(LinkStrategy::Executable)
+    }
+
+    /** The link strategy flag that was passed, if any */
+    pub fn declared_link_strategy(&self) -> Option<LinkStrategy> {
+        match (self.executable, self.static_library, self.dynamic_library) {
+            (true, _, _) => Some(LinkStrategy::Executable),
+            (_, true, _) => Some(LinkStrategy::StaticLibrary),
+            (_, _, true) => Some(LinkStrategy::DynamicLibrary),
+            _ => None,
+        }
+    }
+
+    /** The --template, or the built-in template of the selected link strategy */
+    pub fn template(&self) -> String {
+        match &self.template {
+            Some(template) => template.clone(),
+            None => match self.link_strategy() {
+                LinkStrategy::Executable => "executable".to_string(),
+                LinkStrategy::StaticLibrary => "static-library".to_string(),
+                LinkStrategy::DynamicLibrary => "dynamic-library".to_string(),
+            },
+        }
+    }
+
+    pub fn values(&self) -> BTreeMap<String, String> {
+        self.values.iter().cloned().collect()
+    }
+}
+
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("'{}' is not KEY=VALUE", value))
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -37,6 +115,118 @@ pub struct CleanArgs {
     /// Clean the dependencies directory too.
     #[arg(long)]
     pub deps_too: bool,
+
+    /// Cleans only the given workspace members, or fetched dependencies and their cached objects.
+    #[arg(short = 'p', long = "package", value_name = "PACKAGE")]
+    pub packages: Vec<String>,
+
+    /// Cleans only the builds of the given build profile.
+    #[arg(long, value_enum, value_name = "PROFILE")]
+    pub profile: Option<BuildModeCli>,
+
+    /// Cleans only the builds of the given toolchain, e.g. default, custom or a toolchain from build.yaml.
+    #[arg(long, value_name = "NAME")]
+    pub toolchain: Option<String>,
+
+    /// Cleans only the builds for the given target triple.
+    #[arg(long, value_name = "TRIPLE")]
+    pub target: Option<String>,
+
+    /// Removes the compilation database too.
+    #[arg(long)]
+    pub compdb: bool,
+
+    /// Lists what would be removed and how much space it would free, without removing anything.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+impl CleanArgs {
+    /** Whether only some of the builds under target/ are cleaned */
+    pub fn selects_builds(&self) -> bool {
+        self.profile.is_some() || self.toolchain.is_some() || self.target.is_some()
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CacheCommand {
+    /// Shows how many object files every cache directory holds and their size.
+    Stats,
+    /// Removes object files that weren't used recently, or the least recently used ones above a size.
+    #[group(required = true, multiple = true)]
+    Prune {
+        /// Removes objects no build used for this long, e.g. 30m, 12h, 7d or 2w.
+        #[arg(long, value_parser = parse_duration, value_name = "AGE")]
+        older_than: Option<Duration>,
+
+        /// Removes the least recently used objects until the cache fits, e.g. 500M or 2G.
+        #[arg(long, value_parser = parse_size, value_name = "SIZE")]
+        max_size: Option<u64>,
+    },
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct CacheArgs {
+    #[arg(default_value = "./", value_name = "PATH")]
+    pub path: PathBuf,
+
+    #[command(subcommand)]
+    pub command: CacheCommand,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct MapArgs {
+    #[arg(default_value = "./", value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Map file to read instead of the map_file of build.yaml.
+    #[arg(long, value_name = "FILE")]
+    pub map_file: Option<PathBuf>,
+
+    /// Counts only this output section in the object table, e.g. .text.
+    #[arg(long, value_name = "SECTION")]
+    pub section: Option<String>,
+
+    /// Lists only the largest objects.
+    #[arg(long, value_name = "COUNT")]
+    pub top: Option<usize>,
+}
+
+/* "45s", "30m", "12h", "7d", "2w", or plain seconds */
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = value.split_at(value.find(|char: char| !char.is_ascii_digit()).unwrap_or(value.len()));
+    let number: u64 = number.parse().map_err(|_| format!("'{}' is not a duration like 12h or 7d", value))?;
+
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Unknown duration unit '{}', expected s, m, h, d or w", unit)),
+    };
+
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Duration '{}' is too long", value))
+}
+
+/* "500M", "2G", "100K", or plain bytes; units are powers of 1024 */
+fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, unit) = value.split_at(value.find(|char: char| !char.is_ascii_digit()).unwrap_or(value.len()));
+    let number: u64 = number.parse().map_err(|_| format!("'{}' is not a size like 500M or 2G", value))?;
+
+    let bytes = match unit.to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Unknown size unit '{}', expected K, M or G", unit)),
+    };
+
+    number
+        .checked_mul(bytes)
+        .ok_or_else(|| format!("Size '{}' is too large", value))
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -62,10 +252,30 @@ pub struct CloneArgs {
     pub source: CloneSource,
 }
 
+#[derive(ValueEnum, Debug, Clone)]
+pub enum RootCompilationDatabase {
+    /// Writes a copy of compile_commands.json to the project root.
+    Write,
+    /// Symlinks compile_commands.json in the project root to the generated file.
+    Symlink,
+}
+
 #[derive(Parser, Debug, Clone)]
 pub struct CompilationDatabaseArgs {
-    #[arg(default_value = "./", value_name = "PATH")]
-    pub path: PathBuf,
+    #[command(flatten)]
+    pub build: BuildArgs,
+
+    /// Also places compile_commands.json in the project root, where language tools look for it.
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub root: Option<RootCompilationDatabase>,
+}
+impl CompilationDatabaseArgs {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            build: BuildArgs::new(path),
+            root: None,
+        }
+    }
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -98,9 +308,9 @@ pub struct BuildArgs {
     #[command(subcommand)]
     pub toolchain: Option<BuildToolchain>,
 
-    /// Specifies the link strategy.
-    #[arg(long, value_enum, default_value = "executable", value_name = "LINK_STRATEGY",)]
-    pub link: LinkStrategy,
+    /// Overrides the link strategy declared in build.yaml.
+    #[arg(long, value_enum, value_name = "LINK_STRATEGY")]
+    pub link: Option<LinkStrategy>,
 
     /// Builds the project in release mode (optimized).
     #[arg(long, group = "build_mode")]
@@ -109,8 +319,83 @@ pub struct BuildArgs {
     /// Builds the project in development mode (debug info).
     #[arg(long, group = "build_mode")]
     pub dev: bool,
+
+    /// Cross-compiles for the given target triple, e.g. aarch64-linux-gnu.
+    #[arg(long, value_name = "TRIPLE")]
+    pub target: Option<String>,
+
+    /// Builds only the given workspace members (and the members they depend on).
+    #[arg(short = 'p', long = "package", value_name = "MEMBER")]
+    pub packages: Vec<String>,
+
+    /// Enables features, comma-separated or repeated; `dependency/feature` enables a feature of a dependency.
+    #[arg(short = 'F', long, value_delimiter = ',', value_name = "FEATURES")]
+    pub features: Vec<String>,
+
+    /// Enables every feature of the project.
+    #[arg(long, conflicts_with = "features")]
+    pub all_features: bool,
+
+    /// Doesn't enable the default feature of the project.
+    #[arg(long)]
+    pub no_default_features: bool,
+
+    /// Prints the effective toolchain and where each value comes from, without building.
+    #[arg(long)]
+    pub print_toolchain: bool,
+
+    /// Selects how build progress is reported.
+    #[arg(long, value_enum, default_value = "human", value_name = "FORMAT")]
+    pub message_format: MessageFormat,
+
+    /// Writes the compiler diagnostics of the build to a SARIF file.
+    #[arg(long, value_name = "PATH")]
+    pub sarif: Option<PathBuf>,
+
+    /// Kills a compiler, linker or git command that runs longer than this.
+    #[arg(long, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+
+    /// Writes timings.json and timings.html with how long every fetch, compilation and link took.
+    #[arg(long)]
+    pub timings: bool,
+
+    /// Adds GCC's -ftime-report to compilations and aggregates it per file in the timings.
+    #[arg(long, requires = "timings")]
+    pub time_report: bool,
+
+    /// Whether compiler diagnostics are colored, set from the global --color option.
+    #[arg(skip)]
+    pub color: bool,
 }
 impl BuildArgs {
+    /** A development build with the default toolchain */
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            toolchain: None,
+            link: None,
+            release: false,
+            dev: false,
+            target: None,
+            packages: vec![],
+            features: vec![],
+            all_features: false,
+            no_default_features: false,
+            print_toolchain: false,
+            message_format: MessageFormat::Human,
+            sarif: None,
+            timeout: None,
+            timings: false,
+            time_report: false,
+            color: false,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+
     pub fn build_mode(&self) -> BuildModeCli {
         match (self.release, self.dev) {
             (true, false) => BuildModeCli::Release,
@@ -121,6 +406,38 @@ impl BuildArgs {
 }
 
 
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq)]
+pub enum WatchCommand {
+    /// Rebuilds the project.
+    #[default]
+    Build,
+    /// Rebuilds the project and restarts its executable.
+    Run,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub build: BuildArgs,
+
+    /// What to do after every change.
+    #[arg(short = 'x', long = "exec", value_enum, default_value = "build", value_name = "COMMAND")]
+    pub exec: WatchCommand,
+
+    /// Polls for changes instead of using the file system notifications of the platform.
+    #[arg(long)]
+    pub poll: bool,
+
+    /// Waits this long after a change for more changes before rebuilding.
+    #[arg(long, default_value = "300", value_name = "MILLISECONDS")]
+    pub debounce: u64,
+}
+impl WatchArgs {
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce)
+    }
+}
+
 #[derive(Subcommand, Debug, Clone)]
 pub enum CommandCli {
     /// Clone a project
@@ -133,8 +450,35 @@ pub enum CommandCli {
     Build(BuildArgs),
     /// Builds and runs the project.
     Run(BuildArgs),
+    /// Rebuilds (or reruns) the project whenever its sources, headers or build.yaml change.
+    Watch(WatchArgs),
     /// Generates a compile_commands.json for the project.
+    ///
+    /// Like a build, it fetches missing dependencies and runs the generator steps, but compiles nothing.
     Compdb(CompilationDatabaseArgs),
+    /// Inspects or prunes the object file cache.
+    Cache(CacheArgs),
+    /// Shows the size of every section and object file from the map file of the last link.
+    Map(MapArgs),
+}
+
+#[derive(ValueEnum, Debug, Default, Clone, PartialEq)]
+pub enum ColorChoice {
+    /// Colors when writing to a terminal and NO_COLOR isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+impl ColorChoice {
+    /** Whether messages written to stderr are colored */
+    pub fn enabled(&self) -> bool {
+        match self {
+            ColorChoice::Auto => std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -142,4 +486,66 @@ pub enum CommandCli {
 pub struct CLI {
     #[command(subcommand)]
     pub command: CommandCli,
+
+    /// Logs more details, -vv logs every command that is run.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Logs only warnings, -qq logs only errors.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub quiet: u8,
+
+    /// Colors log messages and errors.
+    #[arg(long, value_enum, default_value = "auto", value_name = "WHEN", global = true)]
+    pub color: ColorChoice,
+}
+impl CLI {
+    pub fn log_level(&self) -> log::LevelFilter {
+        match (self.verbose, self.quiet) {
+            (0, 0) => log::LevelFilter::Info,
+            (1, _) => log::LevelFilter::Debug,
+            (_, 0) => log::LevelFilter::Trace,
+            (_, 1) => log::LevelFilter::Warn,
+            (_, _) => log::LevelFilter::Error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+
+    #[test]
+    fn parse_duration_overflow_is_an_error() {
+        assert_eq!(
+            parse_duration("99999999999999999w"),
+            Err("Duration '99999999999999999w' is too long".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("500M"), Ok(500 * 1024 * 1024));
+        assert_eq!(parse_size("2GiB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("100kb"), Ok(100 * 1024));
+        assert!(parse_size("1T").is_err());
+    }
+
+    #[test]
+    fn parse_size_overflow_is_an_error() {
+        assert_eq!(
+            parse_size("99999999999999999G"),
+            Err("Size '99999999999999999G' is too large".to_string())
+        );
+    }
 }
@@ -1,6 +1,9 @@
 pub mod init;
 pub mod build;
 pub mod run;
+pub mod watch;
 pub mod clean;
+pub mod cache;
 pub mod compilation_database;
 pub mod clone;
+pub mod map;
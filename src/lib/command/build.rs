@@ -1,105 +1,952 @@
 pub mod target_path;
 pub mod dependency_path;
 
-use crate::cli::{BuildArgs, BuildModeCli};
+use crate::cli::{BuildArgs, BuildModeCli, MessageFormat};
 use crate::command::build::dependency_path::DependencyPath;
 use crate::command::build::target_path::TargetPath;
-use crate::config::{Config, LinkStrategy, OptimizationLevel, Profile};
-use crate::tool::compiler::Compiler;
+use crate::config::{Config, LinkStrategy, OptimizationLevel, Profile, Toolchain};
+use crate::error::{ErrorKind, KindContext};
+use crate::reporter::{DiagnosticCollector, Event, Reporter};
+use crate::tool::cfg::Cfg;
+use crate::tool::compiler::{CompileOptions, Compiler, SourceSettings};
 use crate::tool::configuration_parser::ConfigurationParser;
-use crate::tool::dependency_include_fetcher::DependencyIncludeFetcher;
-use crate::tool::dependency_source_fetcher::DependencySourceFetcher;
-use crate::tool::files_fetcher::fetch_files;
-use crate::tool::linker::Linker;
+use crate::tool::dependency_include_fetcher::{self, DependencyIncludeFetcher};
+use crate::tool::dependency_source_fetcher::{self, Artifact, DependencySelection, DependencySourceFetcher, FetchedDependencies};
+use crate::tool::diagnostic_parser;
+use crate::tool::feature_resolver::{FeatureRequest, FeatureResolver, FeatureSet};
+use crate::tool::files_fetcher::SourceLayout;
+use crate::tool::generator::{self, GeneratedSources, GeneratorRunner};
+use crate::tool::resource_embedder::ResourceEmbedder;
+use crate::tool::linker::{LinkSettings, Linker};
+use crate::tool::lockfile::{LockedSource, Lockfile};
+use crate::tool::object_cache::UsedObjects;
+use crate::tool::post_linker::PostLinker;
+use crate::tool::sarif;
+use crate::tool::timings::TimingRecorder;
+use crate::tool::toolchain_environment::{EffectiveToolchain, ToolchainEnvironment};
+use crate::tool::toolchain_probe::{ToolchainInfo, ToolchainProbe};
+use crate::tool::version_header::{BuildInfo, GitState, VersionHeaderWriter};
+use crate::tool::workspace_resolver::{self, WorkspaceResolver};
 use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use target_lexicon::Triple;
 
-pub fn build(build_args: BuildArgs) -> Result<PathBuf> {
+/** A linked project */
+pub struct BuildOutput {
+    pub name: String,
+    pub link_strategy: LinkStrategy,
+    pub path: PathBuf,
+}
+
+/**
+    Builds the project at the given path, or every selected member when the path is a workspace root.
+
+    @return: the linked projects, in build order
+ */
+pub fn build(build_args: BuildArgs) -> Result<Vec<BuildOutput>> {
     let project_path = build_args.path.clone();
 
     log::info!("Building project in directory {:?}", &project_path);
 
-    /** Configuration parsing */
+    /* Configuration parsing */
     log::info!("Parsing build.yaml file");
     let config = ConfigurationParser::new(project_path.clone())
         .parse()
-        .context("Corge project is not detected")?;
+        .kind_context(ErrorKind::Configuration, "Corge project is not detected")?;
+
+    if build_args.print_toolchain {
+        for (config, location) in locate_projects(&build_args, config)? {
+            if let Some(member_name) = &location.member_name {
+                println!("[{}]", member_name);
+            }
+
+            print!("{}", resolve_toolchain(&build_args, &config)?);
+        }
+
+        return Ok(vec![]);
+    }
+
+    let previous_lockfile = Lockfile::read(&project_path)?;
+
+    /* a single project only keeps a lockfile when it has one, e.g. an empty build.lock */
+    let write_lockfile = config.workspace.is_some() || Lockfile::exists(&project_path);
+
+    /* a partial workspace build keeps the entries of the members it doesn't touch */
+    let mut lockfile = if build_args.packages.is_empty() {
+        Lockfile::default()
+    } else {
+        previous_lockfile.clone()
+    };
+
+    let reporter = crate::reporter::reporter(&build_args.message_format);
+    let collector = DiagnosticCollector::new(reporter.as_ref());
+    let recorder = TimingRecorder::new(&collector);
+    let mut build_outputs = vec![];
+
+    let mut used_objects = UsedObjects::default();
+
+    /* objects of earlier versions of the sources would otherwise pile up */
+    let mut result = build_projects(&build_args, config, &previous_lockfile, &mut lockfile, &recorder, &mut build_outputs, &mut used_objects)
+        .and_then(|_| used_objects.prune().context("Failed to remove unused objects"))
+        .and_then(|_| if write_lockfile {
+            lockfile.write(&project_path).context("Failed to write lockfile")
+        } else {
+            Ok(())
+        });
+
+    let build_mode = build_args.build_mode().to_string();
+    let timings = recorder.into_timings(&build_mode, build_args.target.as_deref());
+
+    /* a header warning is reported by every source that includes it */
+    let diagnostics = diagnostic_parser::deduplicate(collector.into_diagnostics());
+    reporter.report(crate::reporter::diagnostic_summary(&diagnostics));
+
+    if let Some(sarif_path) = &build_args.sarif {
+        let sarif_result = sarif::write(sarif_path, &diagnostics);
+        result = result.and(sarif_result);
+    }
+
+    if build_args.timings {
+        let timings_path = TargetPath::build_mode_path(&project_path, build_args.target.as_deref(), &build_mode);
+
+        match timings.write(&timings_path) {
+            Ok((_, html_path)) => log::info!("Build timings written to {:?}", html_path),
+            Err(error) => result = result.and(Err(error)),
+        }
+    }
+
+    reporter.report(Event::BuildFinished {
+        success: result.is_ok(),
+        artifacts: build_outputs.iter().map(|build_output| build_output.path.clone()).collect(),
+    });
+
+    result?;
+
+    Ok(build_outputs)
+}
+
+fn build_projects(
+    build_args: &BuildArgs,
+    config: Config,
+    previous_lockfile: &Lockfile,
+    lockfile: &mut Lockfile,
+    reporter: &dyn Reporter,
+    build_outputs: &mut Vec<BuildOutput>,
+    used_objects: &mut UsedObjects,
+) -> Result<()> {
+    for (config, location) in locate_projects(build_args, config)? {
+        let build_output = match &location.member_name {
+            Some(member_name) => {
+                log::info!("Building workspace member '{}'", member_name);
+
+                build_project(build_args, config, &location, previous_lockfile, lockfile, reporter, used_objects)
+                    .with_context(|| format!("Failed to build workspace member '{}'", member_name))?
+            }
+            None => build_project(build_args, config, &location, previous_lockfile, lockfile, reporter, used_objects)?,
+        };
+
+        build_outputs.push(build_output);
+    }
+
+    Ok(())
+}
+
+/**
+    Where a project lives and where its build directories go.
+    Workspace members share the dependency and target directories of the workspace root.
+ */
+pub(crate) struct ProjectLocation {
+    pub project_path: PathBuf,
+    pub root_path: PathBuf,
+    pub member_name: Option<String>,
+    pub workspace_members: HashMap<String, PathBuf>,
+}
+
+/**
+    Returns the project at the build path, or the selected workspace members in dependency order.
+ */
+pub(crate) fn locate_projects(build_args: &BuildArgs, config: Config) -> Result<Vec<(Config, ProjectLocation)>> {
+    let root_path = build_args.path.clone();
+
+    let Some(workspace) = config.workspace.clone() else {
+        if !build_args.packages.is_empty() {
+            return Err(anyhow::anyhow!("Packages can only be selected in a workspace"))
+                .kind_context(ErrorKind::Configuration, "Failed to select packages");
+        }
+
+        let location = ProjectLocation {
+            project_path: root_path.clone(),
+            root_path,
+            member_name: None,
+            workspace_members: HashMap::new(),
+        };
+
+        return Ok(vec![(config, location)]);
+    };
+
+    let members = WorkspaceResolver::new(root_path.clone(), workspace)
+        .resolve()
+        .kind_context(ErrorKind::Configuration, "Failed to resolve workspace members")?;
+
+    let workspace_members = workspace_resolver::member_paths(&members);
+
+    let projects = workspace_resolver::select(members, &build_args.packages)
+        .kind_context(ErrorKind::Configuration, "Failed to select packages")?
+        .into_iter()
+        .map(|member| {
+            let location = ProjectLocation {
+                project_path: member.path,
+                root_path: root_path.clone(),
+                member_name: Some(member.name),
+                workspace_members: workspace_members.clone(),
+            };
+
+            (member.config, location)
+        })
+        .collect();
+
+    Ok(projects)
+}
+
+/**
+    A project whose toolchain is resolved, directories are created and dependencies are fetched,
+    ready to be compiled.
+ */
+pub(crate) struct PreparedProject {
+    pub name: String,
+    pub link_strategy: LinkStrategy,
+    pub profile: Profile,
+    pub toolchain: Toolchain,
+    pub toolchain_info: ToolchainInfo,
+    pub dependency_path: DependencyPath,
+    pub target_path: TargetPath,
+    pub artifacts: Vec<Artifact>,
+    pub compile_options: CompileOptions,
+    pub source_layout: SourceLayout,
+    pub source_settings: SourceSettings,
+    pub features: FeatureSet,
+    /// Linker script, map file and linker flags of the project; dynamic library dependencies link without them.
+    pub link_settings: LinkSettings,
+    /// Outputs of the generator steps, by project or dependency name.
+    pub generated: HashMap<String, GeneratedSources>,
+}
+
+pub(crate) enum SourceGroupKind {
+    /// Compiled into the project.
+    StaticDependency,
+    /// Linked into its own dynamic library, with the linker flags of the dependency, which the project links against.
+    DynamicDependency { library_name: String, linker_flags: Vec<String> },
+    Project,
+}
+
+/** Source files compiled with the same settings into the same cache directory */
+pub(crate) struct SourceGroup {
+    pub name: String,
+    pub kind: SourceGroupKind,
+    pub source_files: Vec<PathBuf>,
+    pub output_path: PathBuf,
+    pub settings: SourceSettings,
+}
+
+impl PreparedProject {
+    pub fn compiler<'a>(&'a self, reporter: &'a dyn Reporter) -> Compiler<'a> {
+        Compiler::new(
+            self.profile.clone(),
+            self.toolchain.clone(),
+            &self.toolchain_info,
+            self.dependency_path.include.clone(),
+            reporter,
+            self.compile_options,
+        )
+    }
+
+    /** Dependencies first, in fetch order, then the project itself */
+    pub fn source_groups(&self) -> Result<Vec<SourceGroup>> {
+        let pic = self.source_settings.pic;
+
+        let mut source_groups = vec![];
+
+        for artifact in &self.artifacts {
+            let output_path = self.target_path.build_mode.toolchain.cache.dependency.join(&artifact.dependency.name);
+            fs::create_dir_all(&output_path)
+                .with_context(|| format!("Failed to create directory {:?}", &output_path))?;
+
+            let mut source_files = SourceLayout::new(&artifact.path, &artifact.config)
+                .and_then(|layout| layout.files("c"))
+                .with_context(|| format!("Failed to fetch source files for dependency {}", &artifact.dependency.name))?;
+
+            /* dependencies are linked into the project unless they declare otherwise */
+            let dependency_link_strategy = artifact.config.project.link.clone().unwrap_or(LinkStrategy::StaticLibrary);
+
+            let (kind, pic) = match dependency_link_strategy {
+                LinkStrategy::Executable => {
+                    anyhow::bail!("Dependency '{}' is an executable and can't be linked", &artifact.dependency.name);
+                }
+                LinkStrategy::StaticLibrary => (SourceGroupKind::StaticDependency, pic),
+                LinkStrategy::DynamicLibrary => {
+                    let library_name = artifact.config.project.name.clone();
+                    let linker_flags = artifact.config.linker_flags.clone();
+
+                    (SourceGroupKind::DynamicDependency { library_name, linker_flags }, true)
+                }
+            };
+
+            let mut settings = source_settings(&artifact.path, &artifact.config, pic, self.features.features(&artifact.dependency.name));
+            self.add_generated(&artifact.dependency.name, &mut source_files, &mut settings);
+
+            source_groups.push(SourceGroup {
+                name: artifact.dependency.name.clone(),
+                kind,
+                source_files,
+                output_path,
+                settings,
+            });
+        }
+
+        let mut source_files = self.source_layout
+            .files("c")
+            .context("Failed to fetch source files for project")?;
+
+        let mut settings = self.source_settings.clone();
+        self.add_generated(&self.name, &mut source_files, &mut settings);
+
+        source_groups.push(SourceGroup {
+            name: self.name.clone(),
+            kind: SourceGroupKind::Project,
+            source_files,
+            output_path: self.target_path.build_mode.toolchain.cache.project.clone(),
+            settings,
+        });
+
+        Ok(source_groups)
+    }
+
+    /*
+       generated C files are compiled with the sources, and their directory is an include directory;
+       every source can include the version headers of the project and its dependencies
+     */
+    fn add_generated(&self, name: &str, source_files: &mut Vec<PathBuf>, settings: &mut SourceSettings) {
+        if let Some(generated) = self.generated.get(name) {
+            source_files.extend(generated.sources.iter().cloned());
+            settings.include_paths.push(generated.directory.clone());
+        }
+
+        settings.include_paths.push(self.target_path.build_mode.toolchain.version.clone());
+    }
+}
+
+/* The settings a project or dependency declares for its own sources; include directories are relative to it */
+fn source_settings(project_path: &Path, config: &Config, pic: bool, features: Vec<String>) -> SourceSettings {
+    SourceSettings {
+        pic,
+        features,
+        include_paths: config.includes.iter().map(|include| project_path.join(include)).collect(),
+        defines: config.defines.clone(),
+        compiler_flags: config.compiler_flags.clone(),
+    }
+}
+
+/* The linker script and map file of build.yaml, relative to the project, and its linker flags */
+fn link_settings(project_path: &Path, config: &Config) -> Result<LinkSettings> {
+    let linker_script = config.linker_script.as_ref().map(|linker_script| project_path.join(linker_script));
+
+    if let Some(linker_script) = &linker_script {
+        if !linker_script.is_file() {
+            anyhow::bail!("Linker script {:?} doesn't exist", linker_script);
+        }
+    }
+
+    let map_file = config.map_file.as_ref().map(|map_file| project_path.join(map_file));
+
+    if let Some(parent) = map_file.as_ref().and_then(|map_file| map_file.parent()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+
+    Ok(LinkSettings {
+        shared_libraries: vec![],
+        linker_script,
+        map_file,
+        linker_flags: config.linker_flags.clone(),
+    })
+}
+
+pub(crate) fn prepare_project(
+    build_args: &BuildArgs,
+    mut config: Config,
+    location: &ProjectLocation,
+    previous_lockfile: &Lockfile,
+    lockfile: &mut Lockfile,
+    reporter: &dyn Reporter,
+) -> Result<PreparedProject> {
+    let project_path = &location.project_path;
+
+    if config.project.name.is_empty() {
+        anyhow::bail!("build.yaml in {:?} has no project section", project_path);
+    }
 
     let build_mode = build_args.build_mode();
+    let link_strategy = config.link_strategy(build_args.link.clone());
     let profile = fetch_profile(&config, &build_mode);
-    let (toolchain_name, toolchain) = config.toolchain(build_args.toolchain)
-        .context("Failed to find toolchain in build.yaml file")?;
+    let target_triple = build_args.target.as_deref();
+    let effective_toolchain = resolve_toolchain(build_args, &config)?;
+    let toolchain_name = effective_toolchain.name.clone();
+    let mut toolchain = effective_toolchain.toolchain();
+
+    /* a relative sysroot belongs to the project that declares it */
+    toolchain.sysroot = toolchain.sysroot
+        .map(|sysroot| project_path.join(sysroot).display().to_string());
 
     /* Path definition */
-    let dependency_path = DependencyPath::create(&project_path)?;
-    let target_path = TargetPath::create(&project_path, &build_mode.to_string(), &toolchain_name)?;
+    let dependency_path = DependencyPath::create(&location.root_path)?;
+    let target_path = TargetPath::create(&location.root_path, target_triple, &build_mode.to_string(), &toolchain_name)?;
+    let target_path = match &location.member_name {
+        Some(member_name) => target_path.for_member(member_name)?,
+        None => target_path,
+    };
+
+    let toolchain_info = ToolchainProbe::new(location.root_path.join("target").join("toolchain-info"))
+        .probe(&toolchain)
+        .with_kind_context(ErrorKind::Configuration, || format!("Failed to inspect toolchain '{}'", &toolchain_name))?;
+
+    log::info!(
+        "Using {:?} compiler {:?} version {} for {}",
+        toolchain_info.family, &toolchain_info.compiler_path,
+        toolchain_info.version.as_deref().unwrap_or("unknown"), &toolchain_info.target
+    );
+
+    /* conditionals match the triple the compiler produces code for, which --target may not name */
+    let cfg = Cfg::new(&toolchain_info.target, &build_mode.to_string());
+    config.apply_conditionals(&cfg)
+        .kind_context(ErrorKind::Configuration, "Failed to apply conditionals of build.yaml")?;
 
-    /** Dependency fetching */
+    /* Dependency fetching */
     log::info!("Fetching dependencies");
-    let artifacts = DependencySourceFetcher::new(config.registries, config.dependencies, )
-        .fetch(&dependency_path.source)
-        .context("Failed to fetch dependency sources")?;
+    let (features, mut artifacts) = fetch_dependencies(
+        build_args, &config, location, &dependency_path.source, &cfg, previous_lockfile, reporter,
+    )?;
+
+    /* enabled features add their defines and sources to the project or dependency that declares them */
+    let project_features = features.features(&config.project.name);
+    config.apply_features(&project_features);
+
+    for artifact in &mut artifacts {
+        artifact.config.apply_features(&features.features(&artifact.dependency.name));
+    }
+
+    let source_layout = SourceLayout::new(project_path, &config)
+        .kind_context(ErrorKind::Configuration, "Invalid sources in build.yaml")?;
+
+    /* generate position-independent code if the project is a dynamic library */
+    let pic = matches!(link_strategy, LinkStrategy::DynamicLibrary);
+    let source_settings = source_settings(project_path, &config, pic, project_features);
+
+    let mut link_settings = link_settings(project_path, &config)
+        .kind_context(ErrorKind::Configuration, "Invalid linker settings in build.yaml")?;
+
+    /* libraries the project or its dependencies need are linked into the project */
+    for artifact in &artifacts {
+        link_settings.linker_flags.extend(artifact.config.linker_flags.iter().cloned());
+    }
+
+    dependency_source_fetcher::lock(&artifacts, lockfile);
 
     DependencyIncludeFetcher::new(&artifacts)
         .fetch(&dependency_path.include)
-        .context("Failed to fetch dependency headers")?;
+        .kind_context(ErrorKind::DependencyFetch, "Failed to fetch dependency headers")?;
+
+    /* Code generation */
+    let generated = generate_sources(build_args, &config, project_path, &artifacts, &target_path, &dependency_path.include, reporter)?;
+
+    let build_info = BuildInfo::new(&build_mode.to_string(), &toolchain_name, &toolchain_info.target);
+    write_version_headers(&config, project_path, &artifacts, &target_path.build_mode.toolchain.version, build_info)?;
 
-    /** Compilation */
+    Ok(PreparedProject {
+        name: config.project.name,
+        link_strategy,
+        profile,
+        toolchain,
+        toolchain_info,
+        dependency_path,
+        target_path,
+        artifacts,
+        compile_options: CompileOptions {
+            timeout: build_args.timeout(),
+            /* escape codes don't belong into JSON events */
+            color: build_args.color && build_args.message_format == MessageFormat::Human,
+            time_report: build_args.time_report,
+        },
+        source_layout,
+        source_settings,
+        features,
+        link_settings,
+        generated,
+    })
+}
+
+/**
+    Writes <corge_version/<name>.h> for the dependencies and the project.
+    The git state of a file system or workspace dependency is read where it lives, its copy has no repository.
+ */
+fn write_version_headers(
+    config: &Config,
+    project_path: &Path,
+    artifacts: &[Artifact],
+    version_path: &Path,
+    build_info: BuildInfo,
+) -> Result<()> {
+    /* a header that can't be written is an I/O error like a target directory that can't be created, not a compile error */
+    let writer = VersionHeaderWriter::new(version_path, build_info);
+
+    for artifact in artifacts {
+        let name = &artifact.dependency.name;
+        let repository_path = match &artifact.source {
+            LockedSource::Workspace(path) | LockedSource::FileSystem(path) => PathBuf::from(path),
+            LockedSource::Git { .. } => artifact.path.clone(),
+        };
+
+        writer.write(name, &artifact.config.project.version, GitState::read(&repository_path).as_ref())
+            .with_context(|| format!("Failed to write the version header of dependency '{}'", name))?;
+    }
+
+    writer.write(&config.project.name, &config.project.version, GitState::read(project_path).as_ref())
+        .context("Failed to write the version header of the project")?;
+
+    Ok(())
+}
+
+/**
+    Runs the generator steps and embeds the resources of the dependencies and of the project
+    into target/.../generated/<name>. Headers a dependency generates are exported with its other headers.
+ */
+fn generate_sources(
+    build_args: &BuildArgs,
+    config: &Config,
+    project_path: &Path,
+    artifacts: &[Artifact],
+    target_path: &TargetPath,
+    include_path: &Path,
+    reporter: &dyn Reporter,
+) -> Result<HashMap<String, GeneratedSources>> {
+    let generated_path = &target_path.build_mode.toolchain.generated;
+    let mut generated = HashMap::new();
+
+    for artifact in artifacts {
+        generator::check_names(&artifact.config.generators)
+            .with_kind_context(ErrorKind::Configuration, || format!("Invalid generators of dependency '{}'", &artifact.dependency.name))?;
+    }
+
+    generator::check_names(&config.generators)
+        .kind_context(ErrorKind::Configuration, "Invalid generators in build.yaml")?;
+
+    for artifact in artifacts {
+        let name = &artifact.dependency.name;
+
+        if artifact.config.generators.is_empty() && artifact.config.resources.is_empty() {
+            continue;
+        }
+
+        let sources = generate(build_args, name, &artifact.path, &artifact.config, &generated_path.join(name), reporter)
+            .with_kind_context(ErrorKind::Compile, || format!("Failed to generate sources of dependency '{}'", name))?;
+
+        dependency_include_fetcher::copy_headers(&sources.directory, &include_path.join(name))
+            .with_kind_context(ErrorKind::Compile, || format!("Failed to export generated headers of dependency '{}'", name))?;
+
+        generated.insert(name.clone(), sources);
+    }
+
+    if !config.generators.is_empty() || !config.resources.is_empty() {
+        let name = &config.project.name;
+
+        let sources = generate(build_args, name, project_path, config, &generated_path.join(name), reporter)
+            .kind_context(ErrorKind::Compile, "Failed to generate project sources")?;
+
+        generated.insert(name.clone(), sources);
+    }
+
+    Ok(generated)
+}
+
+fn generate(
+    build_args: &BuildArgs,
+    name: &str,
+    project_path: &Path,
+    config: &Config,
+    output_path: &Path,
+    reporter: &dyn Reporter,
+) -> Result<GeneratedSources> {
+    let mut sources = GeneratorRunner::new(project_path, output_path, reporter, build_args.timeout())
+        .run(name, &config.generators)?;
+
+    let resources = ResourceEmbedder::new(project_path, &sources.directory)
+        .embed(&config.resources)?;
+
+    sources.sources.extend(resources);
+
+    Ok(sources)
+}
+
+/**
+    Fetches the dependencies and resolves the features of the project and its dependencies.
+    A feature of a fetched dependency may enable an optional dependency only known once it is fetched,
+    so fetching repeats until the features enable no more dependencies; each pass only fetches the newly enabled ones.
+ */
+fn fetch_dependencies(
+    build_args: &BuildArgs,
+    config: &Config,
+    location: &ProjectLocation,
+    source_path: &Path,
+    cfg: &Cfg,
+    previous_lockfile: &Lockfile,
+    reporter: &dyn Reporter,
+) -> Result<(FeatureSet, Vec<Artifact>)> {
+    let request = FeatureRequest::new(build_args);
+
+    let mut features = FeatureResolver::new(&config.project.name, config, &[])
+        .resolve(&request)
+        .kind_context(ErrorKind::Configuration, "Failed to resolve features")?;
+
+    let fetched = FetchedDependencies::default();
+    let fetcher = DependencySourceFetcher::new(
+        location.project_path.clone(),
+        config,
+        &location.workspace_members,
+        previous_lockfile,
+        reporter,
+        build_args.timeout(),
+        &fetched,
+    );
+
+    loop {
+        let artifacts = fetcher
+            .fetch(source_path, DependencySelection { cfg, features: &features })
+            .kind_context(ErrorKind::DependencyFetch, "Failed to fetch dependency sources")?;
+
+        let resolved = FeatureResolver::new(&config.project.name, config, &artifacts)
+            .resolve(&request)
+            .kind_context(ErrorKind::Configuration, "Failed to resolve features")?;
+
+        if !resolved.enables_more_dependencies_than(&features) {
+            return Ok((resolved, artifacts));
+        }
+
+        features = resolved;
+    }
+}
+
+fn build_project(
+    build_args: &BuildArgs,
+    config: Config,
+    location: &ProjectLocation,
+    previous_lockfile: &Lockfile,
+    lockfile: &mut Lockfile,
+    reporter: &dyn Reporter,
+    used_objects: &mut UsedObjects,
+) -> Result<BuildOutput> {
+    let project = prepare_project(build_args, config, location, previous_lockfile, lockfile, reporter)?;
+
+    /* with -p, the members that aren't built still use the shared dependency caches */
+    let prune_dependencies = build_args.packages.is_empty();
+
+    /* Compilation */
     log::info!("Compiling project");
-    let compiler = Compiler::new(profile, toolchain.clone(), dependency_path.include.clone());
+    let compiler = project.compiler(reporter);
+    let linker = Linker::new(project.toolchain.clone(), &project.toolchain_info, reporter, project.compile_options.timeout);
 
-    /* generate position-independent code if the project is a dynamic library */
-    let pic = matches!(build_args.link, LinkStrategy::DynamicLibrary);
+    let output_path = &project.target_path.build_mode.toolchain.output;
 
     let mut object_files = vec![];
+    let mut link_settings = project.link_settings.clone();
 
-    /* compile dependencies artifacts */
-    for artifact in &artifacts {
-        let target_path = target_path.build_mode.toolchain.cache.dependency.join(&artifact.dependency.name);
-        fs::create_dir_all(&target_path)
-            .with_context(|| format!("Failed to create directory {:?}", &target_path))?;
+    for source_group in project.source_groups()? {
+        match source_group.kind {
+            SourceGroupKind::StaticDependency => {
+                let artifact_object_files = compiler
+                    .compile(&source_group.source_files, &source_group.output_path, &source_group.settings)
+                    .with_kind_context(ErrorKind::Compile, || format!("Failed to compile dependency '{}' artifact", &source_group.name))?;
+
+                if prune_dependencies {
+                    used_objects.add(&source_group.output_path, &artifact_object_files);
+                }
+
+                object_files.extend(artifact_object_files);
+            }
+            SourceGroupKind::DynamicDependency { library_name, linker_flags } => {
+                let artifact_object_files = compiler
+                    .compile(&source_group.source_files, &source_group.output_path, &source_group.settings)
+                    .with_kind_context(ErrorKind::Compile, || format!("Failed to compile dependency '{}' artifact", &source_group.name))?;
 
-        let source_files = fetch_files(&artifact.path.join("src"), "c")
-            .with_context(|| format!("Failed to fetch source files for dependency {}", &artifact.dependency.name))?;
+                if prune_dependencies {
+                    used_objects.add(&source_group.output_path, &artifact_object_files);
+                }
 
-        let artifact_object_files = compiler
-            .compile(&source_files, &target_path, pic)
-            .with_context(|| format!("Failed to compile dependency '{}' artifact", &artifact.dependency.name))?;
+                /* a dynamic library links the libraries its own build.yaml asks for */
+                let library_settings = LinkSettings {
+                    linker_flags,
+                    ..LinkSettings::default()
+                };
 
-        object_files.extend(artifact_object_files);
+                let library_path = linker.link(&LinkStrategy::DynamicLibrary, &artifact_object_files, &library_settings, output_path, &library_name)
+                    .with_kind_context(ErrorKind::Link, || format!("Failed to link dependency '{}'", &source_group.name))?;
+
+                reporter.report(Event::Artifact {
+                    name: source_group.name.clone(),
+                    link_strategy: LinkStrategy::DynamicLibrary,
+                    path: library_path,
+                });
+
+                link_settings.shared_libraries.push(library_name);
+            }
+            SourceGroupKind::Project => {
+                let project_object_files = compiler
+                    .compile(&source_group.source_files, &source_group.output_path, &source_group.settings)
+                    .kind_context(ErrorKind::Compile, "Failed to compile project files")?;
+
+                used_objects.add(&source_group.output_path, &project_object_files);
+                object_files.extend(project_object_files);
+            }
+        }
     }
 
-    /* compile project sources */
-    let source_files = fetch_files(&project_path.join("src"), "c")
-        .context("Failed to fetch source files for project")?;
+    /* Linking */
+    let output_file_path = linker.link(&project.link_strategy, &object_files, &link_settings, output_path, &project.name)
+        .kind_context(ErrorKind::Link, "Failed to link project")?;
 
-    let project_object_files = compiler
-        .compile(&source_files, &target_path.build_mode.toolchain.cache.project, pic)
-        .context("Failed to compile project files")?;
-    object_files.extend(project_object_files);
+    /* Post-link steps */
+    let post_link = project.profile.post_link.as_ref().or(project.toolchain.post_link.as_ref());
 
-    /** Linking */
-    log::info!("Linking project");
-    let linker = Linker::new(toolchain);
-    let output_file_path = linker.link(&build_args.link, &object_files, &target_path.build_mode.toolchain.output, &config.project.name)
-        .context("Failed to link project")?;
+    match (post_link, &project.link_strategy) {
+        (None, _) => {}
+        (Some(_), LinkStrategy::StaticLibrary) => {
+            log::warn!("Skipping the post-link steps of static library '{}'", &project.name);
+        }
+        (Some(post_link), _) => {
+            PostLinker::new(&project.toolchain, reporter, project.compile_options.timeout)
+                .run(&project.name, &output_file_path, post_link)
+                .kind_context(ErrorKind::Link, "Failed to run the post-link steps of the project")?;
+        }
+    }
+
+    reporter.report(Event::Artifact {
+        name: project.name.clone(),
+        link_strategy: project.link_strategy.clone(),
+        path: output_file_path.clone(),
+    });
+
+    Ok(BuildOutput {
+        name: project.name.clone(),
+        link_strategy: project.link_strategy.clone(),
+        path: output_file_path,
+    })
+}
+
+pub(crate) fn resolve_toolchain(build_args: &BuildArgs, config: &Config) -> Result<EffectiveToolchain> {
+    let target_triple = build_args.target.as_deref();
+    if let Some(target_triple) = target_triple {
+        Triple::from_str(target_triple)
+            .map_err(|error| anyhow::anyhow!("{}", error))
+            .with_kind_context(ErrorKind::Configuration, || format!("Invalid target triple '{}'", target_triple))?;
+    }
+
+    let (toolchain_name, toolchain, origin) = config.toolchain(build_args.toolchain.clone(), target_triple)
+        .kind_context(ErrorKind::Configuration, "Failed to find toolchain in build.yaml file")?;
 
-    log::info!("BUILD SUCCESSFUL");
-    Ok(output_file_path)
+    Ok(ToolchainEnvironment::from_env().apply(&toolchain_name, toolchain, origin))
 }
 
-fn fetch_profile(config: &Config, build_mode: &BuildModeCli,) -> Profile {
+fn fetch_profile(config: &Config, build_mode: &BuildModeCli) -> Profile {
     match build_mode {
         BuildModeCli::Development => {
-            config.profiles.development.clone().unwrap_or_else(|| Profile {
-                optimization_level: OptimizationLevel::O
+            config.profiles.development.clone().unwrap_or(Profile {
+                optimization_level: OptimizationLevel::O,
+                debug: true,
+                defines: vec![],
+                post_link: None,
             })
         }
         BuildModeCli::Release => {
-            config.profiles.release.clone().unwrap_or_else(|| Profile {
-                optimization_level: OptimizationLevel::Ofast
+            config.profiles.release.clone().unwrap_or(Profile {
+                optimization_level: OptimizationLevel::Ofast,
+                debug: false,
+                defines: vec![],
+                post_link: None,
             })
         }
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::cli::BuildToolchain;
+    use crate::tool::lockfile::LOCKFILE_NAME;
+    use std::os::unix::fs::PermissionsExt;
+
+    /* answers the probe like a cross gcc, records its arguments and creates the files -o and -MF ask for */
+    const STUB_COMPILER: &str = r#"#!/bin/sh
+echo "$@" >> "$(dirname "$0")/calls.log"
+output=""
+previous=""
+for argument in "$@"; do
+    case "$argument" in
+        --version) echo "gcc (stub) 12.2.0"; exit 0 ;;
+        -dumpmachine) echo "aarch64-unknown-linux-gnu"; exit 0 ;;
+    esac
+    if [ "$previous" = "-o" ]; then output="$argument"; fi
+    if [ "$previous" = "-MF" ]; then : > "$argument"; fi
+    previous="$argument"
+done
+if [ -n "$output" ]; then : > "$output"; fi
+"#;
+
+    fn stub_project(name: &str) -> PathBuf {
+        let project_path = std::env::temp_dir().join(format!("corge-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&project_path);
+        fs::create_dir_all(project_path.join("src")).unwrap();
+
+        fs::write(project_path.join("build.yaml"), r#"
+project:
+  name: app
+  version: 1.0.0
+targets:
+  aarch64-linux-gnu:
+    sysroot: sysroot
+"#).unwrap();
+        fs::write(project_path.join("src").join("main.c"), "int main(void) { return 0; }\n").unwrap();
+
+        let compiler_path = project_path.join("stub-gcc");
+        fs::write(&compiler_path, STUB_COMPILER).unwrap();
+        fs::set_permissions(&compiler_path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        project_path
+    }
+
+    fn stub_build(project_path: &Path, target: Option<&str>) -> Result<Vec<BuildOutput>> {
+        let toolchain = BuildToolchain::Custom {
+            compiler: project_path.join("stub-gcc").display().to_string(),
+            archiver: "ar".to_string(),
+            compiler_flags: vec![],
+            linker_flags: vec![],
+        };
+
+        build(BuildArgs {
+            toolchain: Some(toolchain),
+            target: target.map(str::to_string),
+            ..BuildArgs::new(project_path.to_path_buf())
+        })
+    }
+
+    #[test]
+    fn target_builds_are_isolated_per_triple() {
+        let project_path = stub_project("target-isolation");
+
+        let outputs = stub_build(&project_path, Some("aarch64-linux-gnu")).unwrap();
+        assert_eq!(outputs[0].path, project_path.join("target/aarch64-linux-gnu/development/custom/output/app"));
+
+        stub_build(&project_path, None).unwrap();
+        assert!(project_path.join("target/development/custom/output/app").is_file());
+        assert!(project_path.join("target/aarch64-linux-gnu/development/custom/output/app").is_file());
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn target_sysroot_is_resolved_against_the_project() {
+        let project_path = stub_project("target-sysroot");
+
+        stub_build(&project_path, Some("aarch64-linux-gnu")).unwrap();
+
+        let calls = fs::read_to_string(project_path.join("calls.log")).unwrap();
+        let sysroot = format!("--sysroot={}", project_path.join("sysroot").display());
+
+        /* the probe, the compilation and the link all see the sysroot */
+        assert!(calls.lines().filter(|call| call.contains("-dumpmachine")).all(|call| call.contains(&sysroot)));
+        assert!(calls.lines().any(|call| call.contains("-c") && call.contains("main.c") && call.contains(&sysroot)));
+        assert!(calls.lines().any(|call| call.contains("output/app") && call.contains(&sysroot)));
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn invalid_target_triple_is_a_configuration_error() {
+        let project_path = stub_project("invalid-target");
+
+        let error = stub_build(&project_path, Some("not a triple")).err().unwrap();
+        assert_eq!(ErrorKind::of(&error), Some(ErrorKind::Configuration));
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn single_project_keeps_a_lockfile_only_when_it_has_one() {
+        let project_path = stub_project("lockfile-opt-in");
+
+        stub_build(&project_path, None).unwrap();
+        assert!(!project_path.join(LOCKFILE_NAME).exists());
+
+        fs::write(project_path.join(LOCKFILE_NAME), "").unwrap();
+        stub_build(&project_path, None).unwrap();
+        assert_eq!(fs::read_to_string(project_path.join(LOCKFILE_NAME)).unwrap(), "dependencies: []\n");
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn linker_script_must_exist() {
+        let project_path = stub_project("linker-script");
+        fs::write(project_path.join("build.yaml"), "project:\n  name: app\n  version: 1.0.0\nlinker_script: board.ld\n").unwrap();
+
+        let error = stub_build(&project_path, None).err().unwrap();
+        assert_eq!(ErrorKind::of(&error), Some(ErrorKind::Configuration));
+        assert!(format!("{:#}", error).contains("board.ld\" doesn't exist"));
+
+        fs::write(project_path.join("board.ld"), "").unwrap();
+        stub_build(&project_path, None).unwrap();
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn libraries_are_linked_after_the_objects_project_first() {
+        let project_path = stub_project("link-order");
+
+        fs::write(project_path.join("build.yaml"), r#"
+project:
+  name: app
+  version: 1.0.0
+registries:
+  local: !FileSystem registry
+dependencies:
+  - name: core
+    registry_name: local
+linker_flags: [-lm]
+"#).unwrap();
+
+        let core_path = project_path.join("registry/core");
+        fs::create_dir_all(core_path.join("src")).unwrap();
+        fs::write(core_path.join("build.yaml"), "project:\n  name: core\n  version: 1.0.0\nlinker_flags: [-lpthread]\n").unwrap();
+        fs::write(core_path.join("src/core.c"), "int core(void) { return 0; }\n").unwrap();
+
+        stub_build(&project_path, None).unwrap();
+
+        let calls = fs::read_to_string(project_path.join("calls.log")).unwrap();
+        let link: Vec<&str> = calls
+            .lines()
+            .find(|call| call.contains("output/app"))
+            .unwrap()
+            .split_whitespace()
+            .collect();
+
+        let position = |predicate: &dyn Fn(&str) -> bool| link.iter().position(|argument| predicate(argument)).unwrap();
+        let last_object = link.iter().rposition(|argument| argument.ends_with(".o")).unwrap();
+
+        assert!(position(&|argument| argument.contains("main.c.")) < position(&|argument| argument == "-lm"));
+        assert!(last_object < position(&|argument| argument == "-lm"));
+        assert!(position(&|argument| argument == "-lm") < position(&|argument| argument == "-lpthread"));
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+}
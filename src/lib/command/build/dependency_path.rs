@@ -1,5 +1,5 @@
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub struct DependencyPath {
     pub source: PathBuf,
@@ -7,7 +7,7 @@ pub struct DependencyPath {
 }
 
 impl DependencyPath {
-    pub fn create(project_path: &PathBuf) -> Result<Self> {
+    pub fn create(project_path: &Path) -> Result<Self> {
         let dependency_path = project_path.join("dependency");
 
         let this = Self {
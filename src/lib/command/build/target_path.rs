@@ -1,6 +1,6 @@
 use anyhow::Context;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub struct TargetCachePath {
     pub project: PathBuf,
@@ -10,6 +10,10 @@ pub struct TargetCachePath {
 pub struct TargetToolchainPath {
     pub cache: TargetCachePath,
     pub output: PathBuf,
+    /// Outputs of generator steps, by project or dependency name.
+    pub generated: PathBuf,
+    /// corge_version/<name>.h of the project and its dependencies, on the include path of every source.
+    pub version: PathBuf,
 }
 
 pub struct TargetBuildModePath {
@@ -21,8 +25,13 @@ pub struct TargetPath {
 }
 
 impl TargetPath {
-    pub fn create(project_path: &PathBuf, build_mode: &str, toolchain_name: &str) -> anyhow::Result<Self> {
-        let toolchain_path = project_path.join("target").join(build_mode).join(toolchain_name);
+    /**
+        @param: root_path - directory that holds the target directory (the project or the workspace root)
+        @param: target_triple - cross-compilation target, isolated under target/<triple>/
+     */
+    pub fn create(root_path: &Path, target_triple: Option<&str>, build_mode: &str, toolchain_name: &str) -> anyhow::Result<Self> {
+        let build_mode_path = Self::build_mode_path(root_path, target_triple, build_mode);
+        let toolchain_path = build_mode_path.join(toolchain_name);
         let cache_path = toolchain_path.join("cache");
 
         let this = Self {
@@ -33,6 +42,8 @@ impl TargetPath {
                         dependency: cache_path.join("dependency"),
                     },
                     output: toolchain_path.join("output"),
+                    generated: toolchain_path.join("generated"),
+                    version: toolchain_path.join("version"),
                 }
             }
         };
@@ -46,4 +57,49 @@ impl TargetPath {
 
         Ok(this)
     }
+
+    /** target/[<triple>/]<build_mode>, shared by every toolchain */
+    pub fn build_mode_path(root_path: &Path, target_triple: Option<&str>, build_mode: &str) -> PathBuf {
+        let target_path = match target_triple {
+            Some(target_triple) => root_path.join("target").join(target_triple),
+            None => root_path.join("target"),
+        };
+
+        target_path.join(build_mode)
+    }
+
+    /** Finds the target/[<triple>/]<build_mode>/<toolchain> directories, recognized by their cache directory */
+    pub fn toolchain_directories(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        if !path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        if path.join("cache").is_dir() {
+            return Ok(vec![path.to_path_buf()]);
+        }
+
+        let mut directories = vec![];
+
+        for entry in fs::read_dir(path).with_context(|| format!("Failed to read directory {:?}", path))? {
+            let entry = entry?;
+
+            if entry.path().is_dir() {
+                directories.extend(Self::toolchain_directories(&entry.path())?);
+            }
+        }
+
+        Ok(directories)
+    }
+
+    /* Workspace members share the target directory, but each keeps its own project cache */
+    pub fn for_member(mut self, member_name: &str) -> anyhow::Result<Self> {
+        let member_cache = self.build_mode.toolchain.cache.project.join(member_name);
+
+        fs::create_dir_all(&member_cache)
+            .context("Failed to create target member cache directory")?;
+
+        self.build_mode.toolchain.cache.project = member_cache;
+
+        Ok(self)
+    }
 }
@@ -0,0 +1,89 @@
+use crate::cli::{CacheArgs, CacheCommand};
+use crate::command::build::target_path::TargetPath;
+use crate::tool::object_cache::{self, CachedObject};
+use anyhow::Result;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/**
+    Builds already remove the objects they don't use from the caches they compile into.
+    This handles the caches of other profiles, toolchains and targets, and bounds the total size, e.g. on CI runners.
+ */
+pub fn cache(cache_args: CacheArgs) -> Result<()> {
+    let target_directory = cache_args.path.join("target");
+    let toolchain_directories = TargetPath::toolchain_directories(&target_directory)?;
+
+    let mut caches = vec![];
+    for cache_directory in object_cache::cache_directories(&toolchain_directories)? {
+        let objects = object_cache::cached_objects(&cache_directory)?;
+
+        if !objects.is_empty() {
+            caches.push((cache_directory, objects));
+        }
+    }
+
+    match cache_args.command {
+        CacheCommand::Stats => {
+            stats(&caches);
+            Ok(())
+        }
+        CacheCommand::Prune { older_than, max_size } => prune(caches, older_than, max_size),
+    }
+}
+
+fn stats(caches: &[(PathBuf, Vec<CachedObject>)]) {
+    let mut total_objects = 0;
+    let mut total_size = 0;
+
+    for (cache_directory, objects) in caches {
+        let size: u64 = objects.iter().map(|object| object.size).sum();
+
+        println!("{}: {} object(s), {}", cache_directory.display(), objects.len(), object_cache::format_size(size));
+
+        total_objects += objects.len();
+        total_size += size;
+    }
+
+    println!("Total: {} object(s), {}", total_objects, object_cache::format_size(total_size));
+}
+
+fn prune(caches: Vec<(PathBuf, Vec<CachedObject>)>, older_than: Option<Duration>, max_size: Option<u64>) -> Result<()> {
+    let mut objects: Vec<CachedObject> = caches.into_iter().flat_map(|(_, objects)| objects).collect();
+    let mut removed = vec![];
+
+    if let Some(older_than) = older_than {
+        let cutoff = SystemTime::now().checked_sub(older_than).unwrap_or(SystemTime::UNIX_EPOCH);
+        let (old, recent): (Vec<_>, Vec<_>) = objects.into_iter().partition(|object| object.last_used < cutoff);
+
+        removed.extend(old);
+        objects = recent;
+    }
+
+    if let Some(max_size) = max_size {
+        /* least recently used first */
+        objects.sort_by_key(|object| object.last_used);
+
+        let mut size: u64 = objects.iter().map(|object| object.size).sum();
+        let mut objects_iter = objects.into_iter();
+
+        while size > max_size {
+            let Some(object) = objects_iter.next() else {
+                break;
+            };
+
+            size -= object.size;
+            removed.push(object);
+        }
+    }
+
+    for object in &removed {
+        object.remove()?;
+
+        log::debug!("Removed cached object {}", object.key);
+    }
+
+    let freed: u64 = removed.iter().map(|object| object.size).sum();
+    log::info!("Removed {} object(s), freed {}", removed.len(), object_cache::format_size(freed));
+
+    Ok(())
+}
@@ -1,20 +1,215 @@
 use crate::cli::CleanArgs;
+use crate::command::build::target_path::TargetPath;
+use crate::error::{ErrorKind, KindContext};
+use crate::tool::configuration_parser::ConfigurationParser;
+use crate::tool::object_cache;
+use crate::tool::workspace_resolver::WorkspaceResolver;
 use anyhow::{Context, Result};
 use std::fs;
+use std::path::{Path, PathBuf};
 
 pub fn clean(clean_args: CleanArgs) -> Result<()> {
+    let mut removals: Vec<(PathBuf, u64)> = vec![];
+
+    for path in removals_of(&clean_args)? {
+        /* symlink_metadata, so a dangling compile_commands.json symlink is removed too */
+        if fs::symlink_metadata(&path).is_ok() && !removals.iter().any(|(removal, _)| removal == &path) {
+            let size = disk_size(&path)?;
+            removals.push((path, size));
+        }
+    }
+
+    let freed: u64 = removals.iter().map(|(_, size)| size).sum();
+
+    if clean_args.dry_run {
+        for (path, size) in &removals {
+            println!("Would remove {} ({})", path.display(), object_cache::format_size(*size));
+        }
+
+        println!("Would free {}", object_cache::format_size(freed));
+        return Ok(());
+    }
+
+    for (path, _) in &removals {
+        remove(path)?;
+        log::debug!("Removed {:?}", path);
+    }
+
+    log::info!("Removed {} path(s), freed {}", removals.len(), object_cache::format_size(freed));
+
+    Ok(())
+}
+
+/* The paths the options select, some of which may not exist */
+fn removals_of(clean_args: &CleanArgs) -> Result<Vec<PathBuf>> {
     let target_directory = clean_args.path.join("target");
     let dependency_directory = clean_args.path.join("dependency");
 
-    if target_directory.exists() {
-        fs::remove_dir_all(&target_directory)
-            .with_context(|| format!("Failed to remove directory {:?}", &target_directory))?;
+    let mut removals = vec![];
+
+    if !clean_args.packages.is_empty() {
+        removals.extend(package_removals(clean_args)?);
+    } else if clean_args.selects_builds() {
+        removals.extend(selected_toolchain_directories(clean_args)?);
+    } else {
+        removals.push(target_directory);
     }
 
-    if dependency_directory.exists() && clean_args.deps_too {
-        fs::remove_dir_all(&dependency_directory)
-            .with_context(|| format!("Failed to remove directory {:?}", &dependency_directory))?;
+    if clean_args.deps_too {
+        if clean_args.packages.is_empty() {
+            removals.push(dependency_directory);
+        } else {
+            log::warn!("Dependencies are shared by the whole workspace and are not cleaned per member");
+        }
     }
 
-    Ok(())
+    if clean_args.compdb {
+        removals.push(clean_args.path.join("compilation_database"));
+
+        /* a symlink placed by `compdb --root symlink` would dangle; a copy may be the user's own */
+        let root_json_path = clean_args.path.join("compile_commands.json");
+        if fs::symlink_metadata(&root_json_path).is_ok_and(|metadata| metadata.is_symlink()) {
+            removals.push(root_json_path);
+        }
+    }
+
+    Ok(removals)
+}
+
+/**
+    Finds the target/[<triple>/]<build_mode>/<toolchain> directories matching --profile, --toolchain and --target.
+    Without --target, the builds of every target are selected.
+ */
+fn selected_toolchain_directories(clean_args: &CleanArgs) -> Result<Vec<PathBuf>> {
+    let target_directory = clean_args.path.join("target");
+    let profile = clean_args.profile.as_ref().map(|profile| profile.to_string());
+
+    let directories = TargetPath::toolchain_directories(&target_directory)?
+        .into_iter()
+        .filter(|directory| {
+            let Ok(relative_path) = directory.strip_prefix(&target_directory) else {
+                return false;
+            };
+
+            let components: Vec<String> = relative_path
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().to_string())
+                .collect();
+
+            let (triple, build_mode, toolchain) = match components.as_slice() {
+                [build_mode, toolchain] => (None, build_mode, toolchain),
+                [triple, build_mode, toolchain] => (Some(triple), build_mode, toolchain),
+                _ => return false,
+            };
+
+            clean_args.target.as_ref().is_none_or(|target| triple == Some(target))
+                && profile.as_ref().is_none_or(|profile| build_mode == profile)
+                && clean_args.toolchain.as_ref().is_none_or(|name| toolchain == name)
+        })
+        .collect();
+
+    Ok(directories)
+}
+
+/**
+    A workspace member loses its cached objects, generated sources, version header and outputs.
+    A fetched dependency loses its sources, headers, cached objects and outputs, so the next build fetches it again.
+ */
+fn package_removals(clean_args: &CleanArgs) -> Result<Vec<PathBuf>> {
+    let config = ConfigurationParser::new(clean_args.path.clone())
+        .parse()
+        .kind_context(ErrorKind::Configuration, "Corge project is not detected")?;
+
+    let members = match config.workspace {
+        Some(workspace) => WorkspaceResolver::new(clean_args.path.clone(), workspace)
+            .resolve()
+            .kind_context(ErrorKind::Configuration, "Failed to resolve workspace members")?,
+        None => vec![],
+    };
+
+    let toolchain_directories = selected_toolchain_directories(clean_args)?;
+    let dependency_directory = clean_args.path.join("dependency");
+
+    let mut removals = vec![];
+
+    for package in &clean_args.packages {
+        let is_member = members.iter().any(|member| &member.name == package);
+        let is_dependency = !is_member && (
+            dependency_directory.join("source").join(package).exists()
+                || toolchain_directories.iter().any(|directory| directory.join("cache").join("dependency").join(package).exists())
+        );
+
+        if !is_member && !is_dependency {
+            return Err(anyhow::anyhow!("Package '{}' is neither a workspace member nor a fetched dependency", package))
+                .kind_context(ErrorKind::Configuration, "Failed to select packages to clean");
+        }
+
+        if is_dependency && !clean_args.selects_builds() {
+            removals.push(dependency_directory.join("source").join(package));
+            removals.push(dependency_directory.join("include").join(package));
+        }
+
+        let cache_kind = if is_member { "project" } else { "dependency" };
+
+        for toolchain_directory in &toolchain_directories {
+            removals.push(toolchain_directory.join("cache").join(cache_kind).join(package));
+            removals.push(toolchain_directory.join("generated").join(package));
+            removals.push(toolchain_directory.join("version").join("corge_version").join(format!("{}.h", package)));
+            removals.extend(outputs(&toolchain_directory.join("output"), package)?);
+        }
+    }
+
+    Ok(removals)
+}
+
+/* Output artifacts are named <name>, lib<name>.<ext> or <name>.<ext> */
+fn outputs(output_directory: &Path, name: &str) -> Result<Vec<PathBuf>> {
+    if !output_directory.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut outputs = vec![];
+
+    for entry in fs::read_dir(output_directory).with_context(|| format!("Failed to read directory {:?}", output_directory))? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        let stem = file_name.split('.').next().unwrap_or_default();
+        let is_output = stem == name || stem.strip_prefix("lib") == Some(name);
+
+        if is_output && entry.path().is_file() {
+            outputs.push(entry.path());
+        }
+    }
+
+    Ok(outputs)
+}
+
+fn disk_size(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to read metadata of {:?}", path))?;
+
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+
+    for entry in fs::read_dir(path).with_context(|| format!("Failed to read directory {:?}", path))? {
+        size += disk_size(&entry?.path())?;
+    }
+
+    Ok(size)
+}
+
+fn remove(path: &Path) -> Result<()> {
+    let is_dir = fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir());
+
+    let result = if is_dir {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    result.with_context(|| format!("Failed to remove {:?}", path))
 }
@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 use std::process::Command;
 use crate::cli::{CloneArgs, CloneSource};
-use crate::std_command_ext::ExecuteCommand;
+use crate::std_command_ext::{CliStr, ExecuteCommand};
 use crate::tool::dir_copier::deep_copy;
 
 fn clone_git(url: String, branch: String, destination: PathBuf) -> anyhow::Result<()> {
@@ -11,13 +11,19 @@ fn clone_git(url: String, branch: String, destination: PathBuf) -> anyhow::Resul
 
     let mut command = Command::new("git");
     command.arg("clone");
+    command.arg("--progress");
     command.arg(url);
     command.arg("--single-branch");
     command.arg("--branch");
     command.arg(branch);
     command.arg(destination);
 
-    command.execute(true)?;
+    log::trace!("{}", command.cli_str());
+
+    let output = command.stream(None, &mut |_, line| log::info!("{}", line))?;
+    if !output.success {
+        anyhow::bail!("git clone {}", output.status);
+    }
 
     Ok(())
 }
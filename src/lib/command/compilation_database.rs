@@ -1,54 +1,122 @@
 pub mod compilation_database_path;
 
-use crate::cli::{CompilationDatabaseArgs};
-use crate::command::build::dependency_path::DependencyPath;
-use crate::tool::files_fetcher::fetch_files;
-use anyhow::{bail, Context, Result};
+use crate::cli::{CompilationDatabaseArgs, RootCompilationDatabase};
+use crate::command::build::{locate_projects, prepare_project};
+use crate::command::compilation_database::compilation_database_path::CompilationDatabasePath;
+use crate::error::{ErrorKind, KindContext};
+use crate::reporter::LogReporter;
+use crate::std_command_ext::CliStr;
+use crate::tool::configuration_parser::ConfigurationParser;
+use crate::tool::lockfile::Lockfile;
+use anyhow::{Context, Result};
 use serde::Serialize;
+use std::collections::HashSet;
 use std::fs;
-use crate::command::compilation_database::compilation_database_path::CompilationDatabasePath;
+use std::path::{Path, PathBuf};
 
 #[derive(Serialize)]
 struct CompileCommand {
     directory: String,
     file: String,
-    command: String,
+    arguments: Vec<String>,
+    output: String,
 }
 
 pub fn compilation_database(compilation_database_args: CompilationDatabaseArgs) -> Result<()> {
-    let project_path = compilation_database_args.path.clone();
+    log::info!("Generating compilation database in directory {:?}", &compilation_database_args.build.path);
+
+    /* absolute paths make the database independent of the directory corge-rs runs in */
+    let project_path = fs::canonicalize(&compilation_database_args.build.path)
+        .with_context(|| format!("Failed to resolve project directory {:?}", &compilation_database_args.build.path))?;
 
-    log::info!("Generating compilation database in directory {:?}", &project_path);
+    let mut build_args = compilation_database_args.build;
+    build_args.path = project_path.clone();
+
+    let config = ConfigurationParser::new(project_path.clone())
+        .parse()
+        .kind_context(ErrorKind::Configuration, "Corge project is not detected")?;
 
-    let dependency_path = DependencyPath::create(&project_path)?;
     let compilation_database_path = CompilationDatabasePath::create(&project_path)?;
 
-    // Collect project source files
-    let source_files_paths = fetch_files(&project_path.join("src"), "c").context("Failed to fetch source files for project")?;
+    /* dependencies are resolved as the build would, but the lockfile is left to the build */
+    let lockfile = Lockfile::read(&project_path)?;
+    let mut unused_lockfile = lockfile.clone();
+
+    let mut compile_commands: Vec<CompileCommand> = vec![];
+    let mut listed: HashSet<(PathBuf, PathBuf)> = HashSet::new();
+
+    for (config, location) in locate_projects(&build_args, config)? {
+        let project = prepare_project(&build_args, config, &location, &lockfile, &mut unused_lockfile, &LogReporter)?;
+        let compiler = project.compiler(&LogReporter);
 
-    let project_path = fs::canonicalize(project_path)?;
-    let include_path = fs::canonicalize(dependency_path.include)?;
+        for source_group in project.source_groups()? {
+            for source_file in &source_group.source_files {
+                /* a dependency shared by several workspace members is listed once */
+                if !listed.insert((source_file.clone(), project_path.clone())) {
+                    continue;
+                }
 
-    let compile_commands: Result<Vec<CompileCommand>> = source_files_paths
-        .iter()
-        .map(|source_file_path| {
-            let source_file_path = fs::canonicalize(source_file_path)?;
+                let output_file = compiler.object_file(source_file, &source_group.output_path, &source_group.settings)?;
+                let command = compiler.command(source_file, &output_file, &source_group.settings)
+                    .with_context(|| format!("Failed to generate compile command for {:?}", source_file))?;
 
-            Ok(CompileCommand {
-                directory: project_path.display().to_string(),
-                file: source_file_path.display().to_string(),
-                command: format!("gcc -c {} -I {}", source_file_path.display(), include_path.display())
-            })
-        }).collect();
+                let arguments = command.cli_args();
 
-    match compile_commands {
-        Ok(compile_commands) => {
-            let serialized = serde_json::to_string_pretty(&compile_commands)?;
-            fs::write(compilation_database_path.json, serialized)?;
+                compile_commands.push(CompileCommand {
+                    directory: project_path.display().to_string(),
+                    file: source_file.display().to_string(),
+                    arguments,
+                    output: output_file.display().to_string(),
+                });
+            }
         }
-        Err(err) => bail!("Failed to generate compile commands: {}", err)
+    }
+
+    let serialized = serde_json::to_string_pretty(&compile_commands)?;
+    fs::write(&compilation_database_path.json, serialized)
+        .with_context(|| format!("Failed to write {:?}", &compilation_database_path.json))?;
+
+    if let Some(root) = compilation_database_args.root {
+        place_in_root(&project_path, &compilation_database_path.json, root)?;
     }
 
     log::info!("COMPILATION DATABASE GENERATED SUCCESSFULLY");
     Ok(())
 }
+
+fn place_in_root(project_path: &Path, json_path: &Path, root: RootCompilationDatabase) -> Result<()> {
+    let root_json_path = project_path.join("compile_commands.json");
+
+    if fs::symlink_metadata(&root_json_path).is_ok() {
+        fs::remove_file(&root_json_path)
+            .with_context(|| format!("Failed to replace {:?}", &root_json_path))?;
+    }
+
+    match root {
+        RootCompilationDatabase::Write => {
+            fs::copy(json_path, &root_json_path)
+                .with_context(|| format!("Failed to write {:?}", &root_json_path))?;
+        }
+        RootCompilationDatabase::Symlink => {
+            let relative_json_path = json_path
+                .strip_prefix(project_path)
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|_| PathBuf::from(json_path));
+
+            symlink(&relative_json_path, &root_json_path)
+                .with_context(|| format!("Failed to symlink {:?} to {:?}", &root_json_path, &relative_json_path))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
@@ -1,12 +1,12 @@
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub struct CompilationDatabasePath {
     pub json: PathBuf,
 }
 
 impl CompilationDatabasePath {
-    pub fn create(project_path: &PathBuf) -> Result<Self> {
+    pub fn create(project_path: &Path) -> Result<Self> {
         let compilation_database_path = project_path.join("compilation_database");
 
         let this = Self {
@@ -1,41 +1,47 @@
+pub mod existing;
+pub mod template;
+
 use crate::cli::InitArgs;
+use crate::command::init::template::Template;
 use anyhow::{Context, Result};
 use std::fs;
+use std::path::Path;
 use std::process::Command;
 use crate::std_command_ext::ExecuteCommand;
 
-const MAIN_C_CONTENT: &str = r###"
-int main() {
-    return 0;
-}
-"###;
-
 const GITIGNORE_CONTENT: &str = r###"
 .idea/
 .vscode/
 target/
 dependency/
 compilation_database/
+compile_commands.json
 .env
 "###;
 
-const BUILD_YAML_CONTENT: &str = r###"
-project:
-  name: {{name}}
-  version: 1.0.0
-
-profiles:
-  release:
-    optimization_level: O
-  development:
-    optimization_level: None
-"###;
-
 pub fn init(init_args: InitArgs) -> Result<()> {
-    let project_name = init_args.path.file_name().unwrap().to_str().unwrap();
+    /* absolute, so "." is named after the current directory */
+    let absolute_path = std::path::absolute(&init_args.path)
+        .with_context(|| format!("Failed to resolve path {:?}", init_args.path))?;
+    let project_name = absolute_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow::anyhow!("Failed to name the project after {:?}", absolute_path))?;
 
     log::info!("Initializing project in directory {:?}", init_args.path);
 
+    if init_args.existing {
+        existing::adopt(&init_args.path, &project_name, init_args.declared_link_strategy(), init_args.compile_commands.as_deref())?;
+
+        /* an adopted tree may already be a repository with its own .gitignore */
+        if !init_args.no_git && !init_args.path.join(".git").exists() {
+            init_git(&init_args.path)?;
+        }
+
+        log::info!("PROJECT SUCCESSFULLY ADOPTED");
+        return Ok(());
+    }
+
     let directory_exists = fs::exists(&init_args.path)
         .with_context(|| format!("Failed to check if directory {:?} exists", init_args.path))?;
 
@@ -48,33 +54,32 @@ pub fn init(init_args: InitArgs) -> Result<()> {
         }
     }
 
-    let src_dir = init_args.path.join("src");
-    fs::create_dir_all(&src_dir)
-        .with_context(|| format!("Failed to create directory {:?}", &src_dir))?;
-
-    let main_c_content = MAIN_C_CONTENT.trim_start();
-    fs::write(&src_dir.join("main.c"), main_c_content)
-        .with_context(|| format!("Failed to create file {:?}", &src_dir.join("main.c")))?;
+    let template = Template::load(&init_args.template())
+        .with_context(|| format!("Failed to load template '{}'", init_args.template()))?;
 
-    let build_yaml_content = BUILD_YAML_CONTENT
-        .trim_start()
-        .replace("{{name}}", project_name);
-
-    fs::write(init_args.path.join("build.yaml"), build_yaml_content)
-        .with_context(|| format!("Failed to create file {:?}", &src_dir))?;
+    let values = template.values(template::builtin_values(&project_name), init_args.values())?;
+    template.render(&values, &init_args.path)?;
 
     if !init_args.no_git {
-        Command::new("git")
-            .arg("init")
-            .current_dir(&init_args.path)
-            .execute(true)
-            .with_context(|| format!("Failed to initialize git repository in {:?}", &init_args.path))?;
-
-        let gitignore_content = GITIGNORE_CONTENT.trim_start();
-        fs::write(&init_args.path.join(".gitignore"), gitignore_content)
-            .with_context(|| format!("Failed to create file {:?}", &src_dir))?;
+        init_git(&init_args.path)?;
     }
 
     log::info!("PROJECT SUCCESSFULLY INITIALIZED");
     Ok(())
 }
+
+fn init_git(project_path: &Path) -> Result<()> {
+    Command::new("git")
+        .arg("init")
+        .current_dir(project_path)
+        .execute(true)
+        .with_context(|| format!("Failed to initialize git repository in {:?}", project_path))?;
+
+    let gitignore_path = project_path.join(".gitignore");
+    if gitignore_path.exists() {
+        return Ok(());
+    }
+
+    fs::write(&gitignore_path, GITIGNORE_CONTENT.trim_start())
+        .with_context(|| format!("Failed to create file {:?}", &gitignore_path))
+}
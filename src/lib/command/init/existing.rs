@@ -0,0 +1,488 @@
+use crate::config::LinkStrategy;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/* directories that never hold the project's own sources */
+const SKIPPED_DIRECTORIES: [&str; 3] = ["target", "dependency", "compilation_database"];
+
+/* flags the profiles and the build set themselves */
+const MANAGED_FLAG_PREFIXES: [&str; 9] = ["-O", "-g", "-fPIC", "-fpic", "-MD", "-MMD", "-MF", "-MT", "-MQ"];
+
+/* flags whose value is the next argument, the pair is kept or dropped together */
+const SEPARATE_VALUE_FLAGS: [&str; 16] = [
+    "-o", "-x", "-MF", "-MT", "-MQ",
+    "-isystem", "-idirafter", "-iquote", "-include", "-imacros", "-isysroot",
+    "-target", "-arch", "-Xclang", "-Xpreprocessor", "-Xassembler",
+];
+
+/* separate value flags whose value is a path relative to the directory of the command */
+const PATH_VALUE_FLAGS: [&str; 6] = ["-isystem", "-idirafter", "-iquote", "-include", "-imacros", "-isysroot"];
+
+/** What adopting an existing C tree found, written into its build.yaml */
+struct Adoption {
+    link: LinkStrategy,
+    sources: Vec<String>,
+    includes: Vec<String>,
+    defines: Vec<String>,
+    /// Compiler and flags from compile_commands.json, written as the `imported` toolchain.
+    toolchain: Option<(String, Vec<String>)>,
+}
+
+/**
+    Writes a build.yaml for the C sources already in the project directory, keeping their layout:
+    the directories with .c files become the sources, the directories `#include`s resolve against become the includes.
+    A source defining main() makes the project an executable, otherwise it is proposed as a static library.
+
+    @param: link - the link strategy chosen on the command line, which wins over the detected one
+    @param: compile_commands - an existing compile_commands.json to import defines and flags from
+ */
+pub fn adopt(project_path: &Path, project_name: &str, link: Option<LinkStrategy>, compile_commands: Option<&Path>) -> Result<()> {
+    let build_yaml_path = project_path.join("build.yaml");
+    if build_yaml_path.exists() {
+        anyhow::bail!("{:?} is already a corge project", project_path);
+    }
+
+    let mut source_files = vec![];
+    let mut header_files = vec![];
+    scan(project_path, Path::new(""), &mut source_files, &mut header_files)?;
+
+    if source_files.is_empty() {
+        anyhow::bail!("No C sources found in {:?}", project_path);
+    }
+
+    log::info!("Found {} source(s) and {} header(s)", source_files.len(), header_files.len());
+
+    let mut main_files = vec![];
+    for source_file in &source_files {
+        let content = read(&project_path.join(source_file))?;
+
+        if defines_main(&content) {
+            main_files.push(source_file.display().to_string());
+        }
+    }
+
+    let detected_link = if main_files.is_empty() {
+        log::info!("No source defines main(), proposing a static library");
+        LinkStrategy::StaticLibrary
+    } else {
+        log::info!("main() is defined in {}, proposing an executable", main_files.join(", "));
+        LinkStrategy::Executable
+    };
+
+    if main_files.len() > 1 {
+        log::warn!("Several sources define main(); keep only one of them in the sources before building");
+    }
+
+    let mut adoption = Adoption {
+        link: link.unwrap_or(detected_link),
+        sources: source_directories(&source_files),
+        includes: include_directories(project_path, &source_files, &header_files)?,
+        defines: vec![],
+        toolchain: None,
+    };
+
+    if let Some(compile_commands) = compile_commands {
+        import_compile_commands(project_path, compile_commands, &mut adoption)
+            .with_context(|| format!("Failed to import {:?}", compile_commands))?;
+    }
+
+    fs::write(&build_yaml_path, build_yaml(project_name, &adoption))
+        .with_context(|| format!("Failed to create file {:?}", &build_yaml_path))?;
+
+    log::info!("Sources: {}; includes: {}", adoption.sources.join(", "), adoption.includes.join(", "));
+
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<String> {
+    let content = fs::read(path)
+        .with_context(|| format!("Failed to read {:?}", path))?;
+
+    /* legacy sources aren't always UTF-8 */
+    Ok(String::from_utf8_lossy(&content).to_string())
+}
+
+/* Collects .c and .h files, relative to the project directory */
+fn scan(project_path: &Path, relative_path: &Path, source_files: &mut Vec<PathBuf>, header_files: &mut Vec<PathBuf>) -> Result<()> {
+    let directory = project_path.join(relative_path);
+    let mut entries: Vec<_> = fs::read_dir(&directory)
+        .with_context(|| format!("Failed to read directory {:?}", &directory))?
+        .collect::<std::io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let path = relative_path.join(&file_name);
+
+        if entry.file_type()?.is_dir() {
+            let skipped = file_name.starts_with('.') || (relative_path.as_os_str().is_empty() && SKIPPED_DIRECTORIES.contains(&file_name.as_str()));
+
+            if !skipped {
+                scan(project_path, &path, source_files, header_files)?;
+            }
+        } else if file_name.ends_with(".c") {
+            source_files.push(path);
+        } else if file_name.ends_with(".h") {
+            header_files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/* `int main(` or `void main(` at the start of a definition, not a prototype */
+fn defines_main(content: &str) -> bool {
+    content.lines().any(|line| {
+        let line = line.trim();
+
+        let Some(position) = line.find("main") else {
+            return false;
+        };
+
+        let before = line[..position].trim_end();
+        let after = line[position + "main".len()..].trim_start();
+
+        (before == "int" || before == "void") && after.starts_with('(') && !line.ends_with(';')
+    })
+}
+
+/* The directories holding .c files, without the ones nested in another, since sources are searched recursively */
+fn source_directories(source_files: &[PathBuf]) -> Vec<String> {
+    let directories: BTreeSet<PathBuf> = source_files
+        .iter()
+        .map(|source_file| source_file.parent().map(Path::to_path_buf).unwrap_or_default())
+        .collect();
+
+    directories
+        .iter()
+        .filter(|directory| !directories.iter().any(|other| other != *directory && directory.starts_with(other)))
+        .map(|directory| relative_string(directory))
+        .collect()
+}
+
+/**
+    Resolves every `#include` against the project's headers. An include that isn't next to the including file
+    needs the directory it is relative to, e.g. `#include "net/socket.h"` found as lib/include/net/socket.h needs lib/include.
+ */
+fn include_directories(project_path: &Path, source_files: &[PathBuf], header_files: &[PathBuf]) -> Result<Vec<String>> {
+    let mut directories = BTreeSet::new();
+
+    for file in source_files.iter().chain(header_files) {
+        let content = read(&project_path.join(file))?;
+        let file_directory = file.parent().unwrap_or(Path::new(""));
+
+        for (include, quoted) in includes(&content) {
+            let include = Path::new(&include);
+
+            if quoted && header_files.iter().any(|header| header == &file_directory.join(include)) {
+                continue;
+            }
+
+            let Some(header) = header_files.iter().find(|header| header.ends_with(include)) else {
+                continue;
+            };
+
+            let mut directory = header.clone();
+            for _ in include.components() {
+                directory.pop();
+            }
+
+            directories.insert(relative_string(&directory));
+        }
+    }
+
+    Ok(directories.into_iter().collect())
+}
+
+/* `#include "name"` and `#include <name>`, with whether the name is quoted */
+fn includes(content: &str) -> Vec<(String, bool)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let directive = line.trim().strip_prefix('#')?.trim_start().strip_prefix("include")?.trim();
+
+            let (quoted, closing) = match directive.chars().next()? {
+                '"' => (true, '"'),
+                '<' => (false, '>'),
+                _ => return None,
+            };
+
+            let name = &directive[1..];
+            let end = name.find(closing)?;
+
+            Some((name[..end].to_string(), quoted))
+        })
+        .collect()
+}
+
+fn is_managed(flag: &str) -> bool {
+    MANAGED_FLAG_PREFIXES.iter().any(|prefix| flag.starts_with(prefix))
+}
+
+fn relative_string(path: &Path) -> String {
+    if path.as_os_str().is_empty() {
+        ".".to_string()
+    } else {
+        path.components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[derive(Deserialize)]
+struct CompileCommand {
+    directory: PathBuf,
+    #[serde(default)]
+    arguments: Vec<String>,
+    #[serde(default)]
+    command: Option<String>,
+}
+
+impl CompileCommand {
+    fn arguments(&self) -> Vec<String> {
+        match &self.command {
+            Some(command) if self.arguments.is_empty() => split_command(command),
+            _ => self.arguments.clone(),
+        }
+    }
+}
+
+/**
+    Takes the defines and flags every entry of the database shares, and the union of their include directories.
+    Optimization, debug and dependency file flags are left to the profiles and the build.
+ */
+fn import_compile_commands(project_path: &Path, compile_commands_path: &Path, adoption: &mut Adoption) -> Result<()> {
+    let compile_commands_str = fs::read_to_string(compile_commands_path)
+        .with_context(|| format!("Failed to read {:?}", compile_commands_path))?;
+    let compile_commands: Vec<CompileCommand> = serde_json::from_str(&compile_commands_str)
+        .with_context(|| format!("Failed to parse {:?}", compile_commands_path))?;
+
+    let project_path = fs::canonicalize(project_path)
+        .with_context(|| format!("Failed to resolve {:?}", project_path))?;
+
+    let mut compiler = None;
+    /* a flag with a separate value is one group, so the intersection keeps or drops the pair */
+    let mut common: Option<(Vec<String>, Vec<Vec<String>>)> = None;
+
+    for compile_command in &compile_commands {
+        let arguments = compile_command.arguments();
+        let Some((program, arguments)) = arguments.split_first() else {
+            continue;
+        };
+
+        compiler.get_or_insert_with(|| program.clone());
+
+        let mut defines = vec![];
+        let mut flags = vec![];
+        let mut arguments = arguments.iter();
+
+        while let Some(argument) = arguments.next() {
+            let mut value = |prefix: &str| match argument.strip_prefix(prefix) {
+                Some("") => arguments.next().cloned(),
+                Some(value) => Some(value.to_string()),
+                None => None,
+            };
+
+            if let Some(define) = value("-D") {
+                defines.push(define);
+            } else if let Some(include) = value("-I") {
+                let include_path = compile_command.directory.join(include);
+                let include_path = fs::canonicalize(&include_path).unwrap_or(include_path);
+
+                let include = match include_path.strip_prefix(&project_path) {
+                    Ok(relative_path) => relative_string(relative_path),
+                    Err(_) => include_path.display().to_string(),
+                };
+
+                if !adoption.includes.contains(&include) {
+                    adoption.includes.push(include);
+                }
+            } else if SEPARATE_VALUE_FLAGS.contains(&argument.as_str()) {
+                let Some(value) = arguments.next() else {
+                    continue;
+                };
+
+                /* the output, the language of the file and dependency files are the build's */
+                if argument == "-o" || argument == "-x" || is_managed(argument) {
+                    continue;
+                }
+
+                let value = if PATH_VALUE_FLAGS.contains(&argument.as_str()) {
+                    compile_command.directory.join(value).display().to_string()
+                } else {
+                    value.clone()
+                };
+
+                flags.push(vec![argument.clone(), value]);
+            } else if argument.starts_with('-') && argument != "-c" && !is_managed(argument) {
+                flags.push(vec![argument.clone()]);
+            }
+        }
+
+        common = Some(match common {
+            None => (defines, flags),
+            Some((common_defines, common_flags)) => (
+                common_defines.into_iter().filter(|define| defines.contains(define)).collect(),
+                common_flags.into_iter().filter(|flag| flags.contains(flag)).collect(),
+            ),
+        });
+    }
+
+    let (defines, flags) = common.unwrap_or_default();
+    log::info!("Imported {} define(s) and {} flag(s) from {:?}", defines.len(), flags.len(), compile_commands_path);
+
+    adoption.defines = defines;
+    adoption.toolchain = compiler.map(|compiler| (compiler, flags.concat()));
+
+    Ok(())
+}
+
+/* Splits a shell command line on whitespace, honoring quotes and backslashes */
+fn split_command(command: &str) -> Vec<String> {
+    let mut arguments = vec![];
+    let mut argument: Option<String> = None;
+    let mut quote = None;
+    let mut chars = command.chars();
+
+    while let Some(char) = chars.next() {
+        match (char, quote) {
+            ('\\', _) => {
+                if let Some(escaped) = chars.next() {
+                    argument.get_or_insert_with(String::new).push(escaped);
+                }
+            }
+            ('"' | '\'', None) => {
+                quote = Some(char);
+                argument.get_or_insert_with(String::new);
+            }
+            (char, Some(open)) if char == open => quote = None,
+            (char, None) if char.is_whitespace() => {
+                if let Some(argument) = argument.take() {
+                    arguments.push(argument);
+                }
+            }
+            (char, _) => argument.get_or_insert_with(String::new).push(char),
+        }
+    }
+
+    arguments.extend(argument);
+    arguments
+}
+
+/* YAML lists are written with JSON strings, which YAML reads as double-quoted scalars */
+fn yaml_list(indent: &str, values: &[String]) -> String {
+    values
+        .iter()
+        .map(|value| format!("{}- {}\n", indent, serde_json::to_string(value).unwrap_or_default()))
+        .collect()
+}
+
+fn build_yaml(project_name: &str, adoption: &Adoption) -> String {
+    let mut build_yaml = format!(
+        "project:\n  name: {}\n  version: 1.0.0\n  link: {}\n",
+        project_name,
+        adoption.link.to_yaml_tag()
+    );
+
+    if adoption.toolchain.is_some() {
+        build_yaml.push_str("  toolchain: imported\n");
+    }
+
+    build_yaml.push_str("\nsources:\n");
+    build_yaml.push_str(&yaml_list("  ", &adoption.sources));
+
+    if !adoption.includes.is_empty() {
+        build_yaml.push_str("\nincludes:\n");
+        build_yaml.push_str(&yaml_list("  ", &adoption.includes));
+    }
+
+    build_yaml.push_str("\nprofiles:\n");
+    for (profile, optimization_level) in [("release", "O"), ("development", "None")] {
+        build_yaml.push_str(&format!("  {}:\n    optimization_level: {}\n", profile, optimization_level));
+
+        if !adoption.defines.is_empty() {
+            build_yaml.push_str("    defines:\n");
+            build_yaml.push_str(&yaml_list("      ", &adoption.defines));
+        }
+    }
+
+    if let Some((compiler, flags)) = &adoption.toolchain {
+        build_yaml.push_str("\ntoolchains:\n  imported:\n");
+        build_yaml.push_str(&format!("    compiler: {}\n    archiver: ar\n", serde_json::to_string(compiler).unwrap_or_default()));
+
+        if flags.is_empty() {
+            build_yaml.push_str("    compiler_flags: []\n");
+        } else {
+            build_yaml.push_str("    compiler_flags:\n");
+            build_yaml.push_str(&yaml_list("      ", flags));
+        }
+
+        build_yaml.push_str("    linker_flags: []\n");
+    }
+
+    build_yaml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_command_honors_quotes_and_escapes() {
+        assert_eq!(
+            split_command(r#"gcc  -DNAME="a b" -I'inc dir' -DPATH=\"x\" -c main.c"#),
+            ["gcc", "-DNAME=a b", "-Iinc dir", "-DPATH=\"x\"", "-c", "main.c"]
+        );
+        assert_eq!(split_command(r#"cc -DEMPTY="" x.c"#), ["cc", "-DEMPTY=", "x.c"]);
+    }
+
+    #[test]
+    fn defines_main_skips_prototypes_and_other_names() {
+        assert!(defines_main("#include <stdio.h>\n\nint main(void) {\n    return 0;\n}\n"));
+        assert!(defines_main("void main ()\n{\n}\n"));
+        assert!(!defines_main("int main(void);\n"));
+        assert!(!defines_main("int domain(void) { return 0; }\n"));
+        assert!(!defines_main("static int main_loop(void) { return 0; }\n"));
+    }
+
+    #[test]
+    fn includes_tell_quoted_from_angled() {
+        let content = "#include \"net/socket.h\"\n  #  include <stdio.h>\n#include MACRO_HEADER\n#define X 1\n";
+
+        assert_eq!(includes(content), [("net/socket.h".to_string(), true), ("stdio.h".to_string(), false)]);
+    }
+
+    #[test]
+    fn import_keeps_separate_value_flags_together() {
+        let project_path = std::env::temp_dir().join(format!("corge-existing-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&project_path);
+        fs::create_dir_all(project_path.join("inc")).unwrap();
+
+        let directory = serde_json::to_string(&project_path).unwrap();
+        let compile_commands_path = project_path.join("compile_commands.json");
+        fs::write(&compile_commands_path, format!(r#"[
+  {{ "directory": {0}, "command": "cc -DA -DB -std=c11 -include config.h -Xclang -fno-pch -MT a.o -MF a.d -O2 -c a.c -o a.o" }},
+  {{ "directory": {0}, "arguments": ["cc", "-DA", "-std=c11", "-include", "config.h", "-Xclang", "-ast-dump", "-I", "inc", "-c", "b.c"] }}
+]"#, directory)).unwrap();
+
+        let mut adoption = Adoption {
+            link: LinkStrategy::Executable,
+            sources: vec![],
+            includes: vec![],
+            defines: vec![],
+            toolchain: None,
+        };
+        import_compile_commands(&project_path, &compile_commands_path, &mut adoption).unwrap();
+
+        let config_h = project_path.join("config.h").display().to_string();
+        assert_eq!(adoption.defines, ["A"]);
+        assert_eq!(adoption.includes, ["inc"]);
+        assert_eq!(adoption.toolchain, Some(("cc".to_string(), vec!["-std=c11".to_string(), "-include".to_string(), config_h])));
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+}
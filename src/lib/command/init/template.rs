@@ -0,0 +1,411 @@
+use crate::config::LinkStrategy;
+use crate::std_command_ext::ExecuteCommand;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/* optional file in the root of a template directory, not copied into the project */
+const MANIFEST_NAME: &str = "template.yaml";
+
+const MAIN_C_CONTENT: &str = r###"
+int main() {
+    return 0;
+}
+"###;
+
+const LIBRARY_H_CONTENT: &str = r###"
+#ifndef {{guard}}_H
+#define {{guard}}_H
+
+int {{identifier}}_answer(void);
+
+#endif
+"###;
+
+const LIBRARY_C_CONTENT: &str = r###"
+#include "{{name}}.h"
+
+int {{identifier}}_answer(void) {
+    return 42;
+}
+"###;
+
+const BUILD_YAML_CONTENT: &str = r###"
+project:
+  name: {{name}}
+  version: {{version}}
+  link: {{link}}
+
+profiles:
+  release:
+    optimization_level: O
+  development:
+    optimization_level: None
+"###;
+
+const WORKSPACE_YAML_CONTENT: &str = r###"
+workspace:
+  members:
+    - {{name}}
+    - tests
+"###;
+
+const TESTS_YAML_CONTENT: &str = r###"
+project:
+  name: {{name}}-tests
+  version: {{version}}
+  link: !Executable
+
+profiles:
+  release:
+    optimization_level: O
+  development:
+    optimization_level: None
+
+registries:
+  workspace: !FileSystem ../
+
+dependencies:
+  - name: {{name}}
+    registry_name: workspace
+"###;
+
+const TESTS_MAIN_C_CONTENT: &str = r###"
+#include <stdio.h>
+#include "{{name}}/{{name}}.h"
+
+static int failures = 0;
+
+#define CHECK(condition) \
+    if (!(condition)) { fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #condition); failures++; }
+
+int main() {
+    CHECK({{identifier}}_answer() == 42);
+
+    return failures == 0 ? 0 : 1;
+}
+"###;
+
+pub const BUILTIN_TEMPLATES: [&str; 4] = ["executable", "static-library", "dynamic-library", "library-with-tests"];
+
+/** A value asked for when the template is instantiated, available as {{name}} in its files */
+#[derive(Deserialize, Debug, Clone)]
+pub struct Prompt {
+    pub name: String,
+    pub message: String,
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Manifest {
+    #[serde(default)]
+    prompts: Vec<Prompt>,
+}
+
+/**
+    Files of a new project, with {{placeholder}} in their contents and paths.
+    Built-in placeholders are name, identifier, guard, version, author and license.
+ */
+pub struct Template {
+    files: Vec<(String, Vec<u8>)>,
+    prompts: Vec<Prompt>,
+}
+
+impl Template {
+    /**
+        @param: source - a built-in template name, a template in the user templates directory,
+        a path to a template directory, or a git URL (optionally ending with #<branch>)
+     */
+    pub fn load(source: &str) -> Result<Self> {
+        if let Some(template) = Self::builtin(source) {
+            return Ok(template);
+        }
+
+        if is_git_url(source) {
+            return Self::clone_git(source);
+        }
+
+        if let Some(user_template) = user_templates_directory().map(|directory| directory.join(source))
+            && user_template.is_dir()
+        {
+            return Self::read(&user_template);
+        }
+
+        let path = Path::new(source);
+        if path.is_dir() {
+            return Self::read(path);
+        }
+
+        anyhow::bail!(
+            "Template '{}' is not a built-in template ({}), a user template or a directory",
+            source,
+            BUILTIN_TEMPLATES.join(", ")
+        )
+    }
+
+    fn builtin(name: &str) -> Option<Self> {
+        let project = |link: LinkStrategy| {
+            let build_yaml = BUILD_YAML_CONTENT.replace("{{link}}", &link.to_yaml_tag());
+
+            match link {
+                LinkStrategy::Executable => vec![
+                    ("build.yaml".to_string(), build_yaml),
+                    ("src/main.c".to_string(), MAIN_C_CONTENT.to_string()),
+                ],
+                LinkStrategy::StaticLibrary | LinkStrategy::DynamicLibrary => vec![
+                    ("build.yaml".to_string(), build_yaml),
+                    ("src/{{name}}.h".to_string(), LIBRARY_H_CONTENT.to_string()),
+                    ("src/{{name}}.c".to_string(), LIBRARY_C_CONTENT.to_string()),
+                ],
+            }
+        };
+
+        let files = match name {
+            "executable" => project(LinkStrategy::Executable),
+            "static-library" => project(LinkStrategy::StaticLibrary),
+            "dynamic-library" => project(LinkStrategy::DynamicLibrary),
+            /* a workspace of the library and an executable that checks it, run with `corge-rs run -p <name>-tests` */
+            "library-with-tests" => {
+                let mut files = vec![
+                    ("build.yaml".to_string(), WORKSPACE_YAML_CONTENT.to_string()),
+                    ("tests/build.yaml".to_string(), TESTS_YAML_CONTENT.to_string()),
+                    ("tests/src/main.c".to_string(), TESTS_MAIN_C_CONTENT.to_string()),
+                ];
+
+                files.extend(
+                    project(LinkStrategy::StaticLibrary)
+                        .into_iter()
+                        .map(|(path, content)| (format!("{{{{name}}}}/{}", path), content))
+                );
+
+                files
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            files: files
+                .into_iter()
+                .map(|(path, content)| (path, content.trim_start().as_bytes().to_vec()))
+                .collect(),
+            prompts: vec![],
+        })
+    }
+
+    fn read(template_path: &Path) -> Result<Self> {
+        let manifest_path = template_path.join(MANIFEST_NAME);
+        let manifest: Manifest = if manifest_path.exists() {
+            let manifest_str = fs::read_to_string(&manifest_path)
+                .with_context(|| format!("Failed to read template manifest {:?}", &manifest_path))?;
+
+            serde_yaml::from_str(&manifest_str)
+                .with_context(|| format!("Failed to parse template manifest {:?}", &manifest_path))?
+        } else {
+            Manifest::default()
+        };
+
+        let mut files = vec![];
+        read_files(template_path, template_path, &mut files)?;
+
+        Ok(Self {
+            files,
+            prompts: manifest.prompts,
+        })
+    }
+
+    fn clone_git(source: &str) -> Result<Self> {
+        let (url, branch) = match source.rsplit_once('#') {
+            Some((url, branch)) => (url, Some(branch)),
+            None => (source, None),
+        };
+
+        let clone_path = std::env::temp_dir().join(format!("corge-template-{}", std::process::id()));
+        if clone_path.exists() {
+            fs::remove_dir_all(&clone_path)
+                .with_context(|| format!("Failed to remove directory {:?}", &clone_path))?;
+        }
+
+        log::info!("Cloning template {}", url);
+
+        let mut command = Command::new("git");
+        command.arg("clone").arg("--depth").arg("1");
+        if let Some(branch) = branch {
+            command.arg("--branch").arg(branch);
+        }
+
+        let template = command
+            .arg(url)
+            .arg(&clone_path)
+            .execute(true)
+            .with_context(|| format!("Failed to clone template {}", url))
+            .and_then(|_| Self::read(&clone_path));
+
+        let _ = fs::remove_dir_all(&clone_path);
+
+        template
+    }
+
+    /**
+        Asks for the values of the template prompts that --set didn't give, on a terminal;
+        otherwise their defaults are used.
+        @param: defaults - the built-in values, which prompts of the same name replace
+        @param: set - the values given on the command line
+     */
+    pub fn values(&self, defaults: BTreeMap<String, String>, set: BTreeMap<String, String>) -> Result<BTreeMap<String, String>> {
+        let interactive = std::io::stdin().is_terminal();
+        let mut values = defaults.clone();
+        values.extend(set.clone());
+
+        for prompt in &self.prompts {
+            if set.contains_key(&prompt.name) {
+                continue;
+            }
+
+            let default = prompt.default.clone().or_else(|| defaults.get(&prompt.name).cloned());
+
+            let answer = if interactive {
+                ask(prompt, default.as_deref())?
+            } else {
+                None
+            };
+
+            let value = answer.or(default).ok_or_else(|| {
+                anyhow::anyhow!("Template value '{}' is required, pass it with --set {}=VALUE", prompt.name, prompt.name)
+            })?;
+
+            values.insert(prompt.name.clone(), value);
+        }
+
+        Ok(values)
+    }
+
+    /** Writes the files of the template with the placeholders replaced by the values */
+    pub fn render(&self, values: &BTreeMap<String, String>, project_path: &Path) -> Result<()> {
+        for (relative_path, content) in &self.files {
+            let file_path = project_path.join(substitute(relative_path, values));
+
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory {:?}", parent))?;
+            }
+
+            /* binary files are copied as they are */
+            let content = match std::str::from_utf8(content) {
+                Ok(text) => substitute(text, values).into_bytes(),
+                Err(_) => content.clone(),
+            };
+
+            fs::write(&file_path, content)
+                .with_context(|| format!("Failed to create file {:?}", &file_path))?;
+        }
+
+        Ok(())
+    }
+}
+
+/** The values every template can use, derived from the project name */
+pub fn builtin_values(project_name: &str) -> BTreeMap<String, String> {
+    let identifier: String = project_name
+        .chars()
+        .map(|char| if char.is_ascii_alphanumeric() { char.to_ascii_lowercase() } else { '_' })
+        .collect();
+
+    /* "2048-game" -> "_2048_game", C identifiers can't start with a digit */
+    let identifier = if identifier.starts_with(|char: char| char.is_ascii_digit()) {
+        format!("_{}", identifier)
+    } else {
+        identifier
+    };
+
+    let author = Command::new("git")
+        .args(["config", "user.name"])
+        .execute(false)
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default();
+
+    BTreeMap::from([
+        ("name".to_string(), project_name.to_string()),
+        ("guard".to_string(), identifier.to_ascii_uppercase()),
+        ("identifier".to_string(), identifier),
+        ("version".to_string(), "1.0.0".to_string()),
+        ("author".to_string(), author),
+        ("license".to_string(), "MIT".to_string()),
+    ])
+}
+
+/* Unknown placeholders are left as they are, they may belong to the project itself */
+fn substitute(text: &str, values: &BTreeMap<String, String>) -> String {
+    values
+        .iter()
+        .fold(text.to_string(), |text, (name, value)| text.replace(&format!("{{{{{}}}}}", name), value))
+}
+
+fn ask(prompt: &Prompt, default: Option<&str>) -> Result<Option<String>> {
+    match default {
+        Some(default) => eprint!("{} [{}]: ", prompt.message, default),
+        None => eprint!("{}: ", prompt.message),
+    }
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .context("Failed to read answer")?;
+
+    let answer = answer.trim();
+    Ok((!answer.is_empty()).then(|| answer.to_string()))
+}
+
+fn read_files(template_path: &Path, directory: &Path, files: &mut Vec<(String, Vec<u8>)>) -> Result<()> {
+    for entry in fs::read_dir(directory).with_context(|| format!("Failed to read directory {:?}", directory))? {
+        let entry = entry?;
+        let path = entry.path();
+        let relative_path = path.strip_prefix(template_path)?;
+
+        if relative_path == Path::new(".git") || relative_path == Path::new(MANIFEST_NAME) {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            read_files(template_path, &path, files)?;
+        } else {
+            let content = fs::read(&path)
+                .with_context(|| format!("Failed to read template file {:?}", &path))?;
+            let relative_path = relative_path
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            files.push((relative_path, content));
+        }
+    }
+
+    Ok(())
+}
+
+fn is_git_url(source: &str) -> bool {
+    ["http://", "https://", "ssh://", "git://", "git@", "file://"]
+        .iter()
+        .any(|prefix| source.starts_with(prefix))
+        || source.split('#').next().is_some_and(|url| url.ends_with(".git"))
+}
+
+/* CORGE_TEMPLATES, or corge/templates in the user configuration directory */
+fn user_templates_directory() -> Option<PathBuf> {
+    if let Some(directory) = std::env::var_os("CORGE_TEMPLATES") {
+        return Some(PathBuf::from(directory));
+    }
+
+    let config_directory = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_directory.join("corge").join("templates"))
+}
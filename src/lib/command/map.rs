@@ -0,0 +1,101 @@
+use crate::cli::MapArgs;
+use crate::error::{ErrorKind, KindContext};
+use crate::tool::configuration_parser::ConfigurationParser;
+use crate::tool::map_parser::{self, LinkMap};
+use anyhow::{Context, Result};
+use std::fs;
+
+/* "main.c.<sha256>.o" in the object cache */
+const OBJECT_HASH_LENGTH: usize = 64;
+
+/**
+    Prints the sections of the linked project and the size every object file contributes,
+    from the map file the last link wrote.
+ */
+pub fn map(map_args: MapArgs) -> Result<()> {
+    let map_file = match &map_args.map_file {
+        Some(map_file) => map_file.clone(),
+        None => {
+            let config = ConfigurationParser::new(map_args.path.clone())
+                .parse()
+                .kind_context(ErrorKind::Configuration, "Corge project is not detected")?;
+
+            let map_file = config.map_file
+                .ok_or_else(|| anyhow::anyhow!("build.yaml has no map_file, set it or pass --map-file"))
+                .kind_context(ErrorKind::Configuration, "Failed to find the map file")?;
+
+            map_args.path.join(map_file)
+        }
+    };
+
+    let content = fs::read_to_string(&map_file)
+        .with_context(|| format!("Failed to read map file {:?}, build the project first", &map_file))?;
+
+    let link_map = map_parser::parse(&content)
+        .with_context(|| format!("Failed to parse map file {:?}", &map_file))?;
+
+    print_sections(&link_map);
+    println!();
+    print_objects(&link_map, map_args.section.as_deref(), map_args.top);
+
+    Ok(())
+}
+
+fn print_sections(link_map: &LinkMap) {
+    let sections: Vec<_> = link_map.allocated_sections().filter(|section| section.size > 0).collect();
+    let width = sections.iter().map(|section| section.name.len()).max().unwrap_or_default().max("Section".len());
+
+    println!("{:<width$}  {:>18}  {:>10}", "Section", "Address", "Size");
+
+    for section in &sections {
+        println!("{:<width$}  {:>#18x}  {:>10}", section.name, section.address, section.size);
+    }
+
+    let total: u64 = sections.iter().map(|section| section.size).sum();
+    println!("{:<width$}  {:>18}  {:>10}", "Total", "", total);
+}
+
+fn print_objects(link_map: &LinkMap, section: Option<&str>, top: Option<usize>) {
+    let sizes = link_map.object_sizes(section);
+    let total: u64 = sizes.iter().map(|(_, size)| size).sum();
+
+    let objects: Vec<(String, u64)> = sizes
+        .into_iter()
+        .take(top.unwrap_or(usize::MAX))
+        .map(|(object, size)| (object_name(&object), size))
+        .collect();
+
+    let title = match section {
+        Some(section) => format!("Object ({})", section),
+        None => "Object".to_string(),
+    };
+    let width = objects.iter().map(|(object, _)| object.len()).max().unwrap_or_default().max(title.len());
+
+    println!("{:<width$}  {:>10}  {:>6}", title, "Size", "Share");
+
+    for (object, size) in &objects {
+        let share = if total > 0 { *size as f64 * 100.0 / total as f64 } else { 0.0 };
+
+        println!("{:<width$}  {:>10}  {:>5.1}%", object, size, share);
+    }
+
+    println!("{:<width$}  {:>10}", "Total", total);
+}
+
+/* "./target/development/default/cache/project/main.c.<sha256>.o" -> "project/main.c" */
+fn object_name(object: &str) -> String {
+    let Some((_, cached)) = object.split_once("/cache/") else {
+        return object.to_string();
+    };
+
+    let Some(source) = cached.strip_suffix(".o") else {
+        return object.to_string();
+    };
+
+    match source.rsplit_once('.') {
+        Some((source, hash)) if hash.len() == OBJECT_HASH_LENGTH && hash.chars().all(|char| char.is_ascii_hexdigit()) => {
+            source.to_string()
+        }
+        _ => object.to_string(),
+    }
+}
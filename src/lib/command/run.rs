@@ -1,5 +1,45 @@
 use crate::cli::BuildArgs;
+use crate::command::build::build;
+use crate::config::LinkStrategy;
+use crate::error::{ErrorKind, KindContext};
+use crate::std_command_ext;
+use anyhow::Result;
+use std::process::Command;
 
-pub fn run(build_args: BuildArgs) {
-    todo!("Running project is not implemented yet")
+/**
+    Builds the project and runs its executable.
+    In a workspace, exactly one of the built members has to be an executable; select it with -p.
+ */
+pub fn run(build_args: BuildArgs) -> Result<()> {
+    let build_outputs = build(build_args)?;
+
+    let mut executables = build_outputs
+        .into_iter()
+        .filter(|build_output| build_output.link_strategy == LinkStrategy::Executable);
+
+    let executable = match (executables.next(), executables.next()) {
+        (Some(executable), None) => executable,
+        (None, _) => {
+            return Err(anyhow::anyhow!("Only executables can be run"))
+                .kind_context(ErrorKind::Configuration, "The project has no executable to run");
+        }
+        (Some(_), Some(_)) => {
+            return Err(anyhow::anyhow!("Select the one to run with -p"))
+                .kind_context(ErrorKind::Configuration, "The workspace has several executables");
+        }
+    };
+
+    log::info!("Running '{}' {:?}", &executable.name, &executable.path);
+
+    let mut child = Command::new(&executable.path)
+        .spawn()
+        .with_kind_context(ErrorKind::Run, || format!("Failed to start {:?}", &executable.path))?;
+    let status = std_command_ext::wait(&mut child)?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("'{}' {}", &executable.name, status))
+            .kind_context(ErrorKind::Run, "Running the project failed");
+    }
+
+    Ok(())
 }
@@ -0,0 +1,250 @@
+use crate::cli::{WatchArgs, WatchCommand};
+use crate::command::build::build;
+use crate::command::run::run;
+use crate::error::ErrorKind;
+use crate::std_command_ext::{interrupted, set_cancelled};
+use crate::tool::lockfile::{LockedSource, Lockfile, LOCKFILE_NAME};
+use anyhow::{Context, Result};
+use notify::{Config, Event, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/* how often the watch loop checks for Ctrl-C and a finished build */
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/* how often the polling watcher scans the watched directories */
+const SCAN_INTERVAL: Duration = Duration::from_millis(500);
+
+/* what the build writes into a project, relative to its root */
+const GENERATED_PATHS: [&str; 6] = ["target", "dependency", "compilation_database", ".git", LOCKFILE_NAME, "compile_commands.json"];
+
+type WatchEvents = Receiver<notify::Result<Event>>;
+
+/**
+    Builds (or runs) the project, then again every time its sources, headers, build.yaml or path dependencies change.
+    A change during a build cancels it, so the next build starts from the latest files.
+    Object files are cached by content, so a rebuild only compiles what changed.
+ */
+pub fn watch(watch_args: WatchArgs) -> Result<()> {
+    let root_path = fs::canonicalize(&watch_args.build.path)
+        .with_context(|| format!("Failed to find project directory {:?}", &watch_args.build.path))?;
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = create_watcher(watch_args.poll, sender)?;
+    watcher
+        .watch(&root_path, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {:?}", &root_path))?;
+
+    /* directories outside the project, with the name of the file system dependency copied from them */
+    let mut dependency_paths: BTreeMap<PathBuf, Option<String>> = BTreeMap::new();
+
+    loop {
+        set_cancelled(false);
+        let mut execution = Some(execute(&watch_args));
+
+        let changed_path = loop {
+            if interrupted() {
+                if let Some(execution) = execution.take() {
+                    let _ = execution.join();
+                }
+                log::info!("Stopped watching {:?}", &root_path);
+
+                return Ok(());
+            }
+
+            if execution.as_ref().is_some_and(|execution| execution.is_finished()) {
+                if let Some(execution) = execution.take() {
+                    report(execution);
+                }
+
+                watch_dependencies(watcher.as_mut(), &root_path, &mut dependency_paths);
+                log::info!("Watching for changes in {:?}", &root_path);
+            }
+
+            if let Some(path) = next_change(&events, POLL_INTERVAL, &root_path, &dependency_paths) {
+                break path;
+            }
+        };
+
+        log::info!("Change detected in {:?}", changed_path);
+        let mut changed_paths = vec![changed_path];
+
+        /* an editor saving several files, or a formatter, changes them in a burst */
+        let mut last_change = Instant::now();
+        while last_change.elapsed() < watch_args.debounce() && !interrupted() {
+            if let Some(path) = next_change(&events, POLL_INTERVAL, &root_path, &dependency_paths) {
+                changed_paths.push(path);
+                last_change = Instant::now();
+            }
+        }
+
+        if let Some(execution) = execution.take() {
+            set_cancelled(true);
+            let _ = execution.join();
+
+            log::info!("Cancelled the running {}", command_name(watch_args.exec));
+        }
+
+        refetch_dependencies(&root_path, &changed_paths, &dependency_paths)?;
+    }
+}
+
+fn create_watcher(poll: bool, sender: mpsc::Sender<notify::Result<Event>>) -> Result<Box<dyn Watcher>> {
+    let poll_watcher = |sender| -> Result<Box<dyn Watcher>> {
+        let watcher = PollWatcher::new(sender, Config::default().with_poll_interval(SCAN_INTERVAL))
+            .context("Failed to create polling file watcher")?;
+
+        Ok(Box::new(watcher))
+    };
+
+    if poll {
+        return poll_watcher(sender);
+    }
+
+    match RecommendedWatcher::new(sender.clone(), Config::default()) {
+        Ok(watcher) => Ok(Box::new(watcher)),
+        Err(error) => {
+            log::warn!("File system notifications are unavailable ({}), polling for changes instead", error);
+            poll_watcher(sender)
+        }
+    }
+}
+
+fn execute(watch_args: &WatchArgs) -> JoinHandle<Result<()>> {
+    let build_args = watch_args.build.clone();
+    let exec = watch_args.exec;
+
+    thread::spawn(move || match exec {
+        WatchCommand::Build => build(build_args).map(|_| ()),
+        WatchCommand::Run => run(build_args),
+    })
+}
+
+fn command_name(exec: WatchCommand) -> &'static str {
+    match exec {
+        WatchCommand::Build => "build",
+        WatchCommand::Run => "program",
+    }
+}
+
+/* a failed build doesn't stop watching, the next change may fix it */
+fn report(execution: JoinHandle<Result<()>>) {
+    match execution.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) if ErrorKind::of(&error) == Some(ErrorKind::Interrupted) => {}
+        Ok(Err(error)) => log::error!("{:#}", error),
+        Err(_) => log::error!("The build panicked"),
+    }
+}
+
+/* Waits up to the timeout for a change to a file that isn't written by the build itself */
+fn next_change(
+    events: &WatchEvents,
+    timeout: Duration,
+    root_path: &Path,
+    dependency_paths: &BTreeMap<PathBuf, Option<String>>,
+) -> Option<PathBuf> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let event = match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(Ok(event)) => event,
+            Ok(Err(error)) => {
+                log::warn!("Failed to watch for changes: {}", error);
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => return None,
+        };
+
+        /* reading a file or opening it is not a change */
+        if matches!(event.kind, EventKind::Access(_)) {
+            continue;
+        }
+
+        let roots: Vec<&Path> = std::iter::once(root_path)
+            .chain(dependency_paths.keys().map(PathBuf::as_path))
+            .collect();
+
+        if let Some(path) = event.paths.into_iter().find(|path| is_source_change(path, &roots)) {
+            return Some(path);
+        }
+    }
+}
+
+fn is_source_change(path: &Path, roots: &[&Path]) -> bool {
+    /* editor swap and backup files */
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    if file_name.starts_with('.') || file_name.ends_with('~') {
+        return false;
+    }
+
+    let Some(relative_path) = roots.iter().find_map(|root| path.strip_prefix(root).ok()) else {
+        return false;
+    };
+
+    match relative_path.components().next() {
+        Some(first) => !GENERATED_PATHS.iter().any(|generated| first.as_os_str() == *generated),
+        None => true,
+    }
+}
+
+/**
+    Watches the file system and workspace dependencies recorded by the last build that live outside the project.
+    Git dependencies are pinned to a commit and never change under the project.
+ */
+fn watch_dependencies(watcher: &mut dyn Watcher, root_path: &Path, dependency_paths: &mut BTreeMap<PathBuf, Option<String>>) {
+    let Ok(lockfile) = Lockfile::read(root_path) else {
+        return;
+    };
+
+    for locked in lockfile.dependencies {
+        let (path, copied_name) = match locked.source {
+            LockedSource::FileSystem(path) => (path, Some(locked.name)),
+            LockedSource::Workspace(path) => (path, None),
+            LockedSource::Git { .. } => continue,
+        };
+
+        let Ok(path) = fs::canonicalize(&path) else {
+            continue;
+        };
+
+        if path.starts_with(root_path) || dependency_paths.contains_key(&path) {
+            continue;
+        }
+
+        match watcher.watch(&path, RecursiveMode::Recursive) {
+            Ok(()) => {
+                log::debug!("Watching dependency {:?}", &path);
+                dependency_paths.insert(path, copied_name);
+            }
+            Err(error) => log::warn!("Failed to watch dependency {:?}: {}", &path, error),
+        }
+    }
+}
+
+/* File system dependencies are copied into the project once, so a changed one is copied again */
+fn refetch_dependencies(root_path: &Path, changed_paths: &[PathBuf], dependency_paths: &BTreeMap<PathBuf, Option<String>>) -> Result<()> {
+    for (dependency_path, copied_name) in dependency_paths {
+        let Some(name) = copied_name else {
+            continue;
+        };
+
+        if !changed_paths.iter().any(|path| path.starts_with(dependency_path)) {
+            continue;
+        }
+
+        let source_path = root_path.join("dependency").join("source").join(name);
+        if source_path.exists() {
+            log::info!("Dependency '{}' changed", name);
+
+            fs::remove_dir_all(&source_path)
+                .with_context(|| format!("Failed to remove the copy of dependency '{}'", name))?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,10 +1,13 @@
 use anyhow::Result;
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
 use crate::cli::BuildToolchain;
+use crate::tool::cfg::{Cfg, CfgExpression};
+use anyhow::Context;
 
-#[derive(Serialize, Deserialize, ValueEnum, Debug, Default, Clone)]
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Default, Clone, PartialEq)]
 pub enum LinkStrategy {
     #[default]
     Executable,
@@ -22,10 +25,16 @@ impl LinkStrategy {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Project {
     pub name: String,
     pub version: String,
+    /// What the project produces. `build --link` overrides it.
+    #[serde(default)]
+    pub link: Option<LinkStrategy>,
+    /// Toolchain used when none is selected on the command line.
+    #[serde(default)]
+    pub toolchain: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -42,27 +51,19 @@ pub enum OptimizationLevel {
     Oz,
 }
 
-impl OptimizationLevel {
-    pub fn as_gcc_flag(&self) -> Option<&str> {
-        match self {
-            OptimizationLevel::None => None,
-            OptimizationLevel::O => Some("-O"),
-            OptimizationLevel::O1 => Some("-O1"),
-            OptimizationLevel::O2 => Some("-O2"),
-            OptimizationLevel::O3 => Some("-O3"),
-            OptimizationLevel::O0 => Some("-O0"),
-            OptimizationLevel::Os => Some("-Os"),
-            OptimizationLevel::Ofast => Some("-Ofast"),
-            OptimizationLevel::Og => Some("-Og"),
-            OptimizationLevel::Oz => Some("-Oz"),
-        }
-    }
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Profile {
     /// https://gcc.gnu.org/onlinedocs/gcc/Optimize-Options.html
     pub optimization_level: OptimizationLevel,
+    /// Emits debug information.
+    #[serde(default)]
+    pub debug: bool,
+    /// Preprocessor definitions in the form `NAME` or `NAME=VALUE`.
+    #[serde(default)]
+    pub defines: Vec<String>,
+    /// Replaces the post-link steps of the toolchain.
+    #[serde(default)]
+    pub post_link: Option<PostLink>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -71,7 +72,7 @@ pub struct Profiles {
     pub development: Option<Profile>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Registry {
     Git {
         url: String,
@@ -80,33 +81,284 @@ pub enum Registry {
     FileSystem(String),
 }
 
-
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Dependency {
     pub name: String,
     pub registry_name: String,
+    /// Only fetched and built when a feature enables it.
+    #[serde(default)]
+    pub optional: bool,
+    /// Features of the dependency to enable.
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Whether the `default` feature of the dependency is enabled.
+    #[serde(default = "default_features")]
+    pub default_features: bool,
+}
+
+fn default_features() -> bool {
+    true
+}
+
+/// An optional capability of the project, enabled on the command line or by a project depending on it.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Feature {
+    #[serde(default)]
+    pub defines: Vec<String>,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// Optional dependencies the feature enables.
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    /// Other features the feature enables: `name` of the project or `dependency/name` of a dependency.
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+/// Command line dialect of a compiler. Detected from the compiler unless the toolchain declares it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompilerFamily {
+    Gcc,
+    Clang,
+    /// clang-cl and other MSVC-style drivers.
+    ClangCl,
+    Tcc,
+    Icx,
+    Sdcc,
+    /// Microchip XC16, a GCC derivative without some GCC options.
+    Xc16,
+    Unknown,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Toolchain {
+    #[serde(default)]
+    pub family: Option<CompilerFamily>,
     pub compiler: String,
     pub archiver: String,
     pub compiler_flags: Vec<String>,
     pub linker_flags: Vec<String>,
+    /// Passed as `--sysroot` when compiling and linking. Relative paths are resolved against the project.
+    #[serde(default)]
+    pub sysroot: Option<String>,
+    #[serde(default)]
+    pub post_link: Option<PostLink>,
 }
 impl Default for Toolchain {
     fn default() -> Self {
         Self {
+            family: None,
             compiler: "gcc".to_string(),
             archiver: "ar".to_string(),
             compiler_flags: vec![],
             linker_flags: vec![],
+            sysroot: None,
+            post_link: None,
+        }
+    }
+}
+impl Toolchain {
+    /** Cross toolchain named after the target triple, e.g. `aarch64-linux-gnu-gcc` */
+    pub fn for_triple(triple: &str) -> Self {
+        Self {
+            compiler: format!("{}-gcc", triple),
+            archiver: format!("{}-ar", triple),
+            ..Self::default()
+        }
+    }
+
+    /**
+        A binutils program of the toolchain, named after its archiver:
+        `arm-none-eabi-ar` gives `arm-none-eabi-objcopy`, `llvm-ar` gives `llvm-objcopy`.
+     */
+    pub fn binutil(&self, program: &str) -> String {
+        let Some(prefix) = self.archiver.strip_suffix("ar") else {
+            return program.to_string();
+        };
+
+        /* gcc-ar is a wrapper for LTO, there is no gcc-objcopy */
+        let prefix = prefix.strip_suffix("gcc-").unwrap_or(prefix);
+
+        format!("{}{}", prefix, program)
+    }
+}
+
+/// Formats objcopy converts the linked artifact to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ObjcopyFormat {
+    /// Intel HEX, written to <artifact>.hex.
+    Ihex,
+    /// Raw memory image, written to <artifact>.bin.
+    Binary,
+    /// Motorola S-record, written to <artifact>.srec.
+    Srec,
+}
+
+impl ObjcopyFormat {
+    /** The name objcopy -O takes */
+    pub fn bfd_name(&self) -> &'static str {
+        match self {
+            ObjcopyFormat::Ihex => "ihex",
+            ObjcopyFormat::Binary => "binary",
+            ObjcopyFormat::Srec => "srec",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ObjcopyFormat::Ihex => "hex",
+            ObjcopyFormat::Binary => "bin",
+            ObjcopyFormat::Srec => "srec",
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Strip {
+    /// Keeps the debug information in <artifact>.debug, linked from the stripped artifact.
+    #[serde(default)]
+    pub split_debug: bool,
+}
+
+/// Memory budgets in bytes. Flash holds text and data, RAM holds data and bss.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SizeBudget {
+    #[serde(default)]
+    pub flash: Option<u64>,
+    #[serde(default)]
+    pub ram: Option<u64>,
+}
+
+/// Steps run on a linked executable or dynamic library, in the order size, objcopy, strip.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct PostLink {
+    #[serde(default)]
+    pub objcopy: Vec<ObjcopyFormat>,
+    #[serde(default)]
+    pub strip: Option<Strip>,
+    /// Reports the section totals, and fails the build when they exceed a budget.
+    #[serde(default)]
+    pub size: Option<SizeBudget>,
+}
+
+/// Where an effective toolchain value comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueOrigin {
+    BuiltIn,
+    TargetTriple,
+    BuildYaml,
+    CommandLine,
+    /// Name of the environment variable.
+    Environment(String),
+}
+
+impl Display for ValueOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueOrigin::BuiltIn => write!(f, "built-in default"),
+            ValueOrigin::TargetTriple => write!(f, "derived from target triple"),
+            ValueOrigin::BuildYaml => write!(f, "build.yaml"),
+            ValueOrigin::CommandLine => write!(f, "command line"),
+            ValueOrigin::Environment(variable) => write!(f, "environment {}", variable),
+        }
+    }
+}
+
+/// Build settings for a target triple selected with `build --target`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Target {
+    /// Name of a toolchain from the toolchains section. Derived from the triple if omitted.
+    #[serde(default)]
+    pub toolchain: Option<String>,
+    #[serde(default)]
+    pub sysroot: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Workspace {
+    /// Glob patterns, relative to the workspace root, matching member project directories.
+    pub members: Vec<String>,
+}
+
+/// Settings added to the project when its `cfg` expression matches the target and the profile.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Conditional {
+    /// e.g. `all(os = "linux", arch = "x86_64")`, see `CfgExpression`.
+    pub cfg: String,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub includes: Vec<String>,
+    #[serde(default)]
+    pub defines: Vec<String>,
+    #[serde(default)]
+    pub compiler_flags: Vec<String>,
+    #[serde(default)]
+    pub linker_flags: Vec<String>,
+    /// Replaces the linker script of the project.
+    #[serde(default)]
+    pub linker_script: Option<String>,
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+}
+
+/// A step that generates sources before compiling, e.g. with bison, `xxd -i` or a script.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Generator {
+    /// Names the step in the build output; its first output if omitted.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Program and arguments, run in the project directory. `{out_dir}` is replaced with the directory
+    /// the outputs go to, which is also passed as OUT_DIR.
+    pub command: Vec<String>,
+    /// Files or glob patterns the step reads, relative to the project. The step reruns when they change.
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    /// Files the step writes, relative to the output directory.
+    pub outputs: Vec<String>,
+}
+
+impl Generator {
+    pub fn name(&self) -> String {
+        self.name
+            .clone()
+            .or_else(|| self.outputs.first().cloned())
+            .unwrap_or_else(|| self.command.join(" "))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    /// Raw deflate stream (RFC 1951).
+    Deflate,
+    /// Deflate with the zlib header and checksum (RFC 1950).
+    Zlib,
+}
+
+/// Files embedded into the program as C arrays, declared in the generated <name>.h.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Resource {
+    /// Name of the generated .c and .h files.
+    pub name: String,
+    /// Files or glob patterns, relative to the project. Each file becomes an array named after its path,
+    /// e.g. `web/index.html` becomes `web_index_html` and `web_index_html_len`.
+    pub files: Vec<String>,
+    /// Alignment of the arrays in bytes.
+    #[serde(default)]
+    pub align: Option<u32>,
+    /// Appends a 0 byte, which the length doesn't count, so text can be used as a C string.
+    #[serde(default)]
+    pub nul_terminate: bool,
+    #[serde(default)]
+    pub compression: Option<Compression>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Config {
+    /// A workspace root may omit the project section.
+    #[serde(default)]
     pub project: Project,
     #[serde(default)]
     pub profiles: Profiles,
@@ -115,40 +367,261 @@ pub struct Config {
     #[serde(default)]
     pub dependencies: Vec<Dependency>,
     #[serde(default)]
-    pub toolchains: HashMap<String, Toolchain>
+    pub toolchains: HashMap<String, Toolchain>,
+    #[serde(default)]
+    pub targets: HashMap<String, Target>,
+    #[serde(default)]
+    pub workspace: Option<Workspace>,
+    /// Directories searched for C sources, or glob patterns like `platform/linux/*.c`, relative to the project.
+    /// `src` if omitted.
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// Glob patterns of files or directories left out of the sources and exported headers, e.g. `src/experimental`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Include directories of the project's own headers, relative to the project.
+    #[serde(default)]
+    pub includes: Vec<String>,
+    /// Preprocessor definitions of the project's own sources, in the form `NAME` or `NAME=VALUE`.
+    #[serde(default)]
+    pub defines: Vec<String>,
+    /// Compiler flags of the project's own sources, after the toolchain's.
+    #[serde(default)]
+    pub compiler_flags: Vec<String>,
+    /// Linker flags added to the link of the project and of every project that links it.
+    #[serde(default)]
+    pub linker_flags: Vec<String>,
+    /// Linker script of the executable or dynamic library, relative to the project.
+    #[serde(default)]
+    pub linker_script: Option<String>,
+    /// Map file the linker writes, relative to the project, e.g. `target/firmware.map`.
+    #[serde(default)]
+    pub map_file: Option<String>,
+    #[serde(default)]
+    pub conditionals: Vec<Conditional>,
+    /// Steps run before compiling, in order. Their C outputs are compiled with the project's sources.
+    #[serde(default)]
+    pub generators: Vec<Generator>,
+    #[serde(default)]
+    pub resources: Vec<Resource>,
+    /// The `default` feature is enabled unless the build or the depending project turns default features off.
+    #[serde(default)]
+    pub features: BTreeMap<String, Feature>,
 }
 
 impl Config {
-    /** Returns the toolchain name on an error case */
-    pub fn toolchain(&self, toolchain: Option<BuildToolchain>) -> Result<(String, Toolchain)> {
-        // let toolchain = toolchain.map(|toolchain| toolchain.0);
+    pub fn source_directories(&self) -> Vec<String> {
+        if self.sources.is_empty() {
+            vec!["src".to_string()]
+        } else {
+            self.sources.clone()
+        }
+    }
+
+    /** The declared dependencies and those of every conditional, whether it matches or not */
+    pub fn all_dependencies(&self) -> impl Iterator<Item = &Dependency> {
+        self.dependencies
+            .iter()
+            .chain(self.conditionals.iter().flat_map(|conditional| &conditional.dependencies))
+    }
 
-        match toolchain {
-            None | Some(BuildToolchain::Default) => {
+    /**
+        Merges the conditionals whose cfg expression matches into the project settings.
+        The conditionals are consumed, so applying them twice doesn't add their settings twice.
+     */
+    pub fn apply_conditionals(&mut self, cfg: &Cfg) -> Result<()> {
+        for conditional in std::mem::take(&mut self.conditionals) {
+            let expression = CfgExpression::parse(&conditional.cfg)
+                .with_context(|| format!("Invalid cfg expression '{}'", conditional.cfg))?;
+
+            if !expression.matches(cfg) {
+                continue;
+            }
+
+            if !conditional.sources.is_empty() {
+                self.sources = self.source_directories();
+                self.sources.extend(conditional.sources);
+            }
+
+            self.exclude.extend(conditional.exclude);
+            self.includes.extend(conditional.includes);
+            self.defines.extend(conditional.defines);
+            self.compiler_flags.extend(conditional.compiler_flags);
+            self.linker_flags.extend(conditional.linker_flags);
+            self.linker_script = conditional.linker_script.or(self.linker_script.take());
+            self.dependencies.extend(conditional.dependencies);
+        }
+
+        Ok(())
+    }
+
+    /** Merges the defines and sources of the enabled features into the project settings */
+    pub fn apply_features<'a>(&mut self, features: impl IntoIterator<Item = &'a String>) {
+        for name in features {
+            let Some(feature) = self.features.get(name).cloned() else {
+                continue;
+            };
+
+            if !feature.sources.is_empty() {
+                self.sources = self.source_directories();
+                self.sources.extend(feature.sources);
+            }
+
+            self.defines.extend(feature.defines);
+        }
+    }
+
+    /** Command line selection wins over the project's declared link strategy */
+    pub fn link_strategy(&self, link: Option<LinkStrategy>) -> LinkStrategy {
+        link.or_else(|| self.project.link.clone()).unwrap_or_default()
+    }
+
+    /**
+        Resolves the toolchain in this order: the command line selection, the toolchain mapped to the target triple,
+        a toolchain derived from the target triple, the project's default toolchain and finally the default toolchain.
+
+        Returns the toolchain name on an error case
+     */
+    pub fn toolchain(&self, toolchain: Option<BuildToolchain>, target: Option<&str>) -> Result<(String, Toolchain, ValueOrigin)> {
+        let target_config = target.and_then(|triple| self.targets.get(triple));
+
+        let toolchain = toolchain
+            .or_else(|| {
+                target_config
+                    .and_then(|target_config| target_config.toolchain.clone())
+                    .map(|name| BuildToolchain::Named { name })
+            })
+            .or_else(|| {
+                if target.is_some() {
+                    return None;
+                }
+
+                self.project.toolchain
+                    .clone()
+                    .map(|name| BuildToolchain::Named { name })
+            });
+
+        let (name, mut toolchain, origin) = match (toolchain, target) {
+            (None, Some(triple)) => {
+                let name = "default".to_string();
+                let toolchain = Toolchain::for_triple(triple);
+
+                (name, toolchain, ValueOrigin::TargetTriple)
+            }
+            (None | Some(BuildToolchain::Default), _) => {
                 let name = "default".to_string();
                 let toolchain = Toolchain::default();
 
-                Ok((name, toolchain))
+                (name, toolchain, ValueOrigin::BuiltIn)
             }
-            Some(BuildToolchain::Named { name }) => {
+            (Some(BuildToolchain::Named { name }), _) => {
                 let toolchain = self.toolchains
                     .get(&name)
                     .cloned()
                     .ok_or_else(|| anyhow::anyhow!("Toolchain '{}' not found", name))?;
 
-                Ok((name, toolchain))
+                (name, toolchain, ValueOrigin::BuildYaml)
             }
-            Some(BuildToolchain::Custom { compiler, archiver, compiler_flags, linker_flags }) => {
+            (Some(BuildToolchain::Custom { compiler, archiver, compiler_flags, linker_flags }), _) => {
                 let name = "custom".to_string();
                 let toolchain = Toolchain {
+                    family: None,
                     compiler,
                     archiver,
                     compiler_flags,
                     linker_flags,
+                    sysroot: None,
+                    post_link: None,
                 };
 
-                Ok((name, toolchain))
+                (name, toolchain, ValueOrigin::CommandLine)
             }
+        };
+
+        if toolchain.sysroot.is_none() {
+            toolchain.sysroot = target_config.and_then(|target_config| target_config.sysroot.clone());
         }
+
+        Ok((name, toolchain, origin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(build_yaml: &str) -> Config {
+        serde_yaml::from_str(build_yaml).expect("valid build.yaml")
+    }
+
+    const CROSS_CONFIG: &str = r#"
+project:
+  name: app
+  version: 1.0.0
+  toolchain: native
+toolchains:
+  native:
+    compiler: cc
+    archiver: ar
+    compiler_flags: []
+    linker_flags: []
+  arm:
+    compiler: arm-none-eabi-gcc
+    archiver: arm-none-eabi-ar
+    compiler_flags: [-mthumb]
+    linker_flags: []
+targets:
+  thumbv7em-none-eabihf:
+    toolchain: arm
+    sysroot: sysroots/arm
+  aarch64-linux-gnu:
+    sysroot: /opt/aarch64
+"#;
+
+    #[test]
+    fn target_selects_the_mapped_toolchain_and_sysroot() {
+        let (name, toolchain, origin) = config(CROSS_CONFIG).toolchain(None, Some("thumbv7em-none-eabihf")).unwrap();
+
+        assert_eq!(name, "arm");
+        assert_eq!(toolchain.compiler, "arm-none-eabi-gcc");
+        assert_eq!(toolchain.sysroot.as_deref(), Some("sysroots/arm"));
+        assert_eq!(origin, ValueOrigin::BuildYaml);
+    }
+
+    #[test]
+    fn target_without_a_toolchain_derives_one_from_the_triple() {
+        let (name, toolchain, origin) = config(CROSS_CONFIG).toolchain(None, Some("aarch64-linux-gnu")).unwrap();
+
+        /* the project's default toolchain builds for the host, so it isn't used for a target */
+        assert_eq!(name, "default");
+        assert_eq!(toolchain.compiler, "aarch64-linux-gnu-gcc");
+        assert_eq!(toolchain.archiver, "aarch64-linux-gnu-ar");
+        assert_eq!(toolchain.sysroot.as_deref(), Some("/opt/aarch64"));
+        assert_eq!(origin, ValueOrigin::TargetTriple);
+    }
+
+    #[test]
+    fn command_line_toolchain_wins_over_the_target() {
+        let selection = BuildToolchain::Named { name: "native".to_string() };
+        let (name, toolchain, _) = config(CROSS_CONFIG).toolchain(Some(selection), Some("thumbv7em-none-eabihf")).unwrap();
+
+        assert_eq!(name, "native");
+        assert_eq!(toolchain.compiler, "cc");
+        assert_eq!(toolchain.sysroot.as_deref(), Some("sysroots/arm"));
+    }
+
+    #[test]
+    fn without_target_the_project_toolchain_is_used() {
+        let (name, toolchain, _) = config(CROSS_CONFIG).toolchain(None, None).unwrap();
+
+        assert_eq!(name, "native");
+        assert_eq!(toolchain.sysroot, None);
+    }
+
+    #[test]
+    fn unknown_toolchain_is_an_error() {
+        let selection = BuildToolchain::Named { name: "missing".to_string() };
+
+        assert!(config(CROSS_CONFIG).toolchain(Some(selection), None).is_err());
     }
 }
@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use std::fmt::Display;
+
+/**
+    What kind of step failed, which decides the exit code of corge-rs.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// build.yaml, workspace, toolchain or command line arguments are invalid.
+    Configuration,
+    DependencyFetch,
+    Compile,
+    Link,
+    /// The program started by `run` failed.
+    Run,
+    /// Ctrl-C was pressed.
+    Interrupted,
+}
+
+impl ErrorKind {
+    /* 1 is any other error, 2 is an invalid command line (clap) */
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::Configuration => 3,
+            ErrorKind::DependencyFetch => 4,
+            ErrorKind::Compile => 5,
+            ErrorKind::Link => 6,
+            ErrorKind::Run => 7,
+            ErrorKind::Interrupted => 130,
+        }
+    }
+
+    /** The kind of the outermost step that failed with the error, if it is known */
+    pub fn of(error: &anyhow::Error) -> Option<ErrorKind> {
+        /* an interruption stops whatever step was running */
+        if error.chain().any(|cause| cause.is::<Interrupted>()) {
+            return Some(ErrorKind::Interrupted);
+        }
+
+        error.downcast_ref::<KindError>().map(|kind_error| kind_error.kind)
+    }
+}
+
+/** A command was stopped because Ctrl-C was pressed */
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/**
+    A context message that also records the kind of the failed step.
+    It displays as the message alone, so error chains read the same.
+ */
+#[derive(Debug)]
+struct KindError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl Display for KindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+pub trait KindContext<T> {
+    fn kind_context<C: Display>(self, kind: ErrorKind, context: C) -> Result<T>;
+
+    fn with_kind_context<C: Display, F: FnOnce() -> C>(self, kind: ErrorKind, context: F) -> Result<T>;
+}
+
+impl<T, E> KindContext<T> for Result<T, E>
+where
+    Result<T, E>: Context<T, E>,
+{
+    fn kind_context<C: Display>(self, kind: ErrorKind, context: C) -> Result<T> {
+        self.with_kind_context(kind, || context)
+    }
+
+    fn with_kind_context<C: Display, F: FnOnce() -> C>(self, kind: ErrorKind, context: F) -> Result<T> {
+        self.with_context(|| KindError {
+            kind,
+            message: context().to_string(),
+        })
+    }
+}
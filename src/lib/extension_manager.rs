@@ -1,7 +1,5 @@
-use std::process::Command;
 use std::str::FromStr;
 use target_lexicon::{OperatingSystem, Triple};
-use crate::std_command_ext::ExecuteCommand;
 
 #[derive(Debug)]
 pub enum Extension {
@@ -52,21 +50,11 @@ impl Extension {
         }
     }
 
-    fn for_compiler(&self, compiler: &str) -> Option<String> {
-        log::info!("{:?}. Getting extention for compiler: {}", &self, compiler);
-        let stdout = Command::new(compiler)
-            .arg("-dumpmachine")
-            .execute(true)
-            .expect(format!("Failed to execute: {} -dumpmachine", compiler).as_str());
-
-        /* fixme */
-        let triple = stdout.strip_suffix("\n").unwrap().to_string();
-
-        self.for_triple(&triple)
-    }
-
-    pub fn file_name(&self, name: &str, compiler: &str) -> String {
-        let extension = match self.for_compiler(compiler) {
+    /**
+        @param: target_triple - the triple the compiler produces code for, see `ToolchainInfo::target`
+     */
+    pub fn file_name(&self, name: &str, target_triple: &str) -> String {
+        let extension = match self.for_triple(target_triple) {
             None => "",
             Some(extension) => &format!(".{}", extension),
         };
@@ -4,3 +4,5 @@ pub mod std_command_ext;
 pub mod tool;
 pub mod command;
 pub mod extension_manager;
+pub mod reporter;
+pub mod error;
@@ -0,0 +1,351 @@
+use crate::cli::MessageFormat;
+use crate::config::LinkStrategy;
+use crate::std_command_ext::OutputStream;
+use crate::tool::diagnostic_parser::{Diagnostic, Severity};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/**
+    Something that happened during a build, in the order it happened.
+ */
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    DependencyFetchStarted {
+        name: String,
+        registry: String,
+    },
+    DependencyFetchFinished {
+        name: String,
+        path: PathBuf,
+        /// The dependency was already fetched.
+        fresh: bool,
+    },
+    CompileStarted {
+        source: PathBuf,
+        object: PathBuf,
+        command: Vec<String>,
+    },
+    CompileFinished {
+        source: PathBuf,
+        object: PathBuf,
+        success: bool,
+    },
+    /// The object file of an unchanged source is reused.
+    CacheHit {
+        source: PathBuf,
+        object: PathBuf,
+    },
+    /// A compiler warning or error, replayed from the cache when the object file is reused.
+    Diagnostic {
+        source: PathBuf,
+        cached: bool,
+        #[serde(flatten)]
+        diagnostic: Diagnostic,
+    },
+    /// Unique diagnostics of the whole build, counted per warning option.
+    DiagnosticSummary {
+        warnings: usize,
+        errors: usize,
+        options: BTreeMap<String, usize>,
+    },
+    /// A generator step of build.yaml runs, `name` is <project>/<step>.
+    GenerateStarted {
+        name: String,
+        command: Vec<String>,
+    },
+    GenerateFinished {
+        name: String,
+        success: bool,
+    },
+    /// The outputs of a generator step whose inputs and command didn't change are reused.
+    GenerateFresh {
+        name: String,
+    },
+    LinkStarted {
+        name: String,
+        link_strategy: LinkStrategy,
+        output: PathBuf,
+        command: Vec<String>,
+    },
+    LinkFinished {
+        name: String,
+        output: PathBuf,
+        success: bool,
+    },
+    /// A post-link step of the toolchain or profile writes `output` from a linked artifact, `step` is objcopy or strip.
+    PostLinkStarted {
+        name: String,
+        step: String,
+        output: PathBuf,
+        command: Vec<String>,
+    },
+    PostLinkFinished {
+        name: String,
+        step: String,
+        output: PathBuf,
+        success: bool,
+    },
+    /// Section totals of a linked artifact in bytes, and the budgets they are compared against.
+    SizeReport {
+        name: String,
+        text: u64,
+        data: u64,
+        bss: u64,
+        flash: u64,
+        ram: u64,
+        flash_budget: Option<u64>,
+        ram_budget: Option<u64>,
+    },
+    Artifact {
+        name: String,
+        link_strategy: LinkStrategy,
+        path: PathBuf,
+    },
+    BuildFinished {
+        success: bool,
+        artifacts: Vec<PathBuf>,
+    },
+    /// Wall-clock seconds per compiler phase, from GCC's -ftime-report.
+    TimeReport {
+        source: PathBuf,
+        phases: BTreeMap<String, f64>,
+    },
+    /// A line written by a compiler, linker or git while it runs.
+    Output {
+        job: String,
+        stream: OutputStream,
+        line: String,
+    },
+}
+
+/**
+    Receives build events from the build command and the tools it drives.
+ */
+pub trait Reporter: Send + Sync {
+    fn report(&self, event: Event);
+}
+
+/** Human-readable progress through the logger */
+pub struct LogReporter;
+
+impl Reporter for LogReporter {
+    fn report(&self, event: Event) {
+        match event {
+            Event::DependencyFetchStarted { name, registry } => {
+                log::info!("Fetching dependency '{}' from registry '{}'", name, registry);
+            }
+            Event::DependencyFetchFinished { name, fresh: true, .. } => {
+                log::info!("Skipping already fetched dependency '{}'", name);
+            }
+            Event::DependencyFetchFinished { name, path, fresh: false } => {
+                log::info!("Fetched dependency '{}' into {:?}", name, path);
+            }
+            Event::CompileStarted { source, object, .. } => {
+                log::info!("Compiling {:?} into {:?}", source, object);
+            }
+            Event::CompileFinished { source, success: false, .. } => {
+                log::error!("Failed to compile {:?}", source);
+            }
+            Event::CompileFinished { .. } => {}
+            Event::CacheHit { source, .. } => {
+                log::info!("Skipping already compiled file {:?}", source);
+            }
+            /* the compiler output was shown while it ran */
+            Event::Diagnostic { cached: false, .. } => {}
+            Event::Diagnostic { diagnostic, .. } => {
+                let message = format_diagnostic(&diagnostic);
+
+                match diagnostic.severity {
+                    Severity::Error => log::error!("{}", message),
+                    Severity::Warning | Severity::Note => log::warn!("{}", message),
+                }
+            }
+            Event::DiagnosticSummary { warnings: 0, errors: 0, .. } => {}
+            Event::DiagnosticSummary { warnings, errors, options } => {
+                let options: Vec<String> = options
+                    .iter()
+                    .map(|(option, count)| format!("{} {}", count, option))
+                    .collect();
+
+                if options.is_empty() {
+                    log::warn!("{} warning(s), {} error(s)", warnings, errors);
+                } else {
+                    log::warn!("{} warning(s), {} error(s): {}", warnings, errors, options.join(", "));
+                }
+            }
+            Event::GenerateStarted { name, .. } => {
+                log::info!("Generating '{}'", name);
+            }
+            Event::GenerateFinished { name, success: false } => {
+                log::error!("Failed to generate '{}'", name);
+            }
+            Event::GenerateFinished { .. } => {}
+            Event::GenerateFresh { name } => {
+                log::info!("Skipping up-to-date generator '{}'", name);
+            }
+            Event::LinkStarted { name, link_strategy, output, .. } => {
+                log::info!("Linking {:?} '{}' into {:?}", link_strategy, name, output);
+            }
+            Event::LinkFinished { name, success: false, .. } => {
+                log::error!("Failed to link '{}'", name);
+            }
+            Event::LinkFinished { .. } => {}
+            Event::PostLinkStarted { name, step, output, .. } => {
+                log::info!("Running {} on '{}' into {:?}", step, name, output);
+            }
+            Event::PostLinkFinished { name, step, success: false, .. } => {
+                log::error!("Failed to run {} on '{}'", step, name);
+            }
+            Event::PostLinkFinished { .. } => {}
+            Event::SizeReport { name, text, data, bss, flash, ram, flash_budget, ram_budget } => {
+                log::info!(
+                    "Size of '{}': text {}, data {}, bss {} bytes; flash {}, ram {}",
+                    name, text, data, bss, usage(flash, flash_budget), usage(ram, ram_budget)
+                );
+            }
+            Event::Artifact { name, path, .. } => {
+                log::info!("Built '{}': {:?}", name, path);
+            }
+            Event::BuildFinished { success: true, .. } => {
+                log::info!("BUILD SUCCESSFUL");
+            }
+            Event::BuildFinished { success: false, .. } => {
+                log::error!("BUILD FAILED");
+            }
+            Event::TimeReport { source, phases } => {
+                let total: f64 = phases.values().sum();
+                log::debug!("Compiler phases of {:?} took {:.3}s", source, total);
+            }
+            Event::Output { job, stream, line } => {
+                let level = match stream {
+                    OutputStream::Stdout => log::Level::Info,
+                    OutputStream::Stderr => log::Level::Warn,
+                };
+
+                /* printed as is, to keep the colors of the tool */
+                if log::log_enabled!(level) {
+                    eprintln!("[{}] {}", job, line);
+                }
+            }
+        }
+    }
+}
+
+/* "1794 bytes" or "1794 of 65536 bytes (2.7%)" */
+fn usage(used: u64, budget: Option<u64>) -> String {
+    match budget {
+        Some(budget) if budget > 0 => format!("{} of {} bytes ({:.1}%)", used, budget, used as f64 * 100.0 / budget as f64),
+        _ => format!("{} bytes", used),
+    }
+}
+
+/* "src/main.c:4:9: warning: unused variable 'x' [-Wunused-variable]" followed by its notes */
+fn format_diagnostic(diagnostic: &Diagnostic) -> String {
+    let mut location = diagnostic.file.display().to_string();
+
+    for number in [diagnostic.line, diagnostic.column].into_iter().flatten() {
+        location.push_str(&format!(":{}", number));
+    }
+
+    let severity = match diagnostic.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+    };
+
+    let mut message = format!("{}: {}: {}", location, severity, diagnostic.message);
+
+    if let Some(option) = &diagnostic.option {
+        message.push_str(&format!(" [{}]", option));
+    }
+
+    for note in &diagnostic.notes {
+        message.push('\n');
+        message.push_str(&format_diagnostic(note));
+    }
+
+    message
+}
+
+/**
+    Passes events on to another reporter and keeps the diagnostics for the end of the build.
+ */
+pub struct DiagnosticCollector<'a> {
+    reporter: &'a dyn Reporter,
+    diagnostics: Mutex<Vec<Diagnostic>>,
+}
+
+impl<'a> DiagnosticCollector<'a> {
+    pub fn new(reporter: &'a dyn Reporter) -> Self {
+        Self {
+            reporter,
+            diagnostics: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.diagnostics.into_inner().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl Reporter for DiagnosticCollector<'_> {
+    fn report(&self, event: Event) {
+        if let Event::Diagnostic { diagnostic, .. } = &event {
+            let mut diagnostics = self.diagnostics.lock().unwrap_or_else(|error| error.into_inner());
+            diagnostics.push(diagnostic.clone());
+        }
+
+        self.reporter.report(event);
+    }
+}
+
+/** Counts unique diagnostics by severity and warning option */
+pub fn diagnostic_summary(diagnostics: &[Diagnostic]) -> Event {
+    let mut warnings = 0;
+    let mut errors = 0;
+    let mut options = BTreeMap::new();
+
+    for diagnostic in diagnostics {
+        match diagnostic.severity {
+            Severity::Error => errors += 1,
+            Severity::Warning => warnings += 1,
+            Severity::Note => continue,
+        }
+
+        if let Some(option) = &diagnostic.option {
+            *options.entry(option.clone()).or_insert(0) += 1;
+        }
+    }
+
+    Event::DiagnosticSummary {
+        warnings,
+        errors,
+        options,
+    }
+}
+
+/** Newline-delimited JSON on stdout, one event per line */
+pub struct JsonReporter;
+
+impl Reporter for JsonReporter {
+    fn report(&self, event: Event) {
+        let Ok(line) = serde_json::to_string(&event) else {
+            return;
+        };
+
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+    }
+}
+
+pub fn reporter(message_format: &MessageFormat) -> Box<dyn Reporter> {
+    match message_format {
+        MessageFormat::Human => Box::new(LogReporter),
+        MessageFormat::Json => Box::new(JsonReporter),
+    }
+}
@@ -1,38 +1,245 @@
-use anyhow::Result;
-use std::process::Command;
+use crate::error::Interrupted;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/* how often a running command is checked for its exit, a timeout or Ctrl-C */
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/*
+   after a command exited, its output is read until both pipes close; only when no line arrives for this long
+   another process is assumed to keep them open
+ */
+const EXIT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/**
+    Makes Ctrl-C stop the running command instead of corge-rs itself, so the command can be killed cleanly.
+    Pressing Ctrl-C a second time exits immediately.
+ */
+pub fn handle_interrupts() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+    })
+        .context("Failed to set Ctrl-C handler")
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/**
+    Stops running commands the way Ctrl-C does, until it is reset.
+    `watch` cancels an in-flight build with it when files change again.
+ */
+pub fn set_cancelled(cancelled: bool) {
+    CANCELLED.store(cancelled, Ordering::SeqCst);
+}
+
+fn stopped() -> bool {
+    interrupted() || CANCELLED.load(Ordering::SeqCst)
+}
+
+/** Waits for a child started with inherited stdio, killing it on Ctrl-C or cancellation */
+pub fn wait(child: &mut Child) -> Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait for child process")? {
+            return Ok(status);
+        }
+
+        if stopped() {
+            let _ = child.kill();
+            let _ = child.wait();
+
+            return Err(Interrupted.into());
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+pub struct CapturedOutput {
+    pub status: ExitStatus,
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
 
 pub trait ExecuteCommand {
     fn execute(&mut self, log: bool) -> Result<String>;
+    /** Runs the command and returns its output whether it succeeds or not */
+    fn capture(&mut self, log: bool) -> Result<CapturedOutput>;
+    /**
+        Like capture, and passes every line to on_line as soon as the command writes it.
+        The command is killed when it runs longer than the timeout or Ctrl-C is pressed.
+    */
+    fn stream(&mut self, timeout: Option<Duration>, on_line: &mut dyn FnMut(OutputStream, &str)) -> Result<CapturedOutput>;
 }
 
 impl ExecuteCommand for Command {
     fn execute(&mut self, log: bool) -> Result<String> {
+        let output = self.capture(log)?;
+
+        if output.success {
+            Ok(output.stdout)
+        } else {
+            anyhow::bail!(output.stderr);
+        }
+    }
+
+    fn capture(&mut self, log: bool) -> Result<CapturedOutput> {
         if log {
             log::trace!("{}", self.cli_str());
         }
 
-        match self.output() {
-            Ok(output) => {
-                if output.status.success() {
-                    let std_out: String = String::from_utf8_lossy(&output.stdout).to_string();
+        let captured = self.stream(None, &mut |_, _| {})?;
+
+        if captured.success {
+            log::trace!("{}", captured.stdout);
+        }
+
+        Ok(captured)
+    }
+
+    fn stream(&mut self, timeout: Option<Duration>, on_line: &mut dyn FnMut(OutputStream, &str)) -> Result<CapturedOutput> {
+        if stopped() {
+            return Err(Interrupted.into());
+        }
+
+        let mut child = self
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start {:?}", self.get_program()))?;
 
-                    log::trace!("{std_out}");
+        let (sender, receiver) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            read_lines(stdout, OutputStream::Stdout, sender.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            read_lines(stderr, OutputStream::Stderr, sender);
+        }
+
+        let started = Instant::now();
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+
+        let mut receive = |stream: OutputStream, line: String| {
+            on_line(stream, &line);
+
+            let output = match stream {
+                OutputStream::Stdout => &mut stdout,
+                OutputStream::Stderr => &mut stderr,
+            };
+            output.push_str(&line);
+            output.push('\n');
+        };
+
+        let status = loop {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok((stream, line)) => receive(stream, line),
+                /* both pipes are closed, but the command may still be running */
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(POLL_INTERVAL),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            if stopped() {
+                let _ = child.kill();
+                let _ = child.wait();
+
+                return Err(Interrupted.into());
+            }
 
-                    Ok(std_out)
-                } else {
-                    anyhow::bail!(String::from_utf8_lossy(&output.stderr).to_string());
+            if let Some(timeout) = timeout {
+                if started.elapsed() > timeout {
+                    let _ = child.kill();
+                    let _ = child.wait();
+
+                    anyhow::bail!("{:?} timed out after {} seconds", self.get_program(), timeout.as_secs());
+                }
+            }
+        };
+
+        /*
+           lines written right before the exit, until the reader threads hang up;
+           a grandchild holding a pipe open keeps its reader thread running, which is left behind
+         */
+        loop {
+            match receiver.recv_timeout(EXIT_DRAIN_TIMEOUT) {
+                Ok((stream, line)) => receive(stream, line),
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {
+                    log::warn!(
+                        "{:?} exited but its output is still open, probably by a process it started; not waiting for more",
+                        self.get_program()
+                    );
+                    break;
                 }
-            },
-            Err(error) => Err(anyhow::anyhow!(error))
+            }
         }
+
+        Ok(CapturedOutput {
+            status,
+            success: status.success(),
+            stdout,
+            stderr,
+        })
     }
 }
 
+/* Sends the lines of a pipe from a thread, splitting progress output on carriage returns too */
+fn read_lines<R: Read + Send + 'static>(pipe: R, stream: OutputStream, sender: Sender<(OutputStream, String)>) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buffer = vec![];
+
+        while reader.read_until(b'\n', &mut buffer).unwrap_or(0) > 0 {
+            let text = String::from_utf8_lossy(&buffer);
+
+            for line in text.trim_end_matches(['\n', '\r']).split('\r') {
+                if sender.send((stream, line.to_string())).is_err() {
+                    return;
+                }
+            }
+
+            buffer.clear();
+        }
+    });
+}
+
 pub trait CliStr {
     fn cli_str(&self) -> String;
+    fn cli_args(&self) -> Vec<String>;
 }
 
 impl CliStr for Command {
+    fn cli_args(&self) -> Vec<String> {
+        std::iter::once(self.get_program())
+            .chain(self.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
     fn cli_str(&self) -> String {
         let mut parts: Vec<&str> = vec![
             self.get_program().to_str().unwrap_or("[Non-UTF8 Program]")
@@ -47,3 +254,44 @@ impl CliStr for Command {
         parts.join(" ")
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_written_before_the_exit_is_complete() {
+        let output = Command::new("sh")
+            .args(["-c", "i=0; while [ $i -lt 5000 ]; do echo line$i; echo err$i >&2; i=$((i+1)); done"])
+            .stream(None, &mut |_, _| {})
+            .unwrap();
+
+        assert!(output.success);
+        assert_eq!(output.stdout.lines().count(), 5000);
+        assert_eq!(output.stderr.lines().last(), Some("err4999"));
+    }
+
+    #[test]
+    fn grandchild_holding_the_pipes_does_not_block() {
+        let started = Instant::now();
+
+        let output = Command::new("sh")
+            .args(["-c", "sleep 10 & echo started"])
+            .stream(None, &mut |_, _| {})
+            .unwrap();
+
+        assert_eq!(output.stdout, "started\n");
+        assert!(started.elapsed() < Duration::from_secs(8));
+    }
+
+    #[test]
+    fn timeout_kills_the_command() {
+        let error = Command::new("sleep")
+            .arg("10")
+            .stream(Some(Duration::from_millis(100)), &mut |_, _| {})
+            .err()
+            .unwrap();
+
+        assert_eq!(error.to_string(), "\"sleep\" timed out after 0 seconds");
+    }
+}
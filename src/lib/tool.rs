@@ -5,3 +5,19 @@ pub mod compiler;
 pub mod linker;
 pub mod files_fetcher;
 pub mod dir_copier;
+pub mod lockfile;
+pub mod workspace_resolver;
+pub mod toolchain_probe;
+pub mod flag_renderer;
+pub mod toolchain_environment;
+pub mod diagnostic_parser;
+pub mod sarif;
+pub mod timings;
+pub mod object_cache;
+pub mod cfg;
+pub mod feature_resolver;
+pub mod generator;
+pub mod resource_embedder;
+pub mod post_linker;
+pub mod map_parser;
+pub mod version_header;
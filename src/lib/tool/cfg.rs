@@ -0,0 +1,343 @@
+use anyhow::Result;
+use std::str::FromStr;
+use target_lexicon::{Architecture, Environment, OperatingSystem, Triple};
+
+/**
+    What `cfg` expressions of build.yaml are evaluated against: the target triple the compiler produces code for
+    and the build profile.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub os: String,
+    pub arch: String,
+    pub env: String,
+    pub vendor: String,
+    /// `unix`, `windows` or empty.
+    pub family: String,
+    pub profile: String,
+}
+
+impl Cfg {
+    /**
+        @param: target_triple - the triple the compiler produces code for, see `ToolchainInfo::target`
+        @param: profile - development or release
+     */
+    pub fn new(target_triple: &str, profile: &str) -> Self {
+        /* triples of compilers target-lexicon doesn't know, e.g. SDCC's mcs51, match no os or arch */
+        let triple = Triple::from_str(target_triple).unwrap_or_else(|_| Triple::unknown());
+
+        let os = match triple.operating_system {
+            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => "macos".to_string(),
+            OperatingSystem::Unknown => String::new(),
+            operating_system => operating_system.to_string(),
+        };
+
+        let arch = match triple.architecture {
+            Architecture::Arm(_) => "arm".to_string(),
+            Architecture::Aarch64(_) => "aarch64".to_string(),
+            Architecture::X86_32(_) => "x86".to_string(),
+            Architecture::X86_64 | Architecture::X86_64h => "x86_64".to_string(),
+            Architecture::Riscv32(_) => "riscv32".to_string(),
+            Architecture::Riscv64(_) => "riscv64".to_string(),
+            Architecture::Unknown => String::new(),
+            architecture => architecture.to_string(),
+        };
+
+        let env = match triple.environment {
+            Environment::Unknown => String::new(),
+            environment => environment.to_string(),
+        };
+
+        let family = match triple.operating_system {
+            OperatingSystem::Windows => "windows",
+            OperatingSystem::Unknown | OperatingSystem::None_ => "",
+            _ => "unix",
+        };
+
+        Self {
+            os,
+            arch,
+            env,
+            vendor: triple.vendor.to_string(),
+            family: family.to_string(),
+            profile: profile.to_string(),
+        }
+    }
+
+    fn value(&self, key: &str) -> Option<&str> {
+        match key {
+            "os" => Some(&self.os),
+            "arch" => Some(&self.arch),
+            "env" => Some(&self.env),
+            "vendor" => Some(&self.vendor),
+            "family" => Some(&self.family),
+            "profile" => Some(&self.profile),
+            _ => None,
+        }
+    }
+}
+
+/**
+    A `cfg` expression, e.g. `all(os = "linux", arch = "x86_64")` or `any(windows, not(profile = "release"))`.
+
+    Predicates are `key = "value"` with the keys os, arch, env, vendor, family and profile,
+    and `unix` or `windows` for the family. They are combined with all(), any() and not().
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum CfgExpression {
+    Equals(String, String),
+    All(Vec<CfgExpression>),
+    Any(Vec<CfgExpression>),
+    Not(Box<CfgExpression>),
+}
+
+impl CfgExpression {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser { tokens, position: 0 };
+
+        let parsed = parser.expression()?;
+
+        if let Some(token) = parser.tokens.get(parser.position) {
+            anyhow::bail!("Unexpected {} in cfg expression '{}'", token, expression);
+        }
+
+        Ok(parsed)
+    }
+
+    pub fn matches(&self, cfg: &Cfg) -> bool {
+        match self {
+            CfgExpression::Equals(key, value) => cfg.value(key) == Some(value.as_str()),
+            CfgExpression::All(expressions) => expressions.iter().all(|expression| expression.matches(cfg)),
+            CfgExpression::Any(expressions) => expressions.iter().any(|expression| expression.matches(cfg)),
+            CfgExpression::Not(expression) => !expression.matches(cfg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    String(String),
+    Equals,
+    Comma,
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Identifier(identifier) => write!(f, "'{}'", identifier),
+            Token::String(string) => write!(f, "\"{}\"", string),
+            Token::Equals => write!(f, "'='"),
+            Token::Comma => write!(f, "','"),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = expression.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            ' ' | '\t' | '\n' => {}
+            '=' => tokens.push(Token::Equals),
+            ',' => tokens.push(Token::Comma),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut string = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(char) => string.push(char),
+                        None => anyhow::bail!("Unterminated string in cfg expression '{}'", expression),
+                    }
+                }
+
+                tokens.push(Token::String(string));
+            }
+            char if char.is_ascii_alphanumeric() || char == '_' => {
+                let mut identifier = char.to_string();
+
+                while let Some(&char) = chars.peek() {
+                    if !char.is_ascii_alphanumeric() && char != '_' {
+                        break;
+                    }
+
+                    identifier.push(char);
+                    chars.next();
+                }
+
+                tokens.push(Token::Identifier(identifier));
+            }
+            char => anyhow::bail!("Unexpected '{}' in cfg expression '{}'", char, expression),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => anyhow::bail!("Expected {} but found {}", expected, token),
+            None => anyhow::bail!("Expected {} but the expression ended", expected),
+        }
+    }
+
+    fn expression(&mut self) -> Result<CfgExpression> {
+        let identifier = match self.next() {
+            Some(Token::Identifier(identifier)) => identifier,
+            Some(token) => anyhow::bail!("Expected a predicate, all(), any() or not() but found {}", token),
+            None => anyhow::bail!("Expected a predicate, all(), any() or not() but the expression ended"),
+        };
+
+        match identifier.as_str() {
+            "all" | "any" | "not" if self.peek() == Some(&Token::Open) => {
+                let arguments = self.arguments()?;
+
+                match identifier.as_str() {
+                    "all" => Ok(CfgExpression::All(arguments)),
+                    "any" => Ok(CfgExpression::Any(arguments)),
+                    _ => {
+                        let [argument] = <[CfgExpression; 1]>::try_from(arguments)
+                            .map_err(|_| anyhow::anyhow!("not() takes exactly one expression"))?;
+
+                        Ok(CfgExpression::Not(Box::new(argument)))
+                    }
+                }
+            }
+            "unix" | "windows" if self.peek() != Some(&Token::Equals) => {
+                Ok(CfgExpression::Equals("family".to_string(), identifier))
+            }
+            "os" | "arch" | "env" | "vendor" | "family" | "profile" => {
+                self.expect(Token::Equals)?;
+
+                match self.next() {
+                    Some(Token::String(value)) => Ok(CfgExpression::Equals(identifier, value)),
+                    Some(token) => anyhow::bail!("Expected a quoted value after {} = but found {}", identifier, token),
+                    None => anyhow::bail!("Expected a quoted value after {} =", identifier),
+                }
+            }
+            _ => anyhow::bail!(
+                "Unknown cfg predicate '{}', expected os, arch, env, vendor, family, profile, unix or windows",
+                identifier
+            ),
+        }
+    }
+
+    /* "(" [expression ("," expression)* [","]] ")" */
+    fn arguments(&mut self) -> Result<Vec<CfgExpression>> {
+        self.expect(Token::Open)?;
+
+        let mut arguments = vec![];
+
+        loop {
+            if self.peek() == Some(&Token::Close) {
+                self.next();
+                return Ok(arguments);
+            }
+
+            arguments.push(self.expression()?);
+
+            match self.next() {
+                Some(Token::Comma) => {}
+                Some(Token::Close) => return Ok(arguments),
+                Some(token) => anyhow::bail!("Expected ',' or ')' but found {}", token),
+                None => anyhow::bail!("Expected ')' but the expression ended"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(expression: &str, cfg: &Cfg) -> bool {
+        CfgExpression::parse(expression).unwrap().matches(cfg)
+    }
+
+    #[test]
+    fn cfg_of_target_triples() {
+        let linux = Cfg::new("x86_64-pc-linux-gnu", "release");
+        assert_eq!(
+            (linux.os.as_str(), linux.arch.as_str(), linux.env.as_str(), linux.family.as_str()),
+            ("linux", "x86_64", "gnu", "unix")
+        );
+
+        let macos = Cfg::new("arm64-apple-darwin23.0.0", "development");
+        assert_eq!((macos.os.as_str(), macos.arch.as_str(), macos.vendor.as_str()), ("macos", "aarch64", "apple"));
+
+        let windows = Cfg::new("x86_64-pc-windows-gnu", "release");
+        assert_eq!((windows.os.as_str(), windows.family.as_str()), ("windows", "windows"));
+
+        let unknown = Cfg::new("mcs51", "release");
+        assert_eq!((unknown.os.as_str(), unknown.arch.as_str(), unknown.family.as_str()), ("", "", ""));
+    }
+
+    #[test]
+    fn parse_nested_expressions() {
+        assert_eq!(
+            CfgExpression::parse(r#"all(os = "linux", not(any(windows, profile = "release"),),)"#).unwrap(),
+            CfgExpression::All(vec![
+                CfgExpression::Equals("os".to_string(), "linux".to_string()),
+                CfgExpression::Not(Box::new(CfgExpression::Any(vec![
+                    CfgExpression::Equals("family".to_string(), "windows".to_string()),
+                    CfgExpression::Equals("profile".to_string(), "release".to_string()),
+                ]))),
+            ])
+        );
+    }
+
+    #[test]
+    fn matches_against_the_cfg() {
+        let cfg = Cfg::new("aarch64-unknown-linux-gnu", "development");
+
+        assert!(matches(r#"all(os = "linux", arch = "aarch64")"#, &cfg));
+        assert!(matches("unix", &cfg));
+        assert!(!matches("windows", &cfg));
+        assert!(matches(r#"any(windows, not(profile = "release"))"#, &cfg));
+        assert!(matches("all()", &cfg));
+        assert!(!matches("any()", &cfg));
+    }
+
+    #[test]
+    fn invalid_expressions_are_errors() {
+        for expression in [
+            r#"os = linux"#,
+            r#"os = "linux"#,
+            r#"compiler = "gcc""#,
+            r#"not(unix, windows)"#,
+            r#"all(unix windows)"#,
+            r#"unix)"#,
+            r#"all(unix"#,
+            "",
+        ] {
+            assert!(CfgExpression::parse(expression).is_err(), "{}", expression);
+        }
+    }
+}
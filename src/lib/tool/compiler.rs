@@ -1,6 +1,12 @@
-use crate::config::{Profile, Toolchain};
+use crate::config::{CompilerFamily, Profile, Toolchain};
 use crate::extension_manager::Extension;
-use crate::std_command_ext::ExecuteCommand;
+use crate::reporter::{Event, Reporter};
+use crate::std_command_ext::{CliStr, ExecuteCommand, OutputStream};
+use crate::tool::diagnostic_parser::{self, Diagnostic, Severity};
+use crate::tool::flag_renderer::{flag_renderer, Flag, FlagRenderer};
+use crate::tool::object_cache;
+use crate::tool::timings;
+use crate::tool::toolchain_probe::ToolchainInfo;
 use anyhow::{Context, Result};
 use sha2::{Digest, Sha256};
 use std::fmt::Debug;
@@ -8,15 +14,44 @@ use std::fs;
 use std::hash::Hash;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 
-fn hash<P: AsRef<Path> + Hash + Debug>(path: P) -> Result<String> {
+const DIAGNOSTICS_COLOR_FLAG: &str = "-fdiagnostics-color=always";
+const TIME_REPORT_FLAG: &str = "-ftime-report";
+
+/** Settings that change how compilations run, but not the object files they produce */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompileOptions {
+    pub timeout: Option<Duration>,
+    /// Colored diagnostics, if the compiler supports them.
+    pub color: bool,
+    /// GCC's -ftime-report, reported per source file.
+    pub time_report: bool,
+}
+
+/** Settings of a group of sources, e.g. a dependency, which are part of the object cache key */
+#[derive(Debug, Clone, Default)]
+pub struct SourceSettings {
+    /// Position-independent code.
+    pub pic: bool,
+    /// Include directories of the sources, besides the dependency headers.
+    pub include_paths: Vec<PathBuf>,
+    /// Definitions added to the profile's.
+    pub defines: Vec<String>,
+    /// Flags added after the toolchain's.
+    pub compiler_flags: Vec<String>,
+    /// Enabled features, part of the cache key even when they add no defines.
+    pub features: Vec<String>,
+}
+
+fn hash<P: AsRef<Path> + Hash + Debug>(path: P, arguments: &str) -> Result<String> {
     let source_file_name = path
         .as_ref()
         .file_name()
         .ok_or_else(|| anyhow::anyhow!("Failed to get file name from {:?}", path))?;
 
     let content = fs::read_to_string(&path)?;
-    let file = format!("{:?}-{}", path, content);
+    let file = format!("{:?}-{}-{}", path, arguments, content);
 
     let mut hasher = Sha256::new();
 
@@ -31,76 +66,296 @@ fn hash<P: AsRef<Path> + Hash + Debug>(path: P) -> Result<String> {
     Ok(format!("{}.{}", source_file_name.to_string_lossy(), hash))
 }
 
-pub struct Compiler {
+/* diagnostics of a compilation are kept next to its object file, so cache hits can replay them */
+fn diagnostics_file(object_file: &Path) -> PathBuf {
+    object_file.with_extension("diagnostics.json")
+}
+
+fn read_diagnostics(object_file: &Path) -> Result<Vec<Diagnostic>> {
+    let diagnostics_file = diagnostics_file(object_file);
+
+    if !fs::exists(&diagnostics_file).with_context(|| format!("Failed to check if file exists {:?}", diagnostics_file))? {
+        return Ok(vec![]);
+    }
+
+    let content = fs::read_to_string(&diagnostics_file)
+        .with_context(|| format!("Failed to read {:?}", diagnostics_file))?;
+
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {:?}", diagnostics_file))
+}
+
+fn write_diagnostics(object_file: &Path, diagnostics: &[Diagnostic]) -> Result<()> {
+    let diagnostics_file = diagnostics_file(object_file);
+
+    if diagnostics.is_empty() {
+        if fs::exists(&diagnostics_file).unwrap_or(false) {
+            fs::remove_file(&diagnostics_file)
+                .with_context(|| format!("Failed to remove {:?}", diagnostics_file))?;
+        }
+
+        return Ok(());
+    }
+
+    fs::write(&diagnostics_file, serde_json::to_string(diagnostics)?)
+        .with_context(|| format!("Failed to write {:?}", diagnostics_file))
+}
+
+/* the headers an object file was compiled with, written by the compiler in make syntax */
+fn dependency_file(object_file: &Path) -> PathBuf {
+    object_file.with_extension("d")
+}
+
+/**
+    The object file name covers the source and the arguments, but not the headers it includes,
+    so a header newer than the object file makes it stale.
+    Compilers that don't write dependency files only track the source.
+ */
+fn headers_changed(object_file: &Path) -> bool {
+    let Ok(dependencies) = fs::read_to_string(dependency_file(object_file)) else {
+        return false;
+    };
+
+    let Ok(compiled) = fs::metadata(object_file).and_then(|metadata| metadata.modified()) else {
+        return true;
+    };
+
+    /* "object.o: source.c header.h \" with escaped spaces in paths */
+    let dependencies = dependencies.replace("\\\n", " ").replace("\\ ", "\0");
+    let Some((_, headers)) = dependencies.split_once(": ") else {
+        return false;
+    };
+
+    /* the source itself is covered by the object file name */
+    headers
+        .split_whitespace()
+        .skip(1)
+        .map(|header| header.replace('\0', " "))
+        .any(|header| {
+            fs::metadata(&header)
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| modified > compiled)
+                .unwrap_or(true)
+        })
+}
+
+pub struct Compiler<'a> {
+    renderer: Box<dyn FlagRenderer + 'a>,
     profile: Profile,
     toolchain: Toolchain,
+    toolchain_info: &'a ToolchainInfo,
     include_path: PathBuf,
+    reporter: &'a dyn Reporter,
+    options: CompileOptions,
 }
 
-impl Compiler {
-    pub fn new(profile: Profile, toolchain: Toolchain, include_path: PathBuf) -> Self {
+impl<'a> Compiler<'a> {
+    pub fn new(
+        profile: Profile,
+        toolchain: Toolchain,
+        toolchain_info: &'a ToolchainInfo,
+        include_path: PathBuf,
+        reporter: &'a dyn Reporter,
+        options: CompileOptions,
+    ) -> Self {
         Self {
+            renderer: flag_renderer(toolchain.family, toolchain_info),
             profile,
             toolchain,
+            toolchain_info,
             include_path,
+            reporter,
+            options,
         }
     }
 
     /**
         @param: source_files - list of source files
         @param: output_path - output directory path
-        @param: pic - position independent code
+        @param: settings - settings of the sources, see `SourceSettings`
 
         @return: list of compiled object files
     */
-    pub fn compile(&self, source_files: &[PathBuf], output_path: &PathBuf, pic: bool) -> Result<Vec<PathBuf>> {
+    pub fn compile(&self, source_files: &[PathBuf], output_path: &Path, settings: &SourceSettings) -> Result<Vec<PathBuf>> {
         let mut object_files = vec![];
 
         for source_file in source_files {
-            let output_stem = hash(source_file)
-                .with_context(|| format!("Failed to hash source file {:?}", source_file))?;
-
-            let output_name = Extension::Object.file_name(&output_stem, &self.toolchain.compiler);
-
-            let output_file = output_path.join(output_name);
+            let output_file = self.object_file(source_file, output_path, settings)?;
 
             object_files.push(output_file.clone());
 
             let file_exists = fs::exists(&output_file).with_context(|| format!("Failed to check if file exists {:?}", output_file))?;
-            if file_exists {
-                log::info!("Skipping already compiled file {:?}", source_file);
+            if file_exists && !headers_changed(&output_file) {
+                let diagnostics = read_diagnostics(&output_file)?;
+                object_cache::touch(&output_file)?;
+
+                self.reporter.report(Event::CacheHit {
+                    source: source_file.clone(),
+                    object: output_file,
+                });
+
+                self.report_diagnostics(source_file, diagnostics, true);
                 continue;
             }
-            log::info!("Compiling {:?} into {}", source_file, output_path.display());
 
-            let mut command = Command::new(&self.toolchain.compiler);
+            let mut command = self.command(source_file, &output_file, settings)?;
 
-            if let Some(level) = self.profile.optimization_level.as_gcc_flag() {
-                command.arg(level);
+            self.reporter.report(Event::CompileStarted {
+                source: source_file.clone(),
+                object: output_file.clone(),
+                command: command.cli_args(),
+            });
+
+            /* colors only change how diagnostics look, so they aren't part of the command that names the object */
+            if self.options.color && self.toolchain_info.supports(DIAGNOSTICS_COLOR_FLAG) {
+                command.arg(DIAGNOSTICS_COLOR_FLAG);
             }
 
-            command
-                .arg("-I")
-                .arg(&self.include_path);
+            let time_report = self.options.time_report && self.toolchain_info.family == CompilerFamily::Gcc;
+            if time_report {
+                command.arg(TIME_REPORT_FLAG);
+            }
 
-            command.args(&self.toolchain.compiler_flags);
+            let job = source_file.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let output = command
+                .stream(self.options.timeout, &mut |stream, line| {
+                    /* the table is reported as Event::TimeReport once the compiler finishes */
+                    if time_report && stream == OutputStream::Stderr && timings::is_time_report_line(line) {
+                        return;
+                    }
 
-            if pic {
-                command.arg("-fPIC");
+                    self.reporter.report(Event::Output {
+                        job: job.clone(),
+                        stream,
+                        line: line.to_string(),
+                    });
+                })
+                .with_context(|| format!("Failed to run compiler for file {:?}", source_file))?;
+
+            if time_report {
+                self.reporter.report(Event::TimeReport {
+                    source: source_file.clone(),
+                    phases: timings::parse_time_report(&output.stderr),
+                });
             }
 
-            command
-                .arg("-c")
-                .arg(source_file);
+            let mut diagnostics = diagnostic_parser::parse(&output.stderr);
+
+            /* keep output the parser doesn't understand rather than losing the reason of the failure */
+            if !output.success && diagnostics.is_empty() {
+                diagnostics.push(Diagnostic {
+                    file: source_file.clone(),
+                    line: None,
+                    column: None,
+                    severity: Severity::Error,
+                    message: output.stderr.trim_end().to_string(),
+                    option: None,
+                    notes: vec![],
+                });
+            }
 
-            command
-                .arg("-o")
-                .arg(&output_file);
+            if output.success {
+                write_diagnostics(&output_file, &diagnostics)?;
+            }
 
-            command.execute(true)
-                .with_context(|| format!("Failed to compile file {:?}", source_file))?;
+            self.report_diagnostics(source_file, diagnostics.clone(), false);
+
+            self.reporter.report(Event::CompileFinished {
+                source: source_file.clone(),
+                object: output_file,
+                success: output.success,
+            });
+
+            if !output.success {
+                /* the diagnostics are reported already, unless the compiler output couldn't be parsed */
+                let errors = diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Error).count();
+                let error = match errors {
+                    0 => anyhow::anyhow!(output.stderr),
+                    _ => anyhow::anyhow!("The compiler reported {} error(s)", errors),
+                };
+
+                return Err(error).with_context(|| format!("Failed to compile file {:?}", source_file));
+            }
         }
 
         Ok(object_files)
     }
+
+    fn report_diagnostics(&self, source_file: &Path, diagnostics: Vec<Diagnostic>, cached: bool) {
+        for diagnostic in diagnostics {
+            self.reporter.report(Event::Diagnostic {
+                source: source_file.to_path_buf(),
+                cached,
+                diagnostic,
+            });
+        }
+    }
+
+    /** Object files are named after the source content and the compiler arguments */
+    pub fn object_file(&self, source_file: &Path, output_path: &Path, settings: &SourceSettings) -> Result<PathBuf> {
+        let mut arguments = self.arguments(settings)?.join(" ");
+
+        if !settings.features.is_empty() {
+            arguments.push_str(&format!(" features={}", settings.features.join(",")));
+        }
+
+        let output_stem = hash(source_file, &arguments)
+            .with_context(|| format!("Failed to hash source file {:?}", source_file))?;
+
+        let output_name = Extension::Object.file_name(&output_stem, &self.toolchain_info.target);
+
+        Ok(output_path.join(output_name))
+    }
+
+    /** Builds the command that compiles one source file into one object file */
+    pub fn command(&self, source_file: &Path, output_file: &Path, settings: &SourceSettings) -> Result<Command> {
+        let mut command = Command::new(&self.toolchain_info.compiler_path);
+
+        command.args(self.arguments(settings)?);
+        command.args(self.renderer.render(&Flag::CompileOnly(source_file))?);
+        command.args(self.renderer.render(&Flag::ObjectOutput(output_file))?);
+        command.args(self.renderer.render(&Flag::DependencyFile(&dependency_file(output_file)))?);
+
+        Ok(command)
+    }
+
+    /** Arguments shared by every source file, which are also part of the object cache key */
+    fn arguments(&self, settings: &SourceSettings) -> Result<Vec<String>> {
+        let mut flags = vec![
+            Flag::Optimization(&self.profile.optimization_level),
+        ];
+
+        if self.profile.debug {
+            flags.push(Flag::Debug);
+        }
+
+        for include_path in &settings.include_paths {
+            flags.push(Flag::Include(include_path));
+        }
+
+        flags.push(Flag::Include(&self.include_path));
+
+        for define in self.profile.defines.iter().chain(&settings.defines) {
+            flags.push(Flag::Define(define));
+        }
+
+        let mut arguments = vec![];
+
+        for flag in &flags {
+            arguments.extend(self.renderer.render(flag)?);
+        }
+
+        arguments.extend(self.toolchain.compiler_flags.iter().cloned());
+        arguments.extend(settings.compiler_flags.iter().cloned());
+
+        if let Some(sysroot) = &self.toolchain.sysroot {
+            arguments.extend(self.renderer.render(&Flag::Sysroot(sysroot))?);
+        }
+
+        if settings.pic {
+            arguments.extend(self.renderer.render(&Flag::PositionIndependent)?);
+        }
+
+        Ok(arguments)
+    }
 }
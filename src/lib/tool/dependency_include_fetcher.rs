@@ -1,7 +1,8 @@
 use crate::tool::dependency_source_fetcher::Artifact;
-use anyhow::Result;
+use crate::tool::files_fetcher::{fetch_files, SourceLayout};
+use anyhow::{Context, Result};
 use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
 
 /**
     Converts a dependency tree into a flat dependency list.
@@ -17,45 +18,54 @@ impl<'a> DependencyIncludeFetcher<'a> {
         }
     }
 
-    /* Fetch dependencies header files */
-    pub fn fetch(&self, include_dir: &PathBuf) -> Result<()> {
+    /**
+        Fetch dependencies header files: the headers under the source roots and include directories of every dependency
+        are copied to <include_dir>/<dependency>/, keeping their paths relative to the root.
+     */
+    pub fn fetch(&self, include_dir: &Path) -> Result<()> {
         for artifact in self.artifacts {
-            copy_headers(&artifact.path.join("src"), &include_dir.join(&artifact.dependency.name))?;
+            let layout = SourceLayout::new(&artifact.path, &artifact.config)
+                .with_context(|| format!("Invalid sources in build.yaml of dependency {}", artifact.dependency.name))?;
+
+            let header_roots = layout
+                .roots()
+                .into_iter()
+                .chain(artifact.config.includes.iter().map(|include| artifact.path.join(include)));
+
+            for header_root in header_roots {
+                for header_file in layout.files_under(&header_root, "h")? {
+                    let relative_path = header_file.strip_prefix(&header_root)?;
+
+                    copy_header(&header_file, &include_dir.join(&artifact.dependency.name).join(relative_path))?;
+                }
+            }
         }
 
         Ok(())
     }
 }
 
+/** Copies the headers under a directory, e.g. generated ones, keeping their paths relative to it */
+pub fn copy_headers(directory: &Path, destination: &Path) -> Result<()> {
+    for header_file in fetch_files(&directory.to_path_buf(), "h")? {
+        copy_header(&header_file, &destination.join(header_file.strip_prefix(directory)?))?;
+    }
 
-fn copy_headers(src_path: &PathBuf, dst_path: &PathBuf) -> Result<()> {
-    let source_dir = fs::read_dir(src_path)?;
-
-    for source_file in source_dir {
-        let source_file = source_file?;
-
-        if source_file.path().is_dir() {
-            copy_headers(
-                &source_file.path(),
-                &dst_path.join(source_file.file_name())
-            )?;
-        } else {
-            fs::create_dir_all(dst_path)?;
+    Ok(())
+}
 
-            let is_header_file = source_file
-                .path()
-                .extension()
-                .map(|extension| extension == "h")
-                .unwrap_or(false);
+/* an unchanged header keeps its modification time, so objects including it stay up to date */
+fn copy_header(source_file: &Path, destination_file: &Path) -> Result<()> {
+    if fs::read(destination_file).ok() == Some(fs::read(source_file)?) {
+        return Ok(());
+    }
 
-            if is_header_file {
-                fs::copy(
-                    source_file.path(),
-                    dst_path.join(source_file.file_name())
-                )?;
-            }
-        }
+    if let Some(parent) = destination_file.parent() {
+        fs::create_dir_all(parent)?;
     }
 
+    fs::copy(source_file, destination_file)
+        .with_context(|| format!("Failed to copy header {:?}", source_file))?;
+
     Ok(())
 }
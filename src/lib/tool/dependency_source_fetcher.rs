@@ -2,95 +2,331 @@ mod fs_registry;
 mod git_registry;
 
 use crate::config::{Config, Dependency, Registry};
+use crate::reporter::{Event, Reporter};
+use crate::tool::cfg::Cfg;
+use crate::tool::feature_resolver::FeatureSet;
+use crate::tool::lockfile::{LockedDependency, LockedSource, Lockfile};
 use anyhow::{Context, Result};
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 
+#[derive(Clone)]
 pub struct Artifact {
     pub path: PathBuf,
     pub dependency: Dependency,
+    pub config: Config,
+    pub source: LockedSource,
+}
+
+/**
+    Which dependencies of a build.yaml are fetched: those of conditionals matching the cfg,
+    and optional ones only when a feature enables them.
+ */
+#[derive(Clone, Copy)]
+pub struct DependencySelection<'a> {
+    pub cfg: &'a Cfg,
+    pub features: &'a FeatureSet,
+}
+
+/* where a dependency comes from, every dependency with the same name has to come from the same place */
+#[derive(Debug, Clone, PartialEq)]
+enum Origin {
+    Workspace(PathBuf),
+    FileSystem(PathBuf),
+    Git { url: String, branch: String },
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Workspace(path) => write!(f, "workspace member {:?}", path),
+            Origin::FileSystem(path) => write!(f, "{:?}", path),
+            Origin::Git { url, branch } => write!(f, "git repository {} (branch {})", url, branch),
+        }
+    }
+}
+
+/**
+    The dependencies fetched so far, shared by the fetchers of a project and its dependencies.
+    Fetching again after features enabled more optional dependencies only fetches those.
+ */
+#[derive(Default)]
+pub struct FetchedDependencies {
+    artifacts: RefCell<HashMap<String, (Origin, Artifact)>>,
 }
 
 /**
     Converts a dependency tree into a flat dependency list.
  */
-pub struct DependencySourceFetcher {
-    registries: HashMap<String, Registry>,
-    dependencies: Vec<Dependency>,
+pub struct DependencySourceFetcher<'a> {
+    /* relative file system registries are resolved against this directory */
+    base_path: PathBuf,
+    registries: &'a HashMap<String, Registry>,
+    dependencies: &'a [Dependency],
+    workspace_members: &'a HashMap<String, PathBuf>,
+    lockfile: &'a Lockfile,
+    reporter: &'a dyn Reporter,
+    timeout: Option<Duration>,
+    fetched: &'a FetchedDependencies,
 }
 
-impl DependencySourceFetcher {
-    pub fn new(registries: HashMap<String, Registry>, dependencies: Vec<Dependency>,) -> Self {
+impl<'a> DependencySourceFetcher<'a> {
+    pub fn new(
+        base_path: PathBuf,
+        config: &'a Config,
+        workspace_members: &'a HashMap<String, PathBuf>,
+        lockfile: &'a Lockfile,
+        reporter: &'a dyn Reporter,
+        timeout: Option<Duration>,
+        fetched: &'a FetchedDependencies,
+    ) -> Self {
         Self {
-            registries,
-            dependencies,
+            base_path,
+            registries: &config.registries,
+            dependencies: &config.dependencies,
+            workspace_members,
+            lockfile,
+            reporter,
+            timeout,
+            fetched,
         }
     }
 
     /* Fetch dependencies recursively and return a flat vector */
-    pub fn fetch(&self, sources_dir: &PathBuf) -> Result<Vec<Artifact>> {
+    pub fn fetch(&self, sources_dir: &Path, selection: DependencySelection) -> Result<Vec<Artifact>> {
         let mut artifacts = vec![];
 
-        for dependency in &self.dependencies {
-            let artifact_path = sources_dir.join(&dependency.name);
+        for dependency in self.dependencies {
+            if dependency.optional && !selection.features.is_dependency_enabled(&dependency.name) {
+                continue;
+            }
 
-            let registry = self.registries.get(&dependency.registry_name)
-                .ok_or_else(|| anyhow::anyhow!("Registry '{}' not found", &dependency.registry_name))?;
+            let origin = self.origin(dependency)?;
+            let fetched = self.fetched.artifacts.borrow().get(&dependency.name).cloned();
 
-            let dependency_fetched = fs::exists(&artifact_path)
-                .with_context(|| format!("Failed to check if dependency '{}' is fetched", dependency.name))?;
+            let artifact = match fetched {
+                Some((fetched_origin, _)) if fetched_origin != origin => {
+                    anyhow::bail!(
+                        "Dependency '{}' is required from both {} and {}",
+                        dependency.name, fetched_origin, origin
+                    );
+                }
+                Some((_, artifact)) => artifact,
+                None => {
+                    let artifact = self.fetch_artifact(dependency, sources_dir, selection.cfg)?;
 
-            if dependency_fetched {
-                log::info!("Skipping already fetched dependency '{}'", dependency.name);
-            } else {
-                fetch_dependency(registry, dependency, &artifact_path)?;
-            }
+                    self.fetched.artifacts
+                        .borrow_mut()
+                        .insert(dependency.name.clone(), (origin, artifact.clone()));
 
-            artifacts.push(
-                Artifact {
-                    path: sources_dir.join(&dependency.name),
-                    dependency: dependency.clone()
+                    artifact
                 }
-            );
-
-            let config_path = sources_dir.join(&dependency.name).join("build.yaml");
-            let config_str = fs::read_to_string(config_path)
-                .with_context(|| format!("Failed to read build.yaml for dependency {}", dependency.name))?;
+            };
 
-            let config: Config = serde_yaml::from_str(&config_str)
-                .with_context(|| format!("Failed to parse build.yaml for dependency {}", dependency.name))?;
+            /* nested file system registries are relative to where the dependency originally lives */
+            let base_path = match &artifact.source {
+                LockedSource::Workspace(path) | LockedSource::FileSystem(path) => PathBuf::from(path),
+                LockedSource::Git { .. } => artifact.path.clone(),
+            };
 
-            let children_artifacts = DependencySourceFetcher::new(config.registries, config.dependencies)
-                .fetch(sources_dir)
+            /* the children are selected again, features may have enabled more optional ones since the last fetch */
+            let children_artifacts = DependencySourceFetcher::new(
+                base_path,
+                &artifact.config,
+                self.workspace_members,
+                self.lockfile,
+                self.reporter,
+                self.timeout,
+                self.fetched,
+            )
+                .fetch(sources_dir, selection)
                 .with_context(|| format!("Failed to fetch dependencies for dependency {}", dependency.name))?;
 
+            artifacts.push(artifact);
             artifacts.extend(children_artifacts);
         }
 
+        /* a dependency several projects depend on is built once */
+        let mut names = HashSet::new();
+        artifacts.retain(|artifact| names.insert(artifact.dependency.name.clone()));
+
         Ok(artifacts)
     }
-}
 
-fn fetch_dependency(registry: &Registry, dependency: &Dependency, artifact_path: &PathBuf) -> Result<()> {
-    match registry {
-        Registry::Git { url, branch } => {
-            log::info!("Fetching dependency '{}' from 'git' repository {}", dependency.name, url);
-            git_registry::fetch_git_dependency(
-                url,
-                branch,
-                dependency,
-                artifact_path
-            ).with_context(|| format!("Failed to fetch dependency '{}' from 'git' repository {}", dependency.name, url))
-        },
-        Registry::FileSystem(repository_path) => {
-            log::info!("Fetching dependency '{}' from 'fs' repository {:?}", dependency.name, repository_path);
-            fs_registry::fetch_fs_dependency(
-                repository_path.as_ref(),
-                dependency,
-                artifact_path
-            ).with_context(|| format!("Failed to fetch dependency '{}' from 'fs' repository {}", dependency.name, repository_path))
+    fn fetch_artifact(&self, dependency: &Dependency, sources_dir: &Path, cfg: &Cfg) -> Result<Artifact> {
+        let (artifact_path, source) = match self.workspace_members.get(&dependency.name) {
+            Some(member_path) => {
+                log::info!("Using workspace member '{}' from {:?}", dependency.name, member_path);
+
+                (member_path.clone(), LockedSource::Workspace(member_path.display().to_string()))
+            }
+            None => {
+                let artifact_path = sources_dir.join(&dependency.name);
+                let registry = self.registry(dependency)?;
+
+                let dependency_fetched = fs::exists(&artifact_path)
+                    .with_context(|| format!("Failed to check if dependency '{}' is fetched", dependency.name))?;
+
+                if !dependency_fetched {
+                    self.reporter.report(Event::DependencyFetchStarted {
+                        name: dependency.name.clone(),
+                        registry: dependency.registry_name.clone(),
+                    });
+
+                    self.fetch_dependency(registry, dependency, &artifact_path)?;
+                }
+
+                self.checkout_locked_commit(registry, dependency, &artifact_path)?;
+
+                self.reporter.report(Event::DependencyFetchFinished {
+                    name: dependency.name.clone(),
+                    path: artifact_path.clone(),
+                    fresh: dependency_fetched,
+                });
+
+                let source = self.locked_source(registry, dependency, &artifact_path)?;
+
+                (artifact_path, source)
+            }
+        };
+
+        let config_path = artifact_path.join("build.yaml");
+        let config_str = fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read build.yaml for dependency {}", dependency.name))?;
+
+        let mut config: Config = serde_yaml::from_str(&config_str)
+            .with_context(|| format!("Failed to parse build.yaml for dependency {}", dependency.name))?;
+
+        /* conditionals of every dependency are applied, they may add dependencies */
+        config.apply_conditionals(cfg)
+            .with_context(|| format!("Failed to apply conditionals of dependency {}", dependency.name))?;
+
+        Ok(Artifact {
+            path: artifact_path,
+            dependency: dependency.clone(),
+            config,
+            source,
+        })
+    }
+
+    fn registry(&self, dependency: &Dependency) -> Result<&'a Registry> {
+        self.registries.get(&dependency.registry_name)
+            .ok_or_else(|| anyhow::anyhow!("Registry '{}' not found", &dependency.registry_name))
+    }
+
+    fn origin(&self, dependency: &Dependency) -> Result<Origin> {
+        if let Some(member_path) = self.workspace_members.get(&dependency.name) {
+            return Ok(Origin::Workspace(member_path.clone()));
+        }
+
+        match self.registry(dependency)? {
+            Registry::Git { url, branch } => Ok(Origin::Git {
+                url: url.clone(),
+                branch: branch.clone(),
+            }),
+            Registry::FileSystem(repository_path) => {
+                let dependency_path = self.resolve_repository_path(repository_path).join(&dependency.name);
+
+                /* "../registry" seen from different projects is the same directory */
+                Ok(Origin::FileSystem(fs::canonicalize(&dependency_path).unwrap_or(dependency_path)))
+            }
+        }
+    }
+
+    fn resolve_repository_path(&self, repository_path: &str) -> PathBuf {
+        let repository_path = Path::new(repository_path);
+
+        if repository_path.is_absolute() {
+            repository_path.to_path_buf()
+        } else {
+            self.base_path.join(repository_path)
+        }
+    }
+
+    fn fetch_dependency(&self, registry: &Registry, dependency: &Dependency, artifact_path: &Path) -> Result<()> {
+        match registry {
+            Registry::Git { url, branch } => {
+                log::debug!("Fetching dependency '{}' from 'git' repository {}", dependency.name, url);
+                git_registry::fetch_git_dependency(
+                    url,
+                    branch,
+                    dependency,
+                    artifact_path,
+                    self.timeout,
+                    &mut |stream, line| {
+                        self.reporter.report(Event::Output {
+                            job: dependency.name.clone(),
+                            stream,
+                            line: line.to_string(),
+                        });
+                    },
+                ).with_context(|| format!("Failed to fetch dependency '{}' from 'git' repository {}", dependency.name, url))
+            },
+            Registry::FileSystem(repository_path) => {
+                log::debug!("Fetching dependency '{}' from 'fs' repository {:?}", dependency.name, repository_path);
+                fs_registry::fetch_fs_dependency(
+                    &self.resolve_repository_path(repository_path),
+                    dependency,
+                    artifact_path
+                ).with_context(|| format!("Failed to fetch dependency '{}' from 'fs' repository {}", dependency.name, repository_path))
+            }
+        }
+    }
+
+    /* an existing checkout is moved too, the lockfile may have changed since it was cloned */
+    fn checkout_locked_commit(&self, registry: &Registry, dependency: &Dependency, artifact_path: &Path) -> Result<()> {
+        let Registry::Git { url, branch } = registry else {
+            return Ok(());
+        };
+
+        let Some(commit) = self.lockfile.locked_commit(&dependency.name, url, branch) else {
+            return Ok(());
+        };
+
+        let head = git_registry::head_commit(artifact_path)
+            .with_context(|| format!("Failed to read the commit of dependency '{}'", dependency.name))?;
+
+        if head == commit {
+            return Ok(());
         }
+
+        log::info!("Checking out locked commit {} of dependency '{}'", commit, dependency.name);
+
+        git_registry::checkout_commit(artifact_path, commit)
+            .with_context(|| format!("Failed to check out locked commit {} of dependency '{}'", commit, dependency.name))
+    }
+
+    fn locked_source(&self, registry: &Registry, dependency: &Dependency, artifact_path: &Path) -> Result<LockedSource> {
+        match registry {
+            Registry::Git { url, branch } => {
+                let commit = git_registry::head_commit(artifact_path)
+                    .with_context(|| format!("Failed to read the commit of dependency '{}'", dependency.name))?;
+
+                Ok(LockedSource::Git {
+                    url: url.clone(),
+                    branch: branch.clone(),
+                    commit,
+                })
+            }
+            Registry::FileSystem(repository_path) => {
+                let dependency_path = self.resolve_repository_path(repository_path).join(&dependency.name);
+
+                Ok(LockedSource::FileSystem(dependency_path.display().to_string()))
+            }
+        }
+    }
+}
+
+pub fn lock(artifacts: &[Artifact], lockfile: &mut Lockfile) {
+    for artifact in artifacts {
+        lockfile.insert(LockedDependency {
+            name: artifact.dependency.name.clone(),
+            source: artifact.source.clone(),
+        });
     }
 }
@@ -1,19 +1,76 @@
 use crate::config::Dependency;
-use crate::std_command_ext::ExecuteCommand;
+use crate::std_command_ext::{CliStr, ExecuteCommand, OutputStream};
 use anyhow::Result;
 use std::path::Path;
 use std::process::Command;
+use std::time::Duration;
 
-pub fn fetch_git_dependency(url: &str, branch: &str, dependency: &Dependency, artifact_path: &Path) -> Result<()> {
+/**
+    @param: on_line - receives the output of git while it runs
+ */
+pub fn fetch_git_dependency(
+    url: &str,
+    branch: &str,
+    dependency: &Dependency,
+    artifact_path: &Path,
+    timeout: Option<Duration>,
+    on_line: &mut dyn FnMut(OutputStream, &str),
+) -> Result<()> {
     let mut command = Command::new("git");
     command.arg("clone");
+    /* git only reports progress to a terminal unless asked */
+    command.arg("--progress");
     command.arg(format!("{}/{}", url, dependency.name));
     command.arg("--single-branch");
     command.arg("--branch");
     command.arg(branch);
     command.arg(artifact_path);
 
-    command.execute(true)?;
+    log::trace!("{}", command.cli_str());
+
+    let output = command.stream(timeout, on_line)?;
+    if !output.success {
+        anyhow::bail!("git clone {}", output.status);
+    }
+
+    Ok(())
+}
+
+/** Moves a checkout to the locked commit, fetching it first when the checkout doesn't have it yet */
+pub fn checkout_commit(artifact_path: &Path, commit: &str) -> Result<()> {
+    let commit_exists = Command::new("git")
+        .arg("cat-file")
+        .arg("-e")
+        .arg(format!("{}^{{commit}}", commit))
+        .current_dir(artifact_path)
+        .execute(false)
+        .is_ok();
+
+    if !commit_exists {
+        Command::new("git")
+            .arg("fetch")
+            .arg("--quiet")
+            .arg("origin")
+            .current_dir(artifact_path)
+            .execute(true)?;
+    }
+
+    Command::new("git")
+        .arg("checkout")
+        .arg("--quiet")
+        .arg(commit)
+        .current_dir(artifact_path)
+        .execute(true)?;
 
     Ok(())
 }
+
+pub fn head_commit(artifact_path: &Path) -> Result<String> {
+    let stdout = Command::new("git")
+        .arg("rev-parse")
+        .arg("HEAD")
+        .current_dir(artifact_path)
+        .execute(true)?;
+
+    Ok(stdout.trim().to_string())
+}
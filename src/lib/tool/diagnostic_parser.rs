@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/**
+    One compiler message, e.g. `src/main.c:4:9: warning: unused variable 'x' [-Wunused-variable]`.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+    /// The warning option that enabled the diagnostic, e.g. `-Wunused-variable`.
+    pub option: Option<String>,
+    /// Notes that follow the diagnostic and explain it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<Diagnostic>,
+}
+
+/* checked in this order, so "fatal error" isn't taken for "error" */
+const SEVERITIES: [(&str, Severity); 4] = [
+    ("fatal error", Severity::Error),
+    ("error", Severity::Error),
+    ("warning", Severity::Warning),
+    ("note", Severity::Note),
+];
+
+/**
+    Parses the stderr of a GCC or Clang compatible compiler.
+    Source excerpts, carets and "In function" lines are skipped, notes are attached to the diagnostic before them.
+ */
+pub fn parse(output: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+
+    for line in strip_ansi_escapes(output).lines() {
+        let Some(diagnostic) = parse_line(line) else {
+            continue;
+        };
+
+        match (diagnostic.severity, diagnostics.last_mut()) {
+            (Severity::Note, Some(previous)) => previous.notes.push(diagnostic),
+            _ => diagnostics.push(diagnostic),
+        }
+    }
+
+    diagnostics
+}
+
+/** Keeps the first occurrence of every diagnostic, e.g. of a warning in a header included by several sources */
+pub fn deduplicate(diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    let mut unique: Vec<Diagnostic> = vec![];
+
+    for diagnostic in diagnostics {
+        if !unique.contains(&diagnostic) {
+            unique.push(diagnostic);
+        }
+    }
+
+    unique
+}
+
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    let (index, keyword, severity) = SEVERITIES
+        .iter()
+        .filter_map(|(keyword, severity)| {
+            line.find(&format!(": {}: ", keyword)).map(|index| (index, *keyword, *severity))
+        })
+        .min_by_key(|(index, _, _)| *index)?;
+
+    let location = &line[..index];
+    let message = &line[index + keyword.len() + 4..];
+
+    /* "file:line:column", "file:line" or "file", where the file may contain ':' itself */
+    let mut parts = location.rsplitn(3, ':');
+    let last = parts.next()?;
+    let middle = parts.next();
+    let first = parts.next();
+
+    let (file, line, column) = match (first, middle.map(str::parse::<u32>), last.parse::<u32>()) {
+        (Some(file), Some(Ok(line)), Ok(column)) => (file.to_string(), Some(line), Some(column)),
+        (_, _, Ok(line)) => (location[..location.len() - last.len() - 1].to_string(), Some(line), None),
+        _ => (location.to_string(), None, None),
+    };
+
+    if file.is_empty() || (file.contains(' ') && line.is_none()) {
+        return None;
+    }
+
+    let (message, option) = split_option(message);
+
+    Some(Diagnostic {
+        file: PathBuf::from(file),
+        line,
+        column,
+        severity,
+        message,
+        option,
+        notes: vec![],
+    })
+}
+
+/* "unused variable 'x' [-Wunused-variable]" -> ("unused variable 'x'", "-Wunused-variable") */
+fn split_option(message: &str) -> (String, Option<String>) {
+    let message = message.trim_end();
+
+    if message.ends_with(']') {
+        if let Some(start) = message.rfind(" [-W") {
+            let option = &message[start + 2..message.len() - 1];
+            let option = option.split(',').next().unwrap_or(option);
+
+            return (message[..start].to_string(), Some(option.to_string()));
+        }
+    }
+
+    (message.to_string(), None)
+}
+
+fn strip_ansi_escapes(output: &str) -> String {
+    let mut stripped = String::with_capacity(output.len());
+    let mut chars = output.chars();
+
+    while let Some(char) = chars.next() {
+        if char != '\u{1b}' {
+            stripped.push(char);
+            continue;
+        }
+
+        /* CSI sequences end with a letter, e.g. ESC[01;35m */
+        for char in chars.by_ref() {
+            if char.is_ascii_alphabetic() {
+                break;
+            }
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_location_severity_and_option() {
+        let diagnostics = parse("src/main.c:4:9: warning: unused variable 'x' [-Wunused-variable]\n");
+
+        assert_eq!(diagnostics, [Diagnostic {
+            file: PathBuf::from("src/main.c"),
+            line: Some(4),
+            column: Some(9),
+            severity: Severity::Warning,
+            message: "unused variable 'x'".to_string(),
+            option: Some("-Wunused-variable".to_string()),
+            notes: vec![],
+        }]);
+    }
+
+    #[test]
+    fn fatal_error_is_an_error() {
+        let diagnostics = parse("src/main.c:1:10: fatal error: missing.h: No such file or directory\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].message, "missing.h: No such file or directory");
+    }
+
+    #[test]
+    fn location_without_column_or_line() {
+        let diagnostics = parse("src/util.h:12: error: expected ';'\nld.lld: error: undefined symbol: foo\n");
+
+        assert_eq!(diagnostics[0].file, PathBuf::from("src/util.h"));
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (Some(12), None));
+        assert_eq!(diagnostics[1].file, PathBuf::from("ld.lld"));
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (None, None));
+    }
+
+    #[test]
+    fn notes_are_attached_and_excerpts_skipped() {
+        let output = "\
+src/main.c: In function 'main':
+src/main.c:5:5: error: implicit declaration of function 'foo' [-Wimplicit-function-declaration]
+    5 |     foo();
+      |     ^~~
+src/main.c:2:1: note: include 'foo.h' or provide a declaration of 'foo'
+";
+
+        let diagnostics = parse(output);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].option.as_deref(), Some("-Wimplicit-function-declaration"));
+        assert_eq!(diagnostics[0].notes.len(), 1);
+        assert_eq!(diagnostics[0].notes[0].line, Some(2));
+        assert_eq!(diagnostics[0].notes[0].severity, Severity::Note);
+    }
+
+    #[test]
+    fn strips_ansi_colors() {
+        let output = "\u{1b}[01m\u{1b}[Ksrc/main.c:4:9:\u{1b}[m\u{1b}[K \u{1b}[01;35m\u{1b}[Kwarning: \u{1b}[m\u{1b}[Kunused variable 'x' [\u{1b}[01;35m\u{1b}[K-Wunused-variable\u{1b}[m\u{1b}[K]\n";
+
+        let diagnostics = parse(output);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file, PathBuf::from("src/main.c"));
+        assert_eq!(diagnostics[0].option.as_deref(), Some("-Wunused-variable"));
+    }
+
+    #[test]
+    fn option_lists_keep_the_first_option() {
+        let diagnostics = parse("a.c:1:1: warning: unused [-Wunused-variable,-Werror]\n");
+
+        assert_eq!(diagnostics[0].option.as_deref(), Some("-Wunused-variable"));
+    }
+
+    #[test]
+    fn deduplicate_keeps_first_occurrence() {
+        let warning = "inc/util.h:3:5: warning: unused function 'f' [-Wunused-function]\n";
+        let error = "src/a.c:1:1: error: expected ';'\n";
+
+        let diagnostics = parse(&format!("{}{}{}", warning, error, warning));
+        let unique = deduplicate(diagnostics);
+
+        assert_eq!(unique.len(), 2);
+        assert_eq!(unique[0].severity, Severity::Warning);
+        assert_eq!(unique[1].severity, Severity::Error);
+    }
+}
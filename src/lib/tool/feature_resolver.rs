@@ -0,0 +1,370 @@
+use crate::cli::BuildArgs;
+use crate::config::{Config, Dependency};
+use crate::tool::dependency_source_fetcher::Artifact;
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+const DEFAULT_FEATURE: &str = "default";
+
+/** The features the build enables on the project */
+#[derive(Debug, Clone, Default)]
+pub struct FeatureRequest {
+    /// `name` of the project or `dependency/name` of one of its dependencies.
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+}
+
+impl FeatureRequest {
+    pub fn new(build_args: &BuildArgs) -> Self {
+        Self {
+            features: build_args.features.clone(),
+            all_features: build_args.all_features,
+            no_default_features: build_args.no_default_features,
+        }
+    }
+}
+
+/**
+    The features enabled on the project and its dependencies, unified across the dependency graph:
+    a dependency requested by several projects builds once, with every feature any of them enables.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeatureSet {
+    /// Enabled features by project or dependency name.
+    enabled: BTreeMap<String, BTreeSet<String>>,
+    /// Optional dependencies a feature enables.
+    dependencies: BTreeSet<String>,
+}
+
+impl FeatureSet {
+    pub fn features(&self, name: &str) -> Vec<String> {
+        self.enabled
+            .get(name)
+            .map(|features| features.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /** Whether an optional dependency is fetched and built */
+    pub fn is_dependency_enabled(&self, name: &str) -> bool {
+        self.dependencies.contains(name)
+    }
+
+    /** Whether resolving again enabled optional dependencies that aren't fetched yet */
+    pub fn enables_more_dependencies_than(&self, other: &FeatureSet) -> bool {
+        !self.dependencies.is_subset(&other.dependencies)
+    }
+}
+
+enum Work {
+    Activate(String),
+    Enable { project: String, feature: String },
+}
+
+/**
+    Resolves the features of the project and of the fetched dependencies.
+    Dependencies that aren't fetched yet, e.g. an optional dependency this resolution enables,
+    get their requested features recorded but not expanded; resolving again after fetching them expands them.
+ */
+pub struct FeatureResolver<'a> {
+    project_name: &'a str,
+    configs: HashMap<&'a str, &'a Config>,
+    enabled: BTreeMap<String, BTreeSet<String>>,
+    active: BTreeSet<String>,
+    dependencies: BTreeSet<String>,
+    work: VecDeque<Work>,
+}
+
+impl<'a> FeatureResolver<'a> {
+    pub fn new(project_name: &'a str, config: &'a Config, artifacts: &'a [Artifact]) -> Self {
+        let mut configs = HashMap::from([(project_name, config)]);
+
+        for artifact in artifacts {
+            configs.entry(artifact.dependency.name.as_str()).or_insert(&artifact.config);
+        }
+
+        Self {
+            project_name,
+            configs,
+            enabled: BTreeMap::new(),
+            active: BTreeSet::new(),
+            dependencies: BTreeSet::new(),
+            work: VecDeque::new(),
+        }
+    }
+
+    pub fn resolve(mut self, request: &FeatureRequest) -> Result<FeatureSet> {
+        let project_name = self.project_name;
+        let config = self.configs[project_name];
+
+        self.work.push_back(Work::Activate(project_name.to_string()));
+
+        let requested: Vec<String> = if request.all_features {
+            config.features.keys().cloned().collect()
+        } else {
+            let mut requested = request.features.clone();
+
+            if !request.no_default_features && config.features.contains_key(DEFAULT_FEATURE) {
+                requested.push(DEFAULT_FEATURE.to_string());
+            }
+
+            requested
+        };
+
+        for feature in requested {
+            self.request(project_name, &feature)?;
+        }
+
+        while let Some(work) = self.work.pop_front() {
+            match work {
+                Work::Activate(project) => self.activate(project),
+                Work::Enable { project, feature } => self.enable(project, feature)?,
+            }
+        }
+
+        Ok(FeatureSet {
+            enabled: self.enabled,
+            dependencies: self.dependencies,
+        })
+    }
+
+    /* a `name` of the project or a `dependency/name` of one of its dependencies */
+    fn request(&mut self, project: &str, feature: &str) -> Result<()> {
+        match feature.split_once('/') {
+            Some((dependency_name, feature)) => {
+                let dependency = self.dependency(project, dependency_name)?;
+                self.activate_dependency(&dependency);
+
+                self.work.push_back(Work::Enable {
+                    project: dependency.name,
+                    feature: feature.to_string(),
+                });
+            }
+            None => {
+                self.work.push_back(Work::Enable {
+                    project: project.to_string(),
+                    feature: feature.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn dependency(&self, project: &str, dependency_name: &str) -> Result<Dependency> {
+        self.configs
+            .get(project)
+            .and_then(|config| config.dependencies.iter().find(|dependency| dependency.name == dependency_name))
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("'{}' has no dependency '{}'", project, dependency_name))
+    }
+
+    /* a project that is built enables the features its required dependencies ask for */
+    fn activate(&mut self, project: String) {
+        if !self.active.insert(project.clone()) {
+            return;
+        }
+
+        let Some(config) = self.configs.get(project.as_str()) else {
+            return;
+        };
+
+        for dependency in config.dependencies.clone() {
+            if !dependency.optional {
+                self.activate_dependency(&dependency);
+            }
+        }
+    }
+
+    fn activate_dependency(&mut self, dependency: &Dependency) {
+        if dependency.optional {
+            self.dependencies.insert(dependency.name.clone());
+        }
+
+        self.work.push_back(Work::Activate(dependency.name.clone()));
+
+        let default = dependency.default_features.then(|| DEFAULT_FEATURE.to_string());
+
+        for feature in dependency.features.iter().cloned().chain(default) {
+            self.work.push_back(Work::Enable {
+                project: dependency.name.clone(),
+                feature,
+            });
+        }
+    }
+
+    fn enable(&mut self, project: String, feature: String) -> Result<()> {
+        let Some(config) = self.configs.get(project.as_str()).copied() else {
+            self.enabled.entry(project).or_default().insert(feature);
+            return Ok(());
+        };
+
+        let Some(declared) = config.features.get(&feature) else {
+            /* a project without features has no default feature to turn off */
+            if feature == DEFAULT_FEATURE {
+                return Ok(());
+            }
+
+            anyhow::bail!("Feature '{}' is not declared by '{}'", feature, project);
+        };
+
+        if !self.enabled.entry(project.clone()).or_default().insert(feature) {
+            return Ok(());
+        }
+
+        for dependency_name in &declared.dependencies {
+            let dependency = self.dependency(&project, dependency_name)?;
+            self.activate_dependency(&dependency);
+        }
+
+        for enabled_feature in &declared.features {
+            self.request(&project, enabled_feature)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool::lockfile::LockedSource;
+    use std::path::PathBuf;
+
+    const APP: &str = r#"
+project:
+  name: app
+  version: 1.0.0
+dependencies:
+  - name: codec
+    registry_name: local
+    default_features: false
+    features: [fast]
+  - name: net
+    registry_name: local
+  - name: mbedtls
+    registry_name: local
+    optional: true
+features:
+  default:
+    features: [tls]
+  tls:
+    dependencies: [mbedtls]
+    features: [mbedtls/small]
+  extra:
+    defines: [EXTRA]
+"#;
+
+    const CODEC: &str = r#"
+project:
+  name: codec
+  version: 1.0.0
+features:
+  default:
+    features: [simd]
+  simd: {}
+  fast: {}
+"#;
+
+    const NET: &str = r#"
+project:
+  name: net
+  version: 1.0.0
+dependencies:
+  - name: codec
+    registry_name: local
+"#;
+
+    fn config(build_yaml: &str) -> Config {
+        serde_yaml::from_str(build_yaml).expect("valid build.yaml")
+    }
+
+    fn artifact(build_yaml: &str) -> Artifact {
+        let config = config(build_yaml);
+
+        Artifact {
+            path: PathBuf::from(&config.project.name),
+            dependency: Dependency {
+                name: config.project.name.clone(),
+                registry_name: "local".to_string(),
+                optional: false,
+                features: vec![],
+                default_features: true,
+            },
+            source: LockedSource::FileSystem(config.project.name.clone()),
+            config,
+        }
+    }
+
+    fn resolve(artifacts: &[Artifact], request: FeatureRequest) -> Result<FeatureSet> {
+        let config = config(APP);
+
+        FeatureResolver::new("app", &config, artifacts).resolve(&request)
+    }
+
+    #[test]
+    fn features_are_unified_across_the_graph() {
+        let artifacts = [artifact(CODEC), artifact(NET)];
+        let features = resolve(&artifacts, FeatureRequest::default()).unwrap();
+
+        assert_eq!(features.features("app"), ["default", "tls"]);
+        /* app turns the default off, but net keeps it on */
+        assert_eq!(features.features("codec"), ["default", "fast", "simd"]);
+        assert!(features.features("net").is_empty());
+    }
+
+    #[test]
+    fn optional_dependency_is_enabled_by_a_feature() {
+        let artifacts = [artifact(CODEC), artifact(NET)];
+        let features = resolve(&artifacts, FeatureRequest::default()).unwrap();
+
+        assert!(features.is_dependency_enabled("mbedtls"));
+        /* mbedtls isn't fetched yet, its features are recorded for the next resolution */
+        assert_eq!(features.features("mbedtls"), ["default", "small"]);
+
+        let without_defaults = resolve(&artifacts, FeatureRequest {
+            no_default_features: true,
+            ..FeatureRequest::default()
+        }).unwrap();
+
+        assert!(!without_defaults.is_dependency_enabled("mbedtls"));
+        assert!(features.enables_more_dependencies_than(&without_defaults));
+        assert!(!without_defaults.enables_more_dependencies_than(&features));
+    }
+
+    #[test]
+    fn all_features_and_requested_features() {
+        let artifacts = [artifact(CODEC), artifact(NET)];
+
+        let all = resolve(&artifacts, FeatureRequest {
+            all_features: true,
+            ..FeatureRequest::default()
+        }).unwrap();
+        assert_eq!(all.features("app"), ["default", "extra", "tls"]);
+
+        let requested = resolve(&artifacts, FeatureRequest {
+            features: vec!["extra".to_string(), "codec/simd".to_string()],
+            no_default_features: true,
+            all_features: false,
+        }).unwrap();
+        assert_eq!(requested.features("app"), ["extra"]);
+        assert!(requested.features("codec").contains(&"simd".to_string()));
+    }
+
+    #[test]
+    fn undeclared_features_and_dependencies_are_errors() {
+        let artifacts = [artifact(CODEC), artifact(NET)];
+
+        let undeclared = resolve(&artifacts, FeatureRequest {
+            features: vec!["codec/gpu".to_string()],
+            ..FeatureRequest::default()
+        });
+        assert_eq!(undeclared.unwrap_err().to_string(), "Feature 'gpu' is not declared by 'codec'");
+
+        let unknown_dependency = resolve(&artifacts, FeatureRequest {
+            features: vec!["zlib/fast".to_string()],
+            ..FeatureRequest::default()
+        });
+        assert_eq!(unknown_dependency.unwrap_err().to_string(), "'app' has no dependency 'zlib'");
+    }
+}
@@ -1,5 +1,18 @@
+use crate::config::Config;
+use anyhow::Context;
+use glob::{MatchOptions, Pattern};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/* `*` stays within a directory, `**` crosses directories */
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/* directories corge-rs generates in a project, which never hold its sources */
+const GENERATED_DIRECTORIES: [&str; 2] = ["target", "dependency"];
 
 fn fetch_files_by_extension(path: &PathBuf, extension: &str) -> anyhow::Result<Vec<PathBuf>> {
     let mut c_files = vec![];
@@ -26,6 +39,265 @@ fn fetch_files_by_extension(path: &PathBuf, extension: &str) -> anyhow::Result<V
     Ok(c_files)
 }
 
+/** Files with the extension under the directory, sorted so builds don't depend on read_dir order */
 pub fn fetch_files(path: &PathBuf, extension: &str) -> anyhow::Result<Vec<PathBuf>> {
-    fetch_files_by_extension(&path, extension)
+    let mut files = fetch_files_by_extension(path, extension)?;
+    files.sort();
+
+    Ok(files)
+}
+
+fn is_glob(source: &str) -> bool {
+    source.contains(['*', '?', '['])
+}
+
+/** A glob pattern relative to the project; wildcards in the project path itself match literally */
+pub fn project_pattern(project_path: &Path, pattern: &str) -> String {
+    let escaped_project_path = Pattern::escape(&project_path.display().to_string());
+
+    Path::new(&escaped_project_path).join(pattern).display().to_string()
+}
+
+/**
+    Where the files of a project or dependency are: the `sources` of its build.yaml, directories searched recursively
+    or glob patterns, without the files matching an `exclude` pattern. Both are relative to the project.
+ */
+#[derive(Debug, Clone)]
+pub struct SourceLayout {
+    project_path: PathBuf,
+    sources: Vec<String>,
+    exclude: Vec<Pattern>,
+}
+
+impl SourceLayout {
+    pub fn new(project_path: &Path, config: &Config) -> anyhow::Result<Self> {
+        let exclude = config.exclude
+            .iter()
+            .map(|pattern| Pattern::new(pattern).with_context(|| format!("Invalid exclude pattern '{}'", pattern)))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            project_path: project_path.to_path_buf(),
+            sources: config
+                .source_directories()
+                .into_iter()
+                .map(|source| source.strip_prefix("./").map(str::to_string).unwrap_or(source))
+                .collect(),
+            exclude,
+        })
+    }
+
+    /** Every file with the extension the sources select, sorted and without duplicates */
+    pub fn files(&self, extension: &str) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = vec![];
+
+        for source in &self.sources {
+            if is_glob(source) {
+                let pattern = project_pattern(&self.project_path, source);
+                let paths = glob::glob_with(&pattern, MATCH_OPTIONS)
+                    .with_context(|| format!("Invalid source pattern '{}'", source))?;
+
+                for path in paths {
+                    let path = path?;
+
+                    if path.is_file() && path.extension().is_some_and(|file_extension| file_extension == extension) {
+                        files.push(path);
+                    }
+                }
+            } else {
+                let source_path = self.project_path.join(source);
+
+                files.extend(
+                    fetch_files(&source_path, extension)
+                        .with_context(|| format!("Failed to read source directory {:?}", &source_path))?
+                );
+            }
+        }
+
+        files.retain(|file| self.is_included(file));
+        files.sort();
+        files.dedup();
+
+        Ok(files)
+    }
+
+    /**
+        Files with the extension under a directory of the project, e.g. headers under an include directory,
+        without the excluded ones. A missing directory has no files.
+     */
+    pub fn files_under(&self, directory: &Path, extension: &str) -> anyhow::Result<Vec<PathBuf>> {
+        if !directory.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut files = fetch_files(&directory.to_path_buf(), extension)?;
+        files.retain(|file| self.is_included(file));
+
+        Ok(files)
+    }
+
+    /** The directories the sources start from; for a glob, the directory before its first wildcard */
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.sources
+            .iter()
+            .map(|source| {
+                if !is_glob(source) {
+                    return self.project_path.join(source);
+                }
+
+                let literal_components: Vec<&str> = source
+                    .split('/')
+                    .take_while(|component| !is_glob(component))
+                    .collect();
+
+                self.project_path.join(literal_components.join("/"))
+            })
+            .collect()
+    }
+
+    /* not excluded, and not generated by corge-rs when a source directory is the project itself */
+    fn is_included(&self, file: &Path) -> bool {
+        let Ok(relative_path) = file.strip_prefix(&self.project_path) else {
+            return true;
+        };
+
+        let is_generated = relative_path
+            .components()
+            .next()
+            .is_some_and(|component| GENERATED_DIRECTORIES.iter().any(|generated| component.as_os_str() == *generated));
+
+        /* a pattern matches the file, or a directory it is in */
+        let is_excluded = relative_path
+            .ancestors()
+            .filter(|path| !path.as_os_str().is_empty())
+            .any(|path| self.exclude.iter().any(|pattern| pattern.matches_path_with(path, MATCH_OPTIONS)));
+
+        !is_generated && !is_excluded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT_CONFIG: &str = r#"
+project:
+  name: app
+  version: 1.0.0
+sources:
+  - ./src
+  - platform/linux/*.c
+  - lib/**/*.c
+  - src/*.c
+exclude:
+  - src/experimental
+  - "**/*_scratch.c"
+"#;
+
+    /* a project with the files, created under the temp directory */
+    fn project(name: &str, files: &[&str]) -> PathBuf {
+        let project_path = std::env::temp_dir().join(format!("corge-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&project_path);
+
+        for file in files {
+            let file_path = project_path.join(file);
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(file_path, "").unwrap();
+        }
+
+        project_path
+    }
+
+    fn layout(project_path: &Path) -> SourceLayout {
+        let config: Config = serde_yaml::from_str(LAYOUT_CONFIG).unwrap();
+
+        SourceLayout::new(project_path, &config).unwrap()
+    }
+
+    fn relative(project_path: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| file.strip_prefix(project_path).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sources_are_globbed_excluded_sorted_and_listed_once() {
+        let project_path = project("source-layout", &[
+            "src/z.c",
+            "src/a.c",
+            "src/main.c",
+            "src/net/b.c",
+            "src/net/b_scratch.c",
+            "src/experimental/x.c",
+            "src/notes.txt",
+            "platform/linux/io.c",
+            "platform/linux/nested/deep.c",
+            "platform/windows/io.c",
+            "lib/util.c",
+            "lib/x/y/z.c",
+        ]);
+
+        let files = layout(&project_path).files("c").unwrap();
+
+        assert_eq!(relative(&project_path, files), [
+            "lib/util.c",
+            "lib/x/y/z.c",
+            "platform/linux/io.c",
+            "src/a.c",
+            "src/main.c",
+            "src/net/b.c",
+            "src/z.c",
+        ]);
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn project_as_source_directory_skips_generated_directories() {
+        let project_path = project("source-layout-root", &["main.c", "target/generated.c", "dependency/source/core/core.c"]);
+        let config: Config = serde_yaml::from_str("project:\n  name: app\n  version: 1.0.0\nsources: [.]\n").unwrap();
+
+        let files = SourceLayout::new(&project_path, &config).unwrap().files("c").unwrap();
+        assert_eq!(relative(&project_path, files), ["main.c"]);
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn headers_under_a_directory_are_excluded_too() {
+        let project_path = project("source-layout-headers", &[
+            "src/b.h",
+            "src/a.h",
+            "src/experimental/x.h",
+        ]);
+
+        let headers = layout(&project_path).files_under(&project_path.join("src"), "h").unwrap();
+        assert_eq!(relative(&project_path, headers), ["src/a.h", "src/b.h"]);
+
+        assert!(layout(&project_path).files_under(&project_path.join("include"), "h").unwrap().is_empty());
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn roots_stop_at_the_first_wildcard() {
+        let project_path = Path::new("/work/app");
+
+        assert_eq!(layout(project_path).roots(), [
+            project_path.join("src"),
+            project_path.join("platform/linux"),
+            project_path.join("lib"),
+            project_path.join("src"),
+        ]);
+    }
+
+    #[test]
+    fn project_pattern_matches_the_project_path_literally() {
+        let pattern = project_pattern(Path::new("/work/app[1]"), "src/**/*.c");
+
+        assert_eq!(pattern, "/work/app[[]1[]]/src/**/*.c");
+        assert!(Pattern::new(&pattern).unwrap().matches("/work/app[1]/src/net/socket.c"));
+        assert!(!Pattern::new(&pattern).unwrap().matches("/work/app1/src/main.c"));
+    }
 }
@@ -0,0 +1,330 @@
+use crate::config::{CompilerFamily, OptimizationLevel};
+use crate::tool::toolchain_probe::ToolchainInfo;
+use anyhow::Result;
+use std::path::Path;
+
+/**
+    A compiler, linker or archiver setting independent of any command line dialect.
+ */
+#[derive(Debug)]
+pub enum Flag<'a> {
+    Optimization(&'a OptimizationLevel),
+    Debug,
+    Include(&'a Path),
+    /// `NAME` or `NAME=VALUE`
+    Define(&'a str),
+    PositionIndependent,
+    Sysroot(&'a str),
+    /// Compile the source file without linking.
+    CompileOnly(&'a Path),
+    ObjectOutput(&'a Path),
+    Shared,
+    LinkedOutput(&'a Path),
+    /// Writes the headers a source includes to a make style dependency file, if the compiler can.
+    DependencyFile(&'a Path),
+    /// Links against `name` found in `directory`, and finds it there at runtime.
+    Library { directory: &'a Path, name: &'a str },
+    /// Links with the script instead of the linker's default one.
+    LinkerScript(&'a Path),
+    /// Writes the map of the linked artifact.
+    MapFile(&'a Path),
+}
+
+/**
+    Turns flags into the arguments of one compiler family.
+ */
+pub trait FlagRenderer {
+    fn render(&self, flag: &Flag) -> Result<Vec<String>>;
+
+    /** Arguments for the archiver to create a static library from object files */
+    fn archive(&self, output_file: &Path, object_files: &[&Path]) -> Vec<String>;
+}
+
+/** The toolchain's declared family wins over the detected one */
+pub fn flag_renderer(declared: Option<CompilerFamily>, toolchain_info: &ToolchainInfo) -> Box<dyn FlagRenderer + '_> {
+    let family = declared.unwrap_or(toolchain_info.family);
+
+    match family {
+        CompilerFamily::ClangCl => Box::new(MsvcRenderer),
+        CompilerFamily::Sdcc => Box::new(SdccRenderer),
+        CompilerFamily::Unknown => {
+            log::warn!("Unknown compiler family of {:?}, assuming GCC compatible flags", &toolchain_info.compiler_path);
+            Box::new(GccRenderer { family, toolchain_info })
+        }
+        CompilerFamily::Gcc |
+        CompilerFamily::Clang |
+        CompilerFamily::Tcc |
+        CompilerFamily::Icx |
+        CompilerFamily::Xc16 => Box::new(GccRenderer { family, toolchain_info }),
+    }
+}
+
+fn path(path: &Path) -> String {
+    path.display().to_string()
+}
+
+fn unsupported(family: CompilerFamily, flag: &Flag) -> anyhow::Error {
+    anyhow::anyhow!("{:?} compilers can't express {:?}", family, flag)
+}
+
+/** gcc, clang, tcc, icx and GCC derivatives */
+struct GccRenderer<'a> {
+    family: CompilerFamily,
+    toolchain_info: &'a ToolchainInfo,
+}
+
+impl GccRenderer<'_> {
+    fn optimization(&self, level: &OptimizationLevel) -> Result<Vec<String>> {
+        let flag = match level {
+            OptimizationLevel::None => return Ok(vec![]),
+            OptimizationLevel::O => "-O",
+            OptimizationLevel::O1 => "-O1",
+            OptimizationLevel::O2 => "-O2",
+            OptimizationLevel::O3 => "-O3",
+            OptimizationLevel::O0 => "-O0",
+            OptimizationLevel::Os => "-Os",
+            OptimizationLevel::Ofast => "-Ofast",
+            OptimizationLevel::Og => "-Og",
+            OptimizationLevel::Oz => "-Oz",
+        };
+
+        /* newer levels were probed, see ToolchainProbe */
+        let probed = matches!(level, OptimizationLevel::Ofast | OptimizationLevel::Og | OptimizationLevel::Oz);
+
+        if probed && !self.toolchain_info.supports(flag) {
+            anyhow::bail!(
+                "{:?} compiler {:?} version {} doesn't support {}",
+                self.family,
+                &self.toolchain_info.compiler_path,
+                self.toolchain_info.version.as_deref().unwrap_or("unknown"),
+                flag
+            );
+        }
+
+        Ok(vec![flag.to_string()])
+    }
+}
+
+impl FlagRenderer for GccRenderer<'_> {
+    fn render(&self, flag: &Flag) -> Result<Vec<String>> {
+        let arguments = match flag {
+            Flag::Optimization(level) => return self.optimization(level),
+            Flag::Debug => vec!["-g".to_string()],
+            Flag::Include(include_path) => vec!["-I".to_string(), path(include_path)],
+            Flag::Define(define) => vec![format!("-D{}", define)],
+            Flag::PositionIndependent => vec!["-fPIC".to_string()],
+            Flag::Sysroot(sysroot) => vec![format!("--sysroot={}", sysroot)],
+            Flag::CompileOnly(source_file) => vec!["-c".to_string(), path(source_file)],
+            Flag::ObjectOutput(output_file) |
+            Flag::LinkedOutput(output_file) => vec!["-o".to_string(), path(output_file)],
+            Flag::Shared => vec!["-shared".to_string()],
+            Flag::DependencyFile(dependency_file) => vec!["-MMD".to_string(), "-MF".to_string(), path(dependency_file)],
+            Flag::Library { directory, name } => vec![
+                format!("-L{}", path(directory)),
+                "-Wl,-rpath,$ORIGIN".to_string(),
+                format!("-l{}", name),
+            ],
+            /* tcc links by itself and only takes -Wl,-T for section addresses */
+            Flag::LinkerScript(_) | Flag::MapFile(_) if self.family == CompilerFamily::Tcc => {
+                return Err(unsupported(self.family, flag));
+            }
+            Flag::LinkerScript(script) => vec!["-T".to_string(), path(script)],
+            Flag::MapFile(map_file) => vec![format!("-Wl,-Map={}", path(map_file))],
+        };
+
+        Ok(arguments)
+    }
+
+    fn archive(&self, output_file: &Path, object_files: &[&Path]) -> Vec<String> {
+        let mut arguments = vec!["rcs".to_string(), path(output_file)];
+        arguments.extend(object_files.iter().map(|object_file| path(object_file)));
+
+        arguments
+    }
+}
+
+/** clang-cl and other cl.exe compatible drivers, archiving with lib.exe compatible tools */
+struct MsvcRenderer;
+
+impl FlagRenderer for MsvcRenderer {
+    fn render(&self, flag: &Flag) -> Result<Vec<String>> {
+        let arguments = match flag {
+            Flag::Optimization(level) => match level {
+                OptimizationLevel::None => vec![],
+                OptimizationLevel::O0 => vec!["/Od".to_string()],
+                OptimizationLevel::O | OptimizationLevel::O1 |
+                OptimizationLevel::Os | OptimizationLevel::Oz => vec!["/O1".to_string()],
+                OptimizationLevel::O2 | OptimizationLevel::O3 => vec!["/O2".to_string()],
+                OptimizationLevel::Ofast => vec!["/O2".to_string(), "/fp:fast".to_string()],
+                OptimizationLevel::Og => return Err(unsupported(CompilerFamily::ClangCl, flag)),
+            },
+            Flag::Debug => vec!["/Zi".to_string()],
+            Flag::Include(include_path) => vec!["/I".to_string(), path(include_path)],
+            Flag::Define(define) => vec![format!("/D{}", define)],
+            /* Windows code is position independent */
+            Flag::PositionIndependent => vec![],
+            Flag::Sysroot(_) => return Err(unsupported(CompilerFamily::ClangCl, flag)),
+            Flag::CompileOnly(source_file) => vec!["/c".to_string(), path(source_file)],
+            Flag::ObjectOutput(output_file) => vec![format!("/Fo{}", path(output_file))],
+            Flag::LinkedOutput(output_file) => vec![format!("/Fe{}", path(output_file))],
+            Flag::Shared => vec!["/LD".to_string()],
+            /* cl.exe only lists includes with /showIncludes */
+            Flag::DependencyFile(_) => vec![],
+            /* a DLL is linked through its import library */
+            Flag::Library { directory, name } => vec![path(&directory.join(format!("{}.lib", name)))],
+            Flag::LinkerScript(_) => return Err(unsupported(CompilerFamily::ClangCl, flag)),
+            Flag::MapFile(map_file) => vec![format!("/Fm{}", path(map_file))],
+        };
+
+        Ok(arguments)
+    }
+
+    fn archive(&self, output_file: &Path, object_files: &[&Path]) -> Vec<String> {
+        let mut arguments = vec![format!("/OUT:{}", path(output_file))];
+        arguments.extend(object_files.iter().map(|object_file| path(object_file)));
+
+        arguments
+    }
+}
+
+/** Small Device C Compiler, archiving with sdar */
+struct SdccRenderer;
+
+impl FlagRenderer for SdccRenderer {
+    fn render(&self, flag: &Flag) -> Result<Vec<String>> {
+        let arguments = match flag {
+            Flag::Optimization(level) => match level {
+                OptimizationLevel::None | OptimizationLevel::O0 => vec![],
+                OptimizationLevel::Os | OptimizationLevel::Oz => vec!["--opt-code-size".to_string()],
+                OptimizationLevel::O | OptimizationLevel::O1 | OptimizationLevel::O2 |
+                OptimizationLevel::O3 | OptimizationLevel::Ofast => vec!["--opt-code-speed".to_string()],
+                OptimizationLevel::Og => return Err(unsupported(CompilerFamily::Sdcc, flag)),
+            },
+            Flag::Debug => vec!["--debug".to_string()],
+            Flag::Include(include_path) => vec![format!("-I{}", path(include_path))],
+            Flag::Define(define) => vec![format!("-D{}", define)],
+            Flag::CompileOnly(source_file) => vec!["-c".to_string(), path(source_file)],
+            Flag::ObjectOutput(output_file) |
+            Flag::LinkedOutput(output_file) => vec!["-o".to_string(), path(output_file)],
+            Flag::DependencyFile(_) => vec![],
+            Flag::PositionIndependent |
+            Flag::Sysroot(_) |
+            Flag::Shared |
+            Flag::Library { .. } |
+            Flag::LinkerScript(_) |
+            Flag::MapFile(_) => return Err(unsupported(CompilerFamily::Sdcc, flag)),
+        };
+
+        Ok(arguments)
+    }
+
+    fn archive(&self, output_file: &Path, object_files: &[&Path]) -> Vec<String> {
+        let mut arguments = vec!["-rcs".to_string(), path(output_file)];
+        arguments.extend(object_files.iter().map(|object_file| path(object_file)));
+
+        arguments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn toolchain_info(family: CompilerFamily, supported_flags: &[&str]) -> ToolchainInfo {
+        ToolchainInfo {
+            compiler_path: PathBuf::from("/usr/bin/cc"),
+            family,
+            version: Some("4.8.5".to_string()),
+            target: "x86_64-linux-gnu".to_string(),
+            supported_flags: supported_flags.iter().map(|flag| flag.to_string()).collect(),
+        }
+    }
+
+    fn render(renderer: &dyn FlagRenderer, flag: Flag) -> Vec<String> {
+        renderer.render(&flag).unwrap()
+    }
+
+    #[test]
+    fn gcc_flags() {
+        let toolchain_info = toolchain_info(CompilerFamily::Gcc, &["-Og"]);
+        let renderer = flag_renderer(None, &toolchain_info);
+
+        assert_eq!(render(renderer.as_ref(), Flag::Optimization(&OptimizationLevel::O2)), ["-O2"]);
+        assert_eq!(render(renderer.as_ref(), Flag::Optimization(&OptimizationLevel::Og)), ["-Og"]);
+        assert!(render(renderer.as_ref(), Flag::Optimization(&OptimizationLevel::None)).is_empty());
+        assert_eq!(render(renderer.as_ref(), Flag::Include(Path::new("inc"))), ["-I", "inc"]);
+        assert_eq!(render(renderer.as_ref(), Flag::Define("DEBUG=1")), ["-DDEBUG=1"]);
+        assert_eq!(render(renderer.as_ref(), Flag::Sysroot("/opt/sysroot")), ["--sysroot=/opt/sysroot"]);
+        assert_eq!(render(renderer.as_ref(), Flag::DependencyFile(Path::new("a.d"))), ["-MMD", "-MF", "a.d"]);
+        assert_eq!(
+            render(renderer.as_ref(), Flag::Library { directory: Path::new("output"), name: "core" }),
+            ["-Loutput", "-Wl,-rpath,$ORIGIN", "-lcore"]
+        );
+        assert_eq!(render(renderer.as_ref(), Flag::MapFile(Path::new("app.map"))), ["-Wl,-Map=app.map"]);
+        assert_eq!(renderer.archive(Path::new("libcore.a"), &[Path::new("a.o"), Path::new("b.o")]), ["rcs", "libcore.a", "a.o", "b.o"]);
+    }
+
+    #[test]
+    fn unsupported_optimization_level_of_the_probed_compiler_is_an_error() {
+        let toolchain_info = toolchain_info(CompilerFamily::Gcc, &["-Og"]);
+        let renderer = flag_renderer(None, &toolchain_info);
+
+        let error = renderer.render(&Flag::Optimization(&OptimizationLevel::Oz)).unwrap_err();
+        assert_eq!(error.to_string(), "Gcc compiler \"/usr/bin/cc\" version 4.8.5 doesn't support -Oz");
+
+        /* levels every GCC has aren't probed */
+        assert_eq!(render(renderer.as_ref(), Flag::Optimization(&OptimizationLevel::Os)), ["-Os"]);
+    }
+
+    #[test]
+    fn tcc_has_no_linker_scripts() {
+        let toolchain_info = toolchain_info(CompilerFamily::Tcc, &[]);
+        let renderer = flag_renderer(None, &toolchain_info);
+
+        assert!(renderer.render(&Flag::LinkerScript(Path::new("link.ld"))).is_err());
+        assert_eq!(render(renderer.as_ref(), Flag::Debug), ["-g"]);
+    }
+
+    #[test]
+    fn declared_family_wins_over_the_detected_one() {
+        let toolchain_info = toolchain_info(CompilerFamily::Clang, &[]);
+        let renderer = flag_renderer(Some(CompilerFamily::ClangCl), &toolchain_info);
+
+        assert_eq!(render(renderer.as_ref(), Flag::Debug), ["/Zi"]);
+    }
+
+    #[test]
+    fn msvc_flags() {
+        let toolchain_info = toolchain_info(CompilerFamily::ClangCl, &[]);
+        let renderer = flag_renderer(None, &toolchain_info);
+
+        assert_eq!(render(renderer.as_ref(), Flag::Optimization(&OptimizationLevel::Ofast)), ["/O2", "/fp:fast"]);
+        assert_eq!(render(renderer.as_ref(), Flag::Define("DEBUG")), ["/DDEBUG"]);
+        assert_eq!(render(renderer.as_ref(), Flag::ObjectOutput(Path::new("a.obj"))), ["/Foa.obj"]);
+        assert_eq!(render(renderer.as_ref(), Flag::LinkedOutput(Path::new("app.exe"))), ["/Feapp.exe"]);
+        assert!(render(renderer.as_ref(), Flag::PositionIndependent).is_empty());
+        assert!(render(renderer.as_ref(), Flag::DependencyFile(Path::new("a.d"))).is_empty());
+        assert_eq!(
+            render(renderer.as_ref(), Flag::Library { directory: Path::new("output"), name: "core" }),
+            [Path::new("output").join("core.lib").display().to_string()]
+        );
+        assert!(renderer.render(&Flag::Optimization(&OptimizationLevel::Og)).is_err());
+        assert!(renderer.render(&Flag::Sysroot("/opt")).is_err());
+        assert_eq!(renderer.archive(Path::new("core.lib"), &[Path::new("a.obj")]), ["/OUT:core.lib", "a.obj"]);
+    }
+
+    #[test]
+    fn sdcc_flags() {
+        let toolchain_info = toolchain_info(CompilerFamily::Sdcc, &[]);
+        let renderer = flag_renderer(None, &toolchain_info);
+
+        assert_eq!(render(renderer.as_ref(), Flag::Optimization(&OptimizationLevel::Oz)), ["--opt-code-size"]);
+        assert_eq!(render(renderer.as_ref(), Flag::Optimization(&OptimizationLevel::O3)), ["--opt-code-speed"]);
+        assert_eq!(render(renderer.as_ref(), Flag::Include(Path::new("inc"))), ["-Iinc"]);
+        assert_eq!(render(renderer.as_ref(), Flag::Debug), ["--debug"]);
+        assert!(renderer.render(&Flag::Shared).is_err());
+        assert!(renderer.render(&Flag::PositionIndependent).is_err());
+        assert_eq!(renderer.archive(Path::new("core.lib"), &[Path::new("a.rel")]), ["-rcs", "core.lib", "a.rel"]);
+    }
+}
@@ -0,0 +1,350 @@
+use crate::config::Generator;
+use crate::reporter::{Event, Reporter};
+use crate::std_command_ext::{CliStr, ExecuteCommand};
+use crate::tool::files_fetcher;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+const OUT_DIR_PLACEHOLDER: &str = "{out_dir}";
+
+/** What the generator steps of a project or dependency produced */
+#[derive(Debug, Clone, Default)]
+pub struct GeneratedSources {
+    /// Directory of the outputs, an include directory of the sources.
+    pub directory: PathBuf,
+    /// C outputs, compiled with the sources.
+    pub sources: Vec<PathBuf>,
+}
+
+/**
+    Steps keep their stamp in a file named after them, so two steps of a project can't have the same name,
+    including the default one, their first output.
+ */
+pub fn check_names(generators: &[Generator]) -> Result<()> {
+    let mut stamp_names = HashSet::new();
+
+    for generator in generators {
+        if !stamp_names.insert(stamp_name(generator)) {
+            anyhow::bail!("Two generator steps are named '{}', give one of them another name", generator.name());
+        }
+    }
+
+    Ok(())
+}
+
+fn stamp_name(generator: &Generator) -> String {
+    format!(".{}.stamp", generator.name().replace(['/', '\\'], "_"))
+}
+
+/**
+    Runs the generator steps of a project or dependency into <generated>/<name>.
+    A step reruns only when its command, its inputs or the list of its outputs changed, or an output is missing.
+ */
+pub struct GeneratorRunner<'a> {
+    project_path: PathBuf,
+    output_path: PathBuf,
+    reporter: &'a dyn Reporter,
+    timeout: Option<Duration>,
+}
+
+impl<'a> GeneratorRunner<'a> {
+    /**
+        @param: project_path - directory of the project or dependency declaring the steps, where they run
+        @param: output_path - directory the outputs go to
+     */
+    pub fn new(project_path: &Path, output_path: &Path, reporter: &'a dyn Reporter, timeout: Option<Duration>) -> Self {
+        Self {
+            project_path: project_path.to_path_buf(),
+            output_path: output_path.to_path_buf(),
+            reporter,
+            timeout,
+        }
+    }
+
+    /**
+        @param: name - project or dependency name, used in the build output
+
+        @return: the outputs of every step
+     */
+    pub fn run(&self, name: &str, generators: &[Generator]) -> Result<GeneratedSources> {
+        fs::create_dir_all(&self.output_path)
+            .with_context(|| format!("Failed to create directory {:?}", &self.output_path))?;
+
+        /* the steps run in the project directory, so the output directory they get must not be relative */
+        let output_path = std::path::absolute(&self.output_path)
+            .with_context(|| format!("Failed to resolve directory {:?}", &self.output_path))?;
+
+        let mut sources = vec![];
+
+        for generator in generators {
+            let step_name = format!("{}/{}", name, generator.name());
+
+            self.run_step(generator, &step_name, &output_path)
+                .with_context(|| format!("Failed to run generator '{}'", step_name))?;
+
+            sources.extend(
+                generator.outputs
+                    .iter()
+                    .map(|output| output_path.join(output))
+                    .filter(|output| output.extension().is_some_and(|extension| extension == "c"))
+            );
+        }
+
+        sources.sort();
+        sources.dedup();
+
+        Ok(GeneratedSources {
+            directory: output_path,
+            sources,
+        })
+    }
+
+    fn run_step(&self, generator: &Generator, step_name: &str, output_path: &Path) -> Result<()> {
+        let Some((program, arguments)) = generator.command.split_first() else {
+            anyhow::bail!("The command is empty");
+        };
+
+        let substitute = |argument: &String| argument.replace(OUT_DIR_PLACEHOLDER, &output_path.display().to_string());
+
+        let mut command = Command::new(substitute(program));
+        command
+            .args(arguments.iter().map(substitute))
+            .current_dir(&self.project_path)
+            .env("OUT_DIR", output_path);
+
+        let stamp_path = output_path.join(stamp_name(generator));
+        let stamp = self.stamp(generator, &command)?;
+
+        let outputs: Vec<PathBuf> = generator.outputs.iter().map(|output| output_path.join(output)).collect();
+        let is_fresh = fs::read_to_string(&stamp_path).is_ok_and(|previous| previous == stamp)
+            && outputs.iter().all(|output| output.exists());
+
+        if is_fresh {
+            self.reporter.report(Event::GenerateFresh { name: step_name.to_string() });
+            return Ok(());
+        }
+
+        for output in &outputs {
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory {:?}", parent))?;
+            }
+        }
+
+        self.reporter.report(Event::GenerateStarted {
+            name: step_name.to_string(),
+            command: command.cli_args(),
+        });
+
+        let output = command.stream(self.timeout, &mut |stream, line| {
+            self.reporter.report(Event::Output {
+                job: step_name.to_string(),
+                stream,
+                line: line.to_string(),
+            });
+        });
+
+        let success = output.as_ref().is_ok_and(|output| output.success);
+        self.reporter.report(Event::GenerateFinished {
+            name: step_name.to_string(),
+            success,
+        });
+
+        let output = output?;
+        if !output.success {
+            anyhow::bail!("{:?} exit status: {}", program, output.status.code().unwrap_or(-1));
+        }
+
+        if let Some(missing) = outputs.iter().find(|output| !output.exists()) {
+            anyhow::bail!("The command didn't write the output {:?}", missing);
+        }
+
+        fs::write(&stamp_path, stamp)
+            .with_context(|| format!("Failed to write {:?}", &stamp_path))
+    }
+
+    /* a hash of the command, the outputs and the path and content of every input */
+    fn stamp(&self, generator: &Generator, command: &Command) -> Result<String> {
+        let mut hasher = Sha256::new();
+
+        hasher.update(command.cli_str());
+        hasher.update(generator.outputs.join("\0"));
+
+        for input in self.inputs(generator)? {
+            let content = fs::read(&input)
+                .with_context(|| format!("Failed to read generator input {:?}", &input))?;
+
+            hasher.update(input.display().to_string());
+            hasher.update(content);
+        }
+
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    }
+
+    /* inputs are files or glob patterns; a file that doesn't exist is an error, a pattern may match nothing */
+    fn inputs(&self, generator: &Generator) -> Result<Vec<PathBuf>> {
+        let mut inputs = vec![];
+
+        for input in &generator.inputs {
+            let pattern = files_fetcher::project_pattern(&self.project_path, input);
+            let paths = glob::glob(&pattern)
+                .with_context(|| format!("Invalid generator input pattern '{}'", input))?;
+
+            let mut matched = false;
+
+            for path in paths {
+                let path = path?;
+
+                if path.is_file() {
+                    inputs.push(path);
+                    matched = true;
+                }
+            }
+
+            if !matched && !input.contains(['*', '?', '[']) {
+                anyhow::bail!("Generator input '{}' doesn't exist", input);
+            }
+        }
+
+        inputs.sort();
+        inputs.dedup();
+
+        Ok(inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /* the name of every step that ran, and of every step found fresh */
+    #[derive(Default)]
+    struct StepRecorder {
+        started: Mutex<Vec<String>>,
+        fresh: Mutex<Vec<String>>,
+    }
+
+    impl Reporter for StepRecorder {
+        fn report(&self, event: Event) {
+            match event {
+                Event::GenerateStarted { name, .. } => self.started.lock().unwrap().push(name),
+                Event::GenerateFresh { name } => self.fresh.lock().unwrap().push(name),
+                _ => {}
+            }
+        }
+    }
+
+    fn generator(yaml: &str) -> Generator {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn project(name: &str) -> PathBuf {
+        let project_path = std::env::temp_dir().join(format!("corge-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&project_path);
+
+        fs::create_dir_all(project_path.join("data")).unwrap();
+        fs::write(project_path.join("data/table.csv"), "1,2,3").unwrap();
+
+        project_path
+    }
+
+    #[test]
+    fn stamp_changes_with_the_command_inputs_and_outputs() {
+        let project_path = project("generator-stamp");
+        let recorder = StepRecorder::default();
+        let runner = GeneratorRunner::new(&project_path, &project_path.join("out"), &recorder, None);
+
+        let stamp = |generator: &Generator| {
+            let mut command = Command::new(&generator.command[0]);
+            command.args(&generator.command[1..]);
+
+            runner.stamp(generator, &command).unwrap()
+        };
+
+        let tables = generator("{ command: [gen, tables], inputs: [\"data/*.csv\"], outputs: [tables.c] }");
+        let original = stamp(&tables);
+
+        assert_eq!(stamp(&tables), original);
+        assert_ne!(stamp(&generator("{ command: [gen, tables, -v], inputs: [\"data/*.csv\"], outputs: [tables.c] }")), original);
+        assert_ne!(stamp(&generator("{ command: [gen, tables], inputs: [\"data/*.csv\"], outputs: [tables.c, tables.h] }")), original);
+
+        fs::write(project_path.join("data/extra.csv"), "4").unwrap();
+        assert_ne!(stamp(&tables), original);
+
+        fs::remove_file(project_path.join("data/extra.csv")).unwrap();
+        assert_eq!(stamp(&tables), original);
+
+        fs::write(project_path.join("data/table.csv"), "1,2,4").unwrap();
+        assert_ne!(stamp(&tables), original);
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn step_reruns_only_when_its_stamp_changes() {
+        let project_path = project("generator-rerun");
+        let output_path = project_path.join("out");
+        let recorder = StepRecorder::default();
+        let runner = GeneratorRunner::new(&project_path, &output_path, &recorder, None);
+
+        let tables = generator(r#"{ name: tables, command: [sh, -c, "cat data/table.csv > $OUT_DIR/tables.c"], inputs: [data/table.csv], outputs: [tables.c] }"#);
+        let run = |generator: &Generator| {
+            runner.run("app", std::slice::from_ref(generator)).unwrap();
+
+            (recorder.started.lock().unwrap().len(), recorder.fresh.lock().unwrap().len())
+        };
+
+        assert_eq!(run(&tables), (1, 0));
+        assert_eq!(run(&tables), (1, 1));
+
+        fs::write(project_path.join("data/table.csv"), "4,5,6").unwrap();
+        assert_eq!(run(&tables), (2, 1));
+        assert_eq!(fs::read_to_string(output_path.join("tables.c")).unwrap(), "4,5,6");
+
+        fs::remove_file(output_path.join("tables.c")).unwrap();
+        assert_eq!(run(&tables), (3, 1));
+
+        let mut verbose = tables.clone();
+        verbose.command[2].push_str(" && true");
+        assert_eq!(run(&verbose), (4, 1));
+        assert_eq!(run(&verbose), (4, 2));
+
+        assert_eq!(*recorder.started.lock().unwrap(), ["app/tables"; 4]);
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn steps_with_the_same_name_are_rejected() {
+        let parser = generator("{ command: [bison, src/parser.y], outputs: [parser.c, parser.h] }");
+        let tables = generator("{ command: [gen, tables], outputs: [tables.c] }");
+
+        assert!(check_names(&[parser.clone(), tables.clone()]).is_ok());
+
+        /* the default name of a step is its first output */
+        let other_parser = generator("{ command: [bison, src/other.y], outputs: [parser.c] }");
+        assert_eq!(
+            check_names(&[parser.clone(), tables.clone(), other_parser]).unwrap_err().to_string(),
+            "Two generator steps are named 'parser.c', give one of them another name"
+        );
+
+        let named = generator("{ name: parser.c, command: [gen, tables], outputs: [tables.c] }");
+        assert!(check_names(&[parser.clone(), named]).is_err());
+
+        /* both would write .gen_parser.stamp */
+        let nested = generator("{ command: [gen], outputs: [gen/parser.c] }");
+        let flat = generator("{ command: [gen], outputs: [gen_parser.c] }");
+        assert!(check_names(&[nested, flat]).is_err());
+    }
+}
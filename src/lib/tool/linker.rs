@@ -1,70 +1,194 @@
 use crate::config::{LinkStrategy, Toolchain};
 use crate::extension_manager::Extension;
-use crate::std_command_ext::ExecuteCommand;
+use crate::reporter::{Event, Reporter};
+use crate::std_command_ext::{CliStr, ExecuteCommand};
+use crate::tool::flag_renderer::{flag_renderer, Flag, FlagRenderer};
+use crate::tool::toolchain_probe::{find_executable, ToolchainInfo};
 use anyhow::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
+
+/** What a link reads and writes besides the object files and the artifact */
+#[derive(Debug, Clone, Default)]
+pub struct LinkSettings {
+    /// Names of dynamic library dependencies already linked into the output directory.
+    pub shared_libraries: Vec<String>,
+    pub linker_script: Option<PathBuf>,
+    pub map_file: Option<PathBuf>,
+    /// Linker flags of build.yaml, the project's before its dependencies'. They follow the object files,
+    /// where libraries like `-lm` resolve the symbols the objects need.
+    pub linker_flags: Vec<String>,
+}
 
-pub struct Linker {
+pub struct Linker<'a> {
+    renderer: Box<dyn FlagRenderer + 'a>,
     toolchain: Toolchain,
+    toolchain_info: &'a ToolchainInfo,
+    reporter: &'a dyn Reporter,
+    timeout: Option<Duration>,
 }
 
-impl Linker {
-    pub fn new(toolchain: Toolchain) -> Self {
+impl<'a> Linker<'a> {
+    pub fn new(toolchain: Toolchain, toolchain_info: &'a ToolchainInfo, reporter: &'a dyn Reporter, timeout: Option<Duration>) -> Self {
         Self {
+            renderer: flag_renderer(toolchain.family, toolchain_info),
             toolchain,
+            toolchain_info,
+            reporter,
+            timeout,
         }
     }
 
-    pub fn link(&self, link_strategy: &LinkStrategy, object_files: &[PathBuf], output_path: &PathBuf, output_name: &str) -> Result<PathBuf> {
+    /**
+        Links every time, so changes to the linker script or the linker flags are always picked up.
+
+        @return: path of the linked artifact
+    */
+    pub fn link(&self, link_strategy: &LinkStrategy, object_files: &[PathBuf], settings: &LinkSettings, output_path: &Path, output_name: &str) -> Result<PathBuf> {
         let (mut command, output_file_path) = match link_strategy {
             LinkStrategy::Executable => {
-                let mut command = Command::new(&self.toolchain.compiler);
-
-                let output_name = Extension::Executable.file_name(output_name, &self.toolchain.compiler);
+                let output_name = Extension::Executable.file_name(output_name, &self.toolchain_info.target);
                 let output_file_path = output_path.join(output_name);
 
-                command
-                    .arg("-o")
-                    .arg(&output_file_path);
+                let command = self.link_command(&[], object_files, settings, output_path, &output_file_path)?;
 
                 (command, output_file_path)
             }
             LinkStrategy::StaticLibrary => {
-                let mut command = Command::new(&self.toolchain.archiver);
-                command.arg("rcs");
+                let archiver_path = find_executable(&self.toolchain.archiver)
+                    .ok_or_else(|| anyhow::anyhow!("Archiver '{}' was not found", &self.toolchain.archiver))?;
 
-                let output_name = Extension::StaticLibrary.file_name(output_name, &self.toolchain.compiler);
+                let output_name = Extension::StaticLibrary.file_name(output_name, &self.toolchain_info.target);
                 let output_file_path = output_path.join(output_name);
 
-                command
-                    .arg(&output_file_path);
+                if !settings.shared_libraries.is_empty() {
+                    log::warn!("Static library {:?} doesn't embed its dynamic library dependencies", &output_file_path);
+                }
+
+                let object_files: Vec<&Path> = object_files.iter().map(PathBuf::as_path).collect();
+
+                let mut command = Command::new(archiver_path);
+                command.args(self.renderer.archive(&output_file_path, &object_files));
 
                 (command, output_file_path)
             }
             LinkStrategy::DynamicLibrary => {
-                let mut command = Command::new(&self.toolchain.compiler);
-                command.arg("-shared");
-
-                let output_name = Extension::DynamicLibrary.file_name(output_name, &self.toolchain.compiler);
+                let output_name = Extension::DynamicLibrary.file_name(output_name, &self.toolchain_info.target);
                 let output_file_path = output_path.join(output_name);
 
-                command
-                    .arg("-o")
-                    .arg(&output_file_path);
+                let command = self.link_command(&[Flag::Shared], object_files, settings, output_path, &output_file_path)?;
 
                 (command, output_file_path)
             }
         };
 
+        self.reporter.report(Event::LinkStarted {
+            name: output_name.to_string(),
+            link_strategy: link_strategy.clone(),
+            output: output_file_path.clone(),
+            command: command.cli_args(),
+        });
+
+        log::trace!("{}", command.cli_str());
+
+        let output = command.stream(self.timeout, &mut |stream, line| {
+            self.reporter.report(Event::Output {
+                job: output_name.to_string(),
+                stream,
+                line: line.to_string(),
+            });
+        })?;
+
+        self.reporter.report(Event::LinkFinished {
+            name: output_name.to_string(),
+            output: output_file_path.clone(),
+            success: output.success,
+        });
+
+        /* the output of the tool was reported while it ran */
+        if !output.success {
+            anyhow::bail!("{:?} {}", command.get_program(), output.status);
+        }
+
+        Ok(output_file_path)
+    }
+
+    fn link_command(
+        &self,
+        flags: &[Flag],
+        object_files: &[PathBuf],
+        settings: &LinkSettings,
+        output_path: &Path,
+        output_file_path: &Path,
+    ) -> Result<Command> {
+        let mut command = Command::new(&self.toolchain_info.compiler_path);
+
+        for flag in flags {
+            command.args(self.renderer.render(flag)?);
+        }
+
+        command.args(self.renderer.render(&Flag::LinkedOutput(output_file_path))?);
+
         command.args(&self.toolchain.linker_flags);
 
-        for object_file in object_files {
-            command.arg(object_file);
+        if let Some(linker_script) = &settings.linker_script {
+            command.args(self.renderer.render(&Flag::LinkerScript(linker_script))?);
+        }
+
+        if let Some(map_file) = &settings.map_file {
+            command.args(self.renderer.render(&Flag::MapFile(map_file))?);
+        }
+
+        if let Some(sysroot) = &self.toolchain.sysroot {
+            command.args(self.renderer.render(&Flag::Sysroot(sysroot))?);
         }
 
-        command.execute(true)?;
+        command.args(object_files);
 
-        Ok(output_file_path)
+        for shared_library in &settings.shared_libraries {
+            command.args(self.renderer.render(&Flag::Library { directory: output_path, name: shared_library })?);
+        }
+
+        command.args(&settings.linker_flags);
+
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CompilerFamily;
+    use crate::reporter::LogReporter;
+
+    #[test]
+    fn libraries_of_build_yaml_follow_the_object_files() {
+        let toolchain = Toolchain {
+            linker_flags: vec!["-fuse-ld=lld".to_string()],
+            ..Toolchain::default()
+        };
+        let toolchain_info = ToolchainInfo {
+            compiler_path: PathBuf::from("cc"),
+            family: CompilerFamily::Gcc,
+            version: None,
+            target: "x86_64-unknown-linux-gnu".to_string(),
+            supported_flags: vec![],
+        };
+        let settings = LinkSettings {
+            shared_libraries: vec!["core".to_string()],
+            linker_flags: vec!["-lm".to_string(), "-lpthread".to_string()],
+            ..LinkSettings::default()
+        };
+
+        let command = Linker::new(toolchain, &toolchain_info, &LogReporter, None)
+            .link_command(&[], &[PathBuf::from("main.o")], &settings, Path::new("out"), Path::new("out/app"))
+            .unwrap();
+
+        assert_eq!(command.cli_args(), [
+            "cc", "-o", "out/app", "-fuse-ld=lld", "main.o",
+            "-Lout", "-Wl,-rpath,$ORIGIN", "-lcore",
+            "-lm", "-lpthread",
+        ]);
     }
 }
@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+pub const LOCKFILE_NAME: &str = "build.lock";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum LockedSource {
+    /// A member of the enclosing workspace, used in place.
+    Workspace(String),
+    FileSystem(String),
+    Git {
+        url: String,
+        branch: String,
+        commit: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LockedDependency {
+    pub name: String,
+    pub source: LockedSource,
+}
+
+/**
+    Records the exact source every dependency was resolved from.
+    A workspace writes a single lockfile at its root, a single project only updates one that already exists.
+ */
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Lockfile {
+    #[serde(default)]
+    pub dependencies: Vec<LockedDependency>,
+}
+
+impl Lockfile {
+    pub fn exists(root_path: &Path) -> bool {
+        root_path.join(LOCKFILE_NAME).is_file()
+    }
+
+    pub fn read(root_path: &Path) -> Result<Self> {
+        let lockfile_path = root_path.join(LOCKFILE_NAME);
+
+        if !lockfile_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let lockfile_str = fs::read_to_string(&lockfile_path)
+            .with_context(|| format!("Failed to read lockfile {:?}", &lockfile_path))?;
+
+        /* an empty build.lock opts a single project into locking */
+        if lockfile_str.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        serde_yaml::from_str(&lockfile_str)
+            .with_context(|| format!("Failed to parse lockfile {:?}", &lockfile_path))
+    }
+
+    pub fn write(&self, root_path: &Path) -> Result<()> {
+        let lockfile_path = root_path.join(LOCKFILE_NAME);
+
+        let lockfile_str = serde_yaml::to_string(self)
+            .context("Failed to serialize lockfile")?;
+
+        fs::write(&lockfile_path, lockfile_str)
+            .with_context(|| format!("Failed to write lockfile {:?}", &lockfile_path))
+    }
+
+    /* A commit locked for another repository or branch doesn't apply after the registry changed */
+    pub fn locked_commit(&self, dependency_name: &str, registry_url: &str, registry_branch: &str) -> Option<&str> {
+        self.dependencies
+            .iter()
+            .find(|locked| locked.name == dependency_name)
+            .and_then(|locked| match &locked.source {
+                LockedSource::Git { url, branch, commit } if url == registry_url && branch == registry_branch => {
+                    Some(commit.as_str())
+                }
+                _ => None,
+            })
+    }
+
+    /* Adds or replaces the entry for a dependency, keeping the list sorted by name */
+    pub fn insert(&mut self, locked: LockedDependency) {
+        match self.dependencies.iter_mut().find(|existing| existing.name == locked.name) {
+            Some(existing) => *existing = locked,
+            None => self.dependencies.push(locked),
+        }
+
+        self.dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockfile() -> Lockfile {
+        let mut lockfile = Lockfile::default();
+
+        lockfile.insert(LockedDependency {
+            name: "mbedtls".to_string(),
+            source: LockedSource::Git {
+                url: "https://example.com/c".to_string(),
+                branch: "main".to_string(),
+                commit: "0123abcd".to_string(),
+            },
+        });
+        lockfile.insert(LockedDependency {
+            name: "core".to_string(),
+            source: LockedSource::FileSystem("/registry/core".to_string()),
+        });
+
+        lockfile
+    }
+
+    #[test]
+    fn locked_commit_matches_name_url_and_branch() {
+        let lockfile = lockfile();
+
+        assert_eq!(lockfile.locked_commit("mbedtls", "https://example.com/c", "main"), Some("0123abcd"));
+        assert_eq!(lockfile.locked_commit("mbedtls", "https://example.com/fork", "main"), None);
+        assert_eq!(lockfile.locked_commit("mbedtls", "https://example.com/c", "develop"), None);
+        assert_eq!(lockfile.locked_commit("core", "https://example.com/c", "main"), None);
+        assert_eq!(lockfile.locked_commit("zlib", "https://example.com/c", "main"), None);
+    }
+
+    #[test]
+    fn insert_replaces_and_sorts_by_name() {
+        let mut lockfile = lockfile();
+
+        lockfile.insert(LockedDependency {
+            name: "mbedtls".to_string(),
+            source: LockedSource::FileSystem("/registry/mbedtls".to_string()),
+        });
+
+        let names: Vec<&str> = lockfile.dependencies.iter().map(|locked| locked.name.as_str()).collect();
+        assert_eq!(names, ["core", "mbedtls"]);
+        assert_eq!(lockfile.dependencies[1].source, LockedSource::FileSystem("/registry/mbedtls".to_string()));
+    }
+
+    #[test]
+    fn empty_lockfile_reads_as_no_dependencies() {
+        let root_path = std::env::temp_dir().join(format!("corge-lockfile-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root_path);
+        fs::create_dir_all(&root_path).unwrap();
+
+        assert!(!Lockfile::exists(&root_path));
+
+        fs::write(root_path.join(LOCKFILE_NAME), "").unwrap();
+
+        assert!(Lockfile::exists(&root_path));
+        assert!(Lockfile::read(&root_path).unwrap().dependencies.is_empty());
+
+        fs::remove_dir_all(&root_path).unwrap();
+    }
+}
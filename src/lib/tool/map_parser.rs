@@ -0,0 +1,353 @@
+use anyhow::Result;
+
+/* sections that aren't loaded into memory */
+const NON_ALLOCATED_PREFIXES: [&str; 6] = [".debug", ".comment", ".stab", ".note.GNU-stack", ".ARM.attributes", ".gnu.attributes"];
+
+/** A section of the linked artifact */
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputSection {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+}
+
+/** The part of an output section an object file contributes */
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputSection {
+    pub name: String,
+    pub output_section: String,
+    pub object: String,
+    pub size: u64,
+}
+
+/** The sections of a linked artifact and what each object file put into them */
+#[derive(Debug, Clone, Default)]
+pub struct LinkMap {
+    pub sections: Vec<OutputSection>,
+    pub inputs: Vec<InputSection>,
+}
+
+impl LinkMap {
+    /** Sections loaded into memory, without debug information and other metadata */
+    pub fn allocated_sections(&self) -> impl Iterator<Item = &OutputSection> {
+        self.sections.iter().filter(|section| is_allocated(&section.name))
+    }
+
+    /**
+        Size per object file, largest first.
+
+        @param: section - only counts this output section, every allocated one if None
+     */
+    pub fn object_sizes(&self, section: Option<&str>) -> Vec<(String, u64)> {
+        let mut sizes: Vec<(String, u64)> = vec![];
+
+        let inputs = self.inputs
+            .iter()
+            .filter(|input| match section {
+                Some(section) => input.output_section == section,
+                None => is_allocated(&input.output_section),
+            });
+
+        for input in inputs {
+            match sizes.iter_mut().find(|(object, _)| *object == input.object) {
+                Some((_, size)) => *size += input.size,
+                None => sizes.push((input.object.clone(), input.size)),
+            }
+        }
+
+        sizes.retain(|(_, size)| *size > 0);
+        sizes.sort_by(|(object, size), (other_object, other_size)| other_size.cmp(size).then(object.cmp(other_object)));
+
+        sizes
+    }
+}
+
+fn is_allocated(section: &str) -> bool {
+    !NON_ALLOCATED_PREFIXES.iter().any(|prefix| section.starts_with(prefix))
+}
+
+/** Parses a map file written by GNU ld (-Map) or LLVM lld */
+pub fn parse(content: &str) -> Result<LinkMap> {
+    if let Some(header) = content.lines().find(|line| is_lld_header(line)) {
+        return parse_lld(content, header);
+    }
+
+    let Some((_, memory_map)) = content.split_once("Linker script and memory map") else {
+        anyhow::bail!("Neither a GNU ld nor an lld map file");
+    };
+
+    Ok(parse_gnu(memory_map))
+}
+
+/*
+.text           0x0000000000001040      0x108
+ *(.text .stub .text.* .gnu.linkonce.t.*)
+ .text          0x0000000000001129       0x14 a.o
+                0x0000000000001129                f
+ .text.startup
+                0x0000000000001140        0xb main.o
+
+   Output sections start in the first column and input sections after one space.
+   A long section name is followed by a line break, and its address and size are on the next line.
+ */
+fn parse_gnu(memory_map: &str) -> LinkMap {
+    let mut link_map = LinkMap::default();
+    let mut current_section = String::new();
+    let mut wrapped_name: Option<(String, bool)> = None;
+
+    for line in memory_map.lines() {
+        if line.trim().is_empty() {
+            wrapped_name = None;
+            continue;
+        }
+
+        let (mut tokens, is_input) = match wrapped_name.take() {
+            Some((name, is_input)) => (vec![name], is_input),
+            None if !line.starts_with(' ') => (vec![], false),
+            None if !line.starts_with("  ") => (vec![], true),
+            /* symbols and assignments */
+            None => continue,
+        };
+
+        tokens.extend(line.split_whitespace().map(str::to_string));
+
+        let name = &tokens[0];
+        if name.starts_with('*') {
+            continue;
+        }
+
+        let (Some(address), Some(size)) = (tokens.get(1).and_then(|token| hex(token)), tokens.get(2).and_then(|token| hex(token))) else {
+            if tokens.len() == 1 && !name.contains('(') {
+                wrapped_name = Some((name.clone(), is_input));
+            }
+
+            continue;
+        };
+
+        if is_input {
+            let object = tokens[3..].join(" ");
+
+            if !object.is_empty() {
+                link_map.inputs.push(InputSection {
+                    name: name.clone(),
+                    output_section: current_section.clone(),
+                    object,
+                    size,
+                });
+            }
+        } else {
+            current_section = name.clone();
+
+            link_map.sections.push(OutputSection {
+                name: name.clone(),
+                address,
+                size,
+            });
+        }
+    }
+
+    link_map
+}
+
+fn hex(token: &str) -> Option<u64> {
+    u64::from_str_radix(token.strip_prefix("0x")?, 16).ok()
+}
+
+/* "     VMA      LMA     Size Align Out     In      Symbol", older versions have "Address" instead of VMA and LMA */
+fn is_lld_header(line: &str) -> bool {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+
+    tokens.contains(&"Size") && tokens.ends_with(&["Out", "In", "Symbol"])
+}
+
+/*
+             VMA              LMA     Size Align Out     In      Symbol
+          2002a8           2002a8       1c     1 .interp
+          2002a8           2002a8       1c     1         <internal>:(.interp)
+          201120           201120       14    16         a.o:(.text)
+          201120           201120        0     1                 f
+
+   The column the name starts in tells output sections, input sections and symbols apart.
+ */
+fn parse_lld(content: &str, header: &str) -> Result<LinkMap> {
+    let columns: Vec<&str> = header.split_whitespace().collect();
+    let numeric_columns = columns.len() - 3;
+    let size_column = columns
+        .iter()
+        .position(|column| *column == "Size")
+        .ok_or_else(|| anyhow::anyhow!("No Size column in '{}'", header))?;
+
+    let in_column = header
+        .find(" In ")
+        .map(|position| position + 1)
+        .ok_or_else(|| anyhow::anyhow!("No In column in '{}'", header))?;
+    let symbol_column = header
+        .find("Symbol")
+        .ok_or_else(|| anyhow::anyhow!("No Symbol column in '{}'", header))?;
+
+    let mut link_map = LinkMap::default();
+    let mut current_section = String::new();
+
+    for line in content.lines().skip_while(|line| *line != header).skip(1) {
+        let mut rest = line;
+        let mut numbers = vec![];
+
+        for _ in 0..numeric_columns {
+            let trimmed = rest.trim_start();
+            let (token, remainder) = trimmed.split_at(trimmed.find(' ').unwrap_or(trimmed.len()));
+
+            let Ok(number) = u64::from_str_radix(token, 16) else {
+                break;
+            };
+
+            numbers.push(number);
+            rest = remainder;
+        }
+
+        /* the name is the rest of the line, the column it starts in is its level */
+        let name = rest.trim();
+        if numbers.len() < numeric_columns || name.is_empty() {
+            continue;
+        }
+
+        let name_column = line.len() - rest.trim_start().len();
+
+        if name_column >= symbol_column {
+            continue;
+        }
+
+        if name_column >= in_column {
+            /* "a.o:(.text)", "libc.a(printf.o):(.text.printf)" */
+            let (object, section) = name
+                .rsplit_once(":(")
+                .map(|(object, section)| (object, section.trim_end_matches(')')))
+                .unwrap_or((name, ""));
+
+            link_map.inputs.push(InputSection {
+                name: section.to_string(),
+                output_section: current_section.clone(),
+                object: object.to_string(),
+                size: numbers[size_column],
+            });
+        } else {
+            current_section = name.to_string();
+
+            link_map.sections.push(OutputSection {
+                name: name.to_string(),
+                address: numbers[0],
+                size: numbers[size_column],
+            });
+        }
+    }
+
+    Ok(link_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GNU_MAP: &str = "\
+Memory Configuration
+
+Name             Origin             Length             Attributes
+*default*        0x0000000000000000 0xffffffffffffffff
+
+Linker script and memory map
+
+LOAD a.o
+LOAD main.o
+
+.text           0x0000000000001000       0x16
+ *(.text.startup .text.startup.*)
+ .text.startup.main
+                0x0000000000001000        0x5 main.o
+                0x0000000000001000                main
+ *(.text .stub .text.* .gnu.linkonce.t.*)
+ .text          0x0000000000001005        0x0 a.o
+ *fill*         0x0000000000001005        0xb 
+ .text.f        0x0000000000001010        0x6 a.o
+                0x0000000000001010                f
+
+.bss            0x0000000000004000       0x40
+ *(.bss .bss.* .gnu.linkonce.b.*)
+ .bss           0x0000000000004000       0x40 main.o
+                0x0000000000004000                b
+
+.comment        0x0000000000000000       0x27
+ *(.comment)
+ .comment       0x0000000000000000       0x27 a.o
+                                         0x28 (size before relaxing)
+";
+
+    /* no line continuation, it would strip the indentation the columns are told apart by */
+    const LLD_MAP: &str = "
+             VMA              LMA     Size Align Out     In      Symbol
+          2002a8           2002a8       1c     1 .interp
+          2002a8           2002a8       1c     1         <internal>:(.interp)
+          201120           201120       30    16 .text
+          201120           201120       14    16         a.o:(.text)
+          201120           201120        0     1                 f
+          201140           201140       1c    16         /x/libc.a(printf.o):(.text.printf)
+          202000           202000       40     8 .bss
+          202000           202000       40     8         a.o:(.bss)
+               0                0       99     1 .comment
+";
+
+    fn section(name: &str, address: u64, size: u64) -> OutputSection {
+        OutputSection { name: name.to_string(), address, size }
+    }
+
+    #[test]
+    fn parse_gnu_map() {
+        let link_map = parse(GNU_MAP).unwrap();
+
+        assert_eq!(link_map.sections, [
+            section(".text", 0x1000, 0x16),
+            section(".bss", 0x4000, 0x40),
+            section(".comment", 0, 0x27),
+        ]);
+
+        /* the wrapped .text.startup.main line and its size on the next line */
+        assert_eq!(link_map.inputs[0], InputSection {
+            name: ".text.startup.main".to_string(),
+            output_section: ".text".to_string(),
+            object: "main.o".to_string(),
+            size: 5,
+        });
+
+        assert_eq!(link_map.allocated_sections().count(), 2);
+        assert_eq!(link_map.object_sizes(None), [("main.o".to_string(), 0x45), ("a.o".to_string(), 6)]);
+        assert_eq!(link_map.object_sizes(Some(".comment")), [("a.o".to_string(), 0x27)]);
+    }
+
+    #[test]
+    fn parse_lld_map() {
+        let link_map = parse(LLD_MAP).unwrap();
+
+        assert_eq!(link_map.sections, [
+            section(".interp", 0x2002a8, 0x1c),
+            section(".text", 0x201120, 0x30),
+            section(".bss", 0x202000, 0x40),
+            section(".comment", 0, 0x99),
+        ]);
+
+        assert_eq!(link_map.inputs.len(), 4);
+        assert_eq!(link_map.inputs[2], InputSection {
+            name: ".text.printf".to_string(),
+            output_section: ".text".to_string(),
+            object: "/x/libc.a(printf.o)".to_string(),
+            size: 0x1c,
+        });
+
+        assert_eq!(link_map.object_sizes(Some(".text")), [
+            ("/x/libc.a(printf.o)".to_string(), 0x1c),
+            ("a.o".to_string(), 0x14),
+        ]);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        assert!(parse("text data bss\n").is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/* objects are named <file>.<sha256>.<ext>, see Compiler::object_file */
+const HASH_LENGTH: usize = 64;
+
+/**
+    An object file in a cache directory with the files kept next to it (diagnostics, header dependencies).
+    Its modification time is the last build that used it.
+ */
+#[derive(Debug, Clone)]
+pub struct CachedObject {
+    pub key: String,
+    pub files: Vec<PathBuf>,
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+impl CachedObject {
+    pub fn remove(&self) -> Result<()> {
+        for file in &self.files {
+            fs::remove_file(file)
+                .with_context(|| format!("Failed to remove cached file {:?}", file))?;
+        }
+
+        Ok(())
+    }
+}
+
+/* "main.c.<sha256>" for main.c.<sha256>.o, main.c.<sha256>.d and main.c.<sha256>.diagnostics.json */
+fn object_key(file_name: &str) -> Option<String> {
+    let segments: Vec<&str> = file_name.split('.').collect();
+
+    let hash_position = segments
+        .iter()
+        .position(|segment| segment.len() == HASH_LENGTH && segment.chars().all(|char| char.is_ascii_hexdigit()))?;
+
+    Some(segments[..=hash_position].join("."))
+}
+
+/** Records that a build used the object file, so pruning by age keeps it */
+pub fn touch(object_file: &Path) -> Result<()> {
+    File::options()
+        .write(true)
+        .open(object_file)
+        .and_then(|file| file.set_modified(SystemTime::now()))
+        .with_context(|| format!("Failed to update the modification time of {:?}", object_file))
+}
+
+/** The objects directly in a cache directory; member and dependency caches are separate directories */
+pub fn cached_objects(cache_path: &Path) -> Result<Vec<CachedObject>> {
+    let mut objects: BTreeMap<String, CachedObject> = BTreeMap::new();
+
+    for entry in fs::read_dir(cache_path).with_context(|| format!("Failed to read directory {:?}", cache_path))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        let Some(key) = object_key(&entry.file_name().to_string_lossy()) else {
+            continue;
+        };
+
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let modified = metadata.modified()?;
+        let object = objects.entry(key.clone()).or_insert_with(|| CachedObject {
+            key,
+            files: vec![],
+            size: 0,
+            last_used: modified,
+        });
+
+        object.files.push(entry.path());
+        object.size += metadata.len();
+        object.last_used = object.last_used.max(modified);
+    }
+
+    Ok(objects.into_values().collect())
+}
+
+/** Every directory under the cache directories of target/, including workspace member and dependency caches */
+pub fn cache_directories(toolchain_directories: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    fn collect(path: &Path, directories: &mut Vec<PathBuf>) -> Result<()> {
+        directories.push(path.to_path_buf());
+
+        for entry in fs::read_dir(path).with_context(|| format!("Failed to read directory {:?}", path))? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_dir() {
+                collect(&entry.path(), directories)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut directories = vec![];
+
+    for toolchain_directory in toolchain_directories {
+        let cache_path = toolchain_directory.join("cache");
+
+        if cache_path.is_dir() {
+            collect(&cache_path, &mut directories)?;
+        }
+    }
+
+    Ok(directories)
+}
+
+/**
+    The objects a build used per cache directory.
+    Workspace members share the dependency caches, so the unused objects are only removed once every member is built.
+ */
+#[derive(Debug, Default)]
+pub struct UsedObjects {
+    caches: BTreeMap<PathBuf, HashSet<PathBuf>>,
+}
+
+impl UsedObjects {
+    pub fn add(&mut self, cache_path: &Path, objects: &[PathBuf]) {
+        self.caches
+            .entry(cache_path.to_path_buf())
+            .or_default()
+            .extend(objects.iter().cloned());
+    }
+
+    /** Removes the objects of every recorded cache directory that no project of the build used */
+    pub fn prune(&self) -> Result<()> {
+        for (cache_path, objects) in &self.caches {
+            let objects: Vec<PathBuf> = objects.iter().cloned().collect();
+
+            prune_unreferenced(cache_path, &objects)?;
+        }
+
+        Ok(())
+    }
+}
+
+/**
+    Removes the objects of a cache directory that the build didn't use, which are left behind
+    whenever a source or the compiler arguments change.
+    @return: the number of removed objects
+ */
+pub fn prune_unreferenced(cache_path: &Path, used_objects: &[PathBuf]) -> Result<usize> {
+    let used_keys: HashSet<String> = used_objects
+        .iter()
+        .filter_map(|object| object.file_name())
+        .filter_map(|file_name| object_key(&file_name.to_string_lossy()))
+        .collect();
+
+    let mut removed = 0;
+
+    for object in cached_objects(cache_path)? {
+        if !used_keys.contains(&object.key) {
+            object.remove()?;
+            removed += 1;
+        }
+    }
+
+    if removed > 0 {
+        log::debug!("Removed {} unused object file(s) from {:?}", removed, cache_path);
+    }
+
+    Ok(removed)
+}
+
+/** 1536 -> "1.5 KiB" */
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn object_key_groups_the_files_of_an_object() {
+        let key = format!("main.c.{}", HASH);
+
+        assert_eq!(object_key(&format!("main.c.{}.o", HASH)), Some(key.clone()));
+        assert_eq!(object_key(&format!("main.c.{}.d", HASH)), Some(key.clone()));
+        assert_eq!(object_key(&format!("main.c.{}.diagnostics.json", HASH)), Some(key));
+        assert_eq!(object_key("main.c.o"), None);
+        assert_eq!(object_key(&format!("main.c.{}.o", &HASH[1..])), None);
+    }
+
+    #[test]
+    fn format_size_uses_binary_units() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn prune_keeps_objects_used_by_any_project() {
+        let cache_path = std::env::temp_dir().join(format!("corge-object-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&cache_path);
+        fs::create_dir_all(&cache_path).unwrap();
+
+        let object = |name: &str, hash: char| {
+            let stem = format!("{}.{}", name, hash.to_string().repeat(HASH_LENGTH));
+            fs::write(cache_path.join(format!("{}.o", stem)), "").unwrap();
+            fs::write(cache_path.join(format!("{}.d", stem)), "").unwrap();
+            cache_path.join(format!("{}.o", stem))
+        };
+
+        let first = object("a.c", 'a');
+        let second = object("a.c", 'b');
+        object("c.c", 'c');
+
+        let mut used_objects = UsedObjects::default();
+        used_objects.add(&cache_path, std::slice::from_ref(&first));
+        used_objects.add(&cache_path, std::slice::from_ref(&second));
+        used_objects.prune().unwrap();
+
+        let mut keys: Vec<String> = cached_objects(&cache_path).unwrap().into_iter().map(|object| object.key).collect();
+        keys.sort();
+
+        assert_eq!(keys, [format!("a.c.{}", "a".repeat(HASH_LENGTH)), format!("a.c.{}", "b".repeat(HASH_LENGTH))]);
+        assert_eq!(fs::read_dir(&cache_path).unwrap().count(), 4);
+
+        fs::remove_dir_all(&cache_path).unwrap();
+    }
+}
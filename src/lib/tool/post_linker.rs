@@ -0,0 +1,222 @@
+use crate::config::{PostLink, SizeBudget, Strip, Toolchain};
+use crate::reporter::{Event, Reporter};
+use crate::std_command_ext::{CliStr, ExecuteCommand};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/** Section totals of a linked artifact, in bytes */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SectionSizes {
+    pub text: u64,
+    pub data: u64,
+    pub bss: u64,
+}
+
+impl SectionSizes {
+    /** Code, constants and the initial values of data */
+    pub fn flash(&self) -> u64 {
+        self.text + self.data
+    }
+
+    pub fn ram(&self) -> u64 {
+        self.data + self.bss
+    }
+}
+
+/**
+    Runs the post-link steps of the toolchain or profile on a linked artifact:
+    the size report, the objcopy conversions next to the artifact, then strip.
+ */
+pub struct PostLinker<'a> {
+    toolchain: &'a Toolchain,
+    reporter: &'a dyn Reporter,
+    timeout: Option<Duration>,
+}
+
+impl<'a> PostLinker<'a> {
+    pub fn new(toolchain: &'a Toolchain, reporter: &'a dyn Reporter, timeout: Option<Duration>) -> Self {
+        Self {
+            toolchain,
+            reporter,
+            timeout,
+        }
+    }
+
+    /**
+        @param: name - project or dependency name, used in the build output
+
+        @return: the files the steps wrote next to the artifact
+     */
+    pub fn run(&self, name: &str, artifact: &Path, post_link: &PostLink) -> Result<Vec<PathBuf>> {
+        let mut outputs = vec![];
+
+        if let Some(budget) = &post_link.size {
+            self.size(name, artifact, budget)?;
+        }
+
+        for format in &post_link.objcopy {
+            let output = artifact.with_extension(format.extension());
+
+            let mut command = Command::new(self.toolchain.binutil("objcopy"));
+            command.args(["-O", format.bfd_name()]).arg(artifact).arg(&output);
+
+            self.execute(name, "objcopy", &output, command)?;
+            outputs.push(output);
+        }
+
+        if let Some(strip) = &post_link.strip {
+            outputs.extend(self.strip(name, artifact, strip)?);
+        }
+
+        Ok(outputs)
+    }
+
+    fn size(&self, name: &str, artifact: &Path, budget: &SizeBudget) -> Result<()> {
+        let program = self.toolchain.binutil("size");
+
+        let stdout = Command::new(&program)
+            .args(["-B", "-d"])
+            .arg(artifact)
+            .execute(true)
+            .with_context(|| format!("Failed to run '{}' on {:?}", program, artifact))?;
+
+        let sizes = parse_size(&stdout)
+            .with_context(|| format!("Failed to parse the output of '{}':\n{}", program, stdout))?;
+
+        self.reporter.report(Event::SizeReport {
+            name: name.to_string(),
+            text: sizes.text,
+            data: sizes.data,
+            bss: sizes.bss,
+            flash: sizes.flash(),
+            ram: sizes.ram(),
+            flash_budget: budget.flash,
+            ram_budget: budget.ram,
+        });
+
+        for (memory, used, budget) in [("Flash", sizes.flash(), budget.flash), ("RAM", sizes.ram(), budget.ram)] {
+            if let Some(budget) = budget {
+                if used > budget {
+                    anyhow::bail!(
+                        "{} usage of {} bytes exceeds the budget of {} bytes by {} bytes",
+                        memory, used, budget, used - budget
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /* with split_debug, the debug information is copied out first and the stripped artifact links to it */
+    fn strip(&self, name: &str, artifact: &Path, strip: &Strip) -> Result<Option<PathBuf>> {
+        let objcopy = self.toolchain.binutil("objcopy");
+
+        let debug_file = if strip.split_debug {
+            let debug_file = artifact.with_extension("debug");
+
+            let mut command = Command::new(&objcopy);
+            command.arg("--only-keep-debug").arg(artifact).arg(&debug_file);
+
+            self.execute(name, "objcopy", &debug_file, command)?;
+            Some(debug_file)
+        } else {
+            None
+        };
+
+        let mut command = Command::new(self.toolchain.binutil("strip"));
+        command.arg(artifact);
+
+        self.execute(name, "strip", artifact, command)?;
+
+        if let Some(debug_file) = &debug_file {
+            let mut command = Command::new(&objcopy);
+            command.arg(format!("--add-gnu-debuglink={}", debug_file.display())).arg(artifact);
+
+            self.execute(name, "objcopy", artifact, command)?;
+        }
+
+        Ok(debug_file)
+    }
+
+    fn execute(&self, name: &str, step: &str, output_path: &Path, mut command: Command) -> Result<()> {
+        self.reporter.report(Event::PostLinkStarted {
+            name: name.to_string(),
+            step: step.to_string(),
+            output: output_path.to_path_buf(),
+            command: command.cli_args(),
+        });
+
+        log::trace!("{}", command.cli_str());
+
+        let output = command.stream(self.timeout, &mut |stream, line| {
+            self.reporter.report(Event::Output {
+                job: name.to_string(),
+                stream,
+                line: line.to_string(),
+            });
+        });
+
+        let success = output.as_ref().is_ok_and(|output| output.success);
+        self.reporter.report(Event::PostLinkFinished {
+            name: name.to_string(),
+            step: step.to_string(),
+            output: output_path.to_path_buf(),
+            success,
+        });
+
+        let output = output?;
+        if !output.success {
+            anyhow::bail!("{:?} {}", command.get_program(), output.status);
+        }
+
+        Ok(())
+    }
+}
+
+/*
+   text    data     bss     dec     hex filename
+   1234     560      16    1810     712 app
+ */
+fn parse_size(stdout: &str) -> Result<SectionSizes> {
+    let line = stdout
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with(|char: char| char.is_ascii_digit()))
+        .ok_or_else(|| anyhow::anyhow!("No section totals found"))?;
+
+    let totals = line
+        .split_whitespace()
+        .take(3)
+        .map(str::parse::<u64>)
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid section totals")?;
+
+    let [text, data, bss] = <[u64; 3]>::try_from(totals)
+        .map_err(|_| anyhow::anyhow!("Expected text, data and bss totals in '{}'", line))?;
+
+    Ok(SectionSizes { text, data, bss })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_reads_the_berkeley_totals() {
+        let stdout = "   text    data     bss     dec     hex filename\n   1234     560      16    1810     712 app\n";
+        let sizes = parse_size(stdout).unwrap();
+
+        assert_eq!(sizes, SectionSizes { text: 1234, data: 560, bss: 16 });
+        assert_eq!(sizes.flash(), 1794);
+        assert_eq!(sizes.ram(), 576);
+    }
+
+    #[test]
+    fn parse_size_without_totals_is_an_error() {
+        assert!(parse_size("   text    data     bss     dec     hex filename\n").is_err());
+        assert!(parse_size("   1234     560\n").is_err());
+    }
+}
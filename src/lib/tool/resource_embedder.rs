@@ -0,0 +1,346 @@
+use crate::config::{Compression, Resource};
+use crate::tool::files_fetcher;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/* bytes per line of the generated arrays */
+const BYTES_PER_LINE: usize = 12;
+
+/* miniz levels go from 0 to 10, 9 compresses as well as zlib's best */
+const COMPRESSION_LEVEL: u8 = 9;
+
+const ALIGNED_MACRO: &str = r###"
+#ifndef CORGE_ALIGNED
+#if defined(_MSC_VER)
+#define CORGE_ALIGNED(bytes) __declspec(align(bytes))
+#else
+#define CORGE_ALIGNED(bytes) __attribute__((aligned(bytes)))
+#endif
+#endif
+"###;
+
+/**
+    Writes the resources of a project or dependency as C arrays: <name>.c and <name>.h in the output directory.
+    A file is only rewritten when its content changes, so unchanged resources don't cause recompilations.
+ */
+pub struct ResourceEmbedder {
+    project_path: PathBuf,
+    output_path: PathBuf,
+}
+
+impl ResourceEmbedder {
+    pub fn new(project_path: &Path, output_path: &Path) -> Self {
+        Self {
+            project_path: project_path.to_path_buf(),
+            output_path: output_path.to_path_buf(),
+        }
+    }
+
+    /** @return: the generated C files */
+    pub fn embed(&self, resources: &[Resource]) -> Result<Vec<PathBuf>> {
+        let mut sources = vec![];
+
+        for resource in resources {
+            let source = self.embed_resource(resource)
+                .with_context(|| format!("Failed to embed resources '{}'", resource.name))?;
+
+            sources.push(source);
+        }
+
+        Ok(sources)
+    }
+
+    fn embed_resource(&self, resource: &Resource) -> Result<PathBuf> {
+        if resource.align.is_some_and(|align| !align.is_power_of_two()) {
+            anyhow::bail!("Alignment {} is not a power of two", resource.align.unwrap_or_default());
+        }
+
+        /* file, array content and length of the file, by array name */
+        let mut arrays: BTreeMap<String, (PathBuf, Vec<u8>, usize)> = BTreeMap::new();
+
+        for file in self.files(resource)? {
+            let content = fs::read(&file)
+                .with_context(|| format!("Failed to read resource {:?}", &file))?;
+            let length = content.len();
+
+            let mut data = match resource.compression {
+                None => content,
+                Some(Compression::Deflate) => miniz_oxide::deflate::compress_to_vec(&content, COMPRESSION_LEVEL),
+                Some(Compression::Zlib) => miniz_oxide::deflate::compress_to_vec_zlib(&content, COMPRESSION_LEVEL),
+            };
+
+            if resource.nul_terminate {
+                data.push(0);
+            }
+
+            let identifier = identifier(file.strip_prefix(&self.project_path).unwrap_or(&file));
+
+            if let Some((other, ..)) = arrays.get(&identifier) {
+                anyhow::bail!("Resources {:?} and {:?} have the same name '{}'", other, &file, identifier);
+            }
+
+            arrays.insert(identifier, (file, data, length));
+        }
+
+        let header_name = format!("{}.h", resource.name);
+        let guard = format!("{}_H", identifier(Path::new(&resource.name)).to_ascii_uppercase());
+
+        let mut header = String::new();
+        writeln!(header, "/* Generated by corge-rs from the resources of build.yaml, do not edit */")?;
+        writeln!(header, "#ifndef {}", guard)?;
+        writeln!(header, "#define {}", guard)?;
+        writeln!(header)?;
+        writeln!(header, "#include <stddef.h>")?;
+        writeln!(header)?;
+        writeln!(header, "#ifdef __cplusplus")?;
+        writeln!(header, "extern \"C\" {{")?;
+        writeln!(header, "#endif")?;
+
+        let mut source = String::new();
+        writeln!(source, "/* Generated by corge-rs from the resources of build.yaml, do not edit */")?;
+        writeln!(source, "#include \"{}\"", header_name)?;
+
+        let alignment = match resource.align {
+            Some(align) => {
+                source.push_str(ALIGNED_MACRO);
+                format!("CORGE_ALIGNED({}) ", align)
+            }
+            None => String::new(),
+        };
+
+        for (identifier, (file, data, length)) in &arrays {
+            let relative_path = file.strip_prefix(&self.project_path).unwrap_or(file);
+
+            writeln!(header)?;
+            writeln!(header, "/* {} */", relative_path.display())?;
+            writeln!(header, "extern const unsigned char {}[];", identifier)?;
+
+            match resource.compression {
+                Some(_) => {
+                    writeln!(header, "/* length of the compressed data */")?;
+                    writeln!(header, "extern const size_t {}_len;", identifier)?;
+                    writeln!(header, "extern const size_t {}_uncompressed_len;", identifier)?;
+                }
+                None => writeln!(header, "extern const size_t {}_len;", identifier)?,
+            }
+
+            let stored_length = data.len() - usize::from(resource.nul_terminate);
+
+            writeln!(source)?;
+            writeln!(source, "{}const unsigned char {}[] = {{", alignment, identifier)?;
+
+            /* C doesn't allow an empty initializer */
+            let bytes: &[u8] = if data.is_empty() { &[0] } else { data };
+            for line in bytes.chunks(BYTES_PER_LINE) {
+                let line: Vec<String> = line.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+                writeln!(source, "    {},", line.join(", "))?;
+            }
+
+            writeln!(source, "}};")?;
+            writeln!(source, "const size_t {}_len = {};", identifier, stored_length)?;
+
+            if resource.compression.is_some() {
+                writeln!(source, "const size_t {}_uncompressed_len = {};", identifier, length)?;
+            }
+        }
+
+        writeln!(header)?;
+        writeln!(header, "#ifdef __cplusplus")?;
+        writeln!(header, "}}")?;
+        writeln!(header, "#endif")?;
+        writeln!(header)?;
+        writeln!(header, "#endif")?;
+
+        let source_path = self.output_path.join(format!("{}.c", resource.name));
+
+        write_if_changed(&self.output_path.join(header_name), &header)?;
+        write_if_changed(&source_path, &source)?;
+
+        Ok(source_path)
+    }
+
+    /* files or glob patterns; a file that doesn't exist is an error, a pattern may match nothing */
+    fn files(&self, resource: &Resource) -> Result<Vec<PathBuf>> {
+        let mut files = vec![];
+
+        for file in &resource.files {
+            let pattern = files_fetcher::project_pattern(&self.project_path, file);
+            let paths = glob::glob(&pattern)
+                .with_context(|| format!("Invalid resource pattern '{}'", file))?;
+
+            let mut matched = false;
+
+            for path in paths {
+                let path = path?;
+
+                if path.is_file() {
+                    files.push(path);
+                    matched = true;
+                }
+            }
+
+            if !matched && !file.contains(['*', '?', '[']) {
+                anyhow::bail!("Resource '{}' doesn't exist", file);
+            }
+        }
+
+        files.sort();
+        files.dedup();
+
+        Ok(files)
+    }
+}
+
+/* "web/index.html" -> "web_index_html", a C identifier */
+fn identifier(path: &Path) -> String {
+    let path = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let identifier: String = path
+        .chars()
+        .map(|char| if char.is_ascii_alphanumeric() { char } else { '_' })
+        .collect();
+
+    if identifier.starts_with(|char: char| char.is_ascii_digit()) {
+        format!("_{}", identifier)
+    } else {
+        identifier
+    }
+}
+
+fn write_if_changed(path: &Path, content: &str) -> Result<()> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+
+    log::info!("Writing embedded resources {:?}", path);
+
+    fs::write(path, content)
+        .with_context(|| format!("Failed to write {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(yaml: &str) -> Resource {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn project(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let project_path = std::env::temp_dir().join(format!("corge-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&project_path);
+
+        for (file, content) in files {
+            let file_path = project_path.join(file);
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(file_path, content).unwrap();
+        }
+
+        project_path
+    }
+
+    /* the generated .c and .h of the resource */
+    fn embed(project_path: &Path, resource: &Resource) -> (String, String) {
+        let output_path = project_path.join("out");
+        ResourceEmbedder::new(project_path, &output_path).embed(std::slice::from_ref(resource)).unwrap();
+
+        (
+            fs::read_to_string(output_path.join(format!("{}.c", resource.name))).unwrap(),
+            fs::read_to_string(output_path.join(format!("{}.h", resource.name))).unwrap(),
+        )
+    }
+
+    /* the bytes of an array of the generated source */
+    fn array(source: &str, identifier: &str) -> Vec<u8> {
+        let start = source.find(&format!("unsigned char {}[] = {{", identifier)).unwrap();
+        let body = &source[start..];
+        let body = &body[body.find('{').unwrap() + 1..body.find("};").unwrap()];
+
+        body.split(',')
+            .map(str::trim)
+            .filter(|byte| !byte.is_empty())
+            .map(|byte| u8::from_str_radix(byte.trim_start_matches("0x"), 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn identifiers_are_c_identifiers() {
+        assert_eq!(identifier(Path::new("web/index.html")), "web_index_html");
+        assert_eq!(identifier(Path::new("fonts/sans-serif.ttf")), "fonts_sans_serif_ttf");
+        assert_eq!(identifier(Path::new("3d/cube.obj")), "_3d_cube_obj");
+        assert_eq!(identifier(Path::new("web\\index.html")), "web_index_html");
+    }
+
+    #[test]
+    fn arrays_are_aligned_and_nul_terminated() {
+        let project_path = project("resource-text", &[("text/hello.txt", b"hello"), ("3d/cube.obj", b"")]);
+
+        let (source, header) = embed(&project_path, &resource("{ name: assets, files: [text/hello.txt, 3d/cube.obj], align: 16, nul_terminate: true }"));
+
+        assert!(source.contains(ALIGNED_MACRO));
+        assert!(source.contains("CORGE_ALIGNED(16) const unsigned char text_hello_txt[] = {"));
+        assert_eq!(array(&source, "text_hello_txt"), b"hello\0");
+        assert!(source.contains("const size_t text_hello_txt_len = 5;"));
+
+        /* an empty file is only its terminator */
+        assert_eq!(array(&source, "_3d_cube_obj"), [0]);
+        assert!(source.contains("const size_t _3d_cube_obj_len = 0;"));
+
+        assert!(header.contains("#ifndef ASSETS_H"));
+        assert!(header.contains("extern const unsigned char text_hello_txt[];"));
+        assert!(header.contains("extern const size_t text_hello_txt_len;"));
+        assert!(!header.contains("_uncompressed_len"));
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn alignment_must_be_a_power_of_two() {
+        let project_path = project("resource-align", &[("data.bin", b"\x01\x02")]);
+
+        let error = ResourceEmbedder::new(&project_path, &project_path.join("out"))
+            .embed(&[resource("{ name: data, files: [data.bin], align: 12 }")])
+            .unwrap_err();
+        assert_eq!(format!("{:#}", error), "Failed to embed resources 'data': Alignment 12 is not a power of two");
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+
+    #[test]
+    fn compressed_resources_inflate_to_the_file() {
+        let content = "<html><body>corge corge corge corge corge corge corge corge</body></html>\n".repeat(20);
+        let project_path = project("resource-compressed", &[("web/index.html", content.as_bytes())]);
+
+        let (source, header) = embed(&project_path, &resource("{ name: web, files: [web/index.html], compression: Deflate }"));
+        let data = array(&source, "web_index_html");
+
+        assert!(data.len() < content.len());
+        assert_eq!(miniz_oxide::inflate::decompress_to_vec(&data).unwrap(), content.as_bytes());
+        assert!(source.contains(&format!("const size_t web_index_html_len = {};", data.len())));
+        assert!(source.contains(&format!("const size_t web_index_html_uncompressed_len = {};", content.len())));
+        assert!(header.contains("extern const size_t web_index_html_len;"));
+        assert!(header.contains("extern const size_t web_index_html_uncompressed_len;"));
+
+        /* the terminator follows the compressed data and isn't counted */
+        let (source, _) = embed(&project_path, &resource("{ name: web, files: [web/index.html], compression: Zlib, nul_terminate: true }"));
+        let data = array(&source, "web_index_html");
+
+        assert_eq!(data.last(), Some(&0));
+        assert_eq!(miniz_oxide::inflate::decompress_to_vec_zlib(&data[..data.len() - 1]).unwrap(), content.as_bytes());
+        assert!(source.contains(&format!("const size_t web_index_html_len = {};", data.len() - 1)));
+
+        fs::remove_dir_all(&project_path).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+use crate::tool::diagnostic_parser::{Diagnostic, Severity};
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+const SARIF_VERSION: &str = "2.1.0";
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/**
+    Writes diagnostics as a SARIF 2.1.0 log with a single run.
+    Warning options become rules, notes become related locations.
+ */
+pub fn write(path: &Path, diagnostics: &[Diagnostic]) -> Result<()> {
+    let rules: BTreeSet<&str> = diagnostics
+        .iter()
+        .filter_map(|diagnostic| diagnostic.option.as_deref())
+        .collect();
+
+    let sarif = json!({
+        "version": SARIF_VERSION,
+        "$schema": SARIF_SCHEMA,
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.iter().map(|rule| json!({ "id": rule })).collect::<Vec<_>>(),
+                }
+            },
+            "results": diagnostics.iter().map(result).collect::<Vec<_>>(),
+        }]
+    });
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+
+    let serialized = serde_json::to_string_pretty(&sarif)?;
+    fs::write(path, serialized)
+        .with_context(|| format!("Failed to write SARIF file {:?}", path))
+}
+
+fn result(diagnostic: &Diagnostic) -> Value {
+    let mut result = json!({
+        "level": level(diagnostic.severity),
+        "message": { "text": diagnostic.message },
+        "locations": [location(diagnostic)],
+    });
+
+    if let Some(option) = &diagnostic.option {
+        result["ruleId"] = json!(option);
+    }
+
+    if !diagnostic.notes.is_empty() {
+        result["relatedLocations"] = diagnostic.notes
+            .iter()
+            .map(|note| {
+                let mut location = location(note);
+                location["message"] = json!({ "text": note.message });
+
+                location
+            })
+            .collect();
+    }
+
+    result
+}
+
+fn location(diagnostic: &Diagnostic) -> Value {
+    let mut physical_location = json!({
+        "artifactLocation": {
+            "uri": diagnostic.file.to_string_lossy().replace('\\', "/"),
+        }
+    });
+
+    if let Some(line) = diagnostic.line {
+        let mut region = json!({ "startLine": line });
+
+        if let Some(column) = diagnostic.column {
+            region["startColumn"] = json!(column);
+        }
+
+        physical_location["region"] = region;
+    }
+
+    json!({ "physicalLocation": physical_location })
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+    }
+}
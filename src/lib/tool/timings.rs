@@ -0,0 +1,436 @@
+use crate::reporter::{Event, Reporter};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/* number of translation units listed as the slowest ones */
+const SLOWEST_COUNT: usize = 10;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobKind {
+    Fetch,
+    /// The dependency was already fetched.
+    FetchCached,
+    Compile,
+    /// The object file was reused.
+    CacheHit,
+    Generate,
+    Link,
+    PostLink,
+}
+
+/** One step of the build on the timeline, in seconds since the build started */
+#[derive(Serialize, Debug, Clone)]
+pub struct Job {
+    pub kind: JobKind,
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub start: f64,
+    pub duration: f64,
+    /// Row of the timeline, jobs in different lanes ran at the same time.
+    pub lane: usize,
+    pub success: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Timings {
+    pub build_mode: String,
+    pub target: Option<String>,
+    pub duration: f64,
+    pub jobs: Vec<Job>,
+    /// Compilations, slowest first.
+    pub slowest: Vec<Job>,
+    /// Wall-clock seconds per compiler phase and source file, from GCC's -ftime-report.
+    pub time_report: BTreeMap<PathBuf, BTreeMap<String, f64>>,
+}
+
+#[derive(Default)]
+struct Recording {
+    /* started jobs by their key, waiting for the event that finishes them */
+    running: HashMap<(JobKind, PathBuf), (String, Instant)>,
+    jobs: Vec<Job>,
+    time_report: BTreeMap<PathBuf, BTreeMap<String, f64>>,
+}
+
+/**
+    Passes events on to another reporter and measures how long the jobs they describe take.
+ */
+pub struct TimingRecorder<'a> {
+    reporter: &'a dyn Reporter,
+    started: Instant,
+    recording: Mutex<Recording>,
+}
+
+impl<'a> TimingRecorder<'a> {
+    pub fn new(reporter: &'a dyn Reporter) -> Self {
+        Self {
+            reporter,
+            started: Instant::now(),
+            recording: Mutex::new(Recording::default()),
+        }
+    }
+
+    pub fn into_timings(self, build_mode: &str, target: Option<&str>) -> Timings {
+        let duration = self.started.elapsed().as_secs_f64();
+        let recording = self.recording.into_inner().unwrap_or_else(|error| error.into_inner());
+
+        let mut jobs = recording.jobs;
+        jobs.sort_by(|a, b| a.start.total_cmp(&b.start));
+        assign_lanes(&mut jobs);
+
+        let mut slowest: Vec<Job> = jobs.iter().filter(|job| job.kind == JobKind::Compile).cloned().collect();
+        slowest.sort_by(|a, b| b.duration.total_cmp(&a.duration));
+        slowest.truncate(SLOWEST_COUNT);
+
+        Timings {
+            build_mode: build_mode.to_string(),
+            target: target.map(str::to_string),
+            duration,
+            jobs,
+            slowest,
+            time_report: recording.time_report,
+        }
+    }
+
+    fn seconds(&self, instant: Instant) -> f64 {
+        instant.duration_since(self.started).as_secs_f64()
+    }
+
+    fn start(&self, kind: JobKind, key: PathBuf, name: String) {
+        let mut recording = self.recording.lock().unwrap_or_else(|error| error.into_inner());
+        recording.running.insert((kind, key), (name, Instant::now()));
+    }
+
+    fn finish(&self, kind: JobKind, key: PathBuf, path: Option<PathBuf>, success: bool) {
+        let now = Instant::now();
+        let mut recording = self.recording.lock().unwrap_or_else(|error| error.into_inner());
+
+        let Some((name, started)) = recording.running.remove(&(kind, key)) else {
+            return;
+        };
+
+        recording.jobs.push(Job {
+            kind,
+            name,
+            path,
+            start: self.seconds(started),
+            duration: now.duration_since(started).as_secs_f64(),
+            lane: 0,
+            success,
+        });
+    }
+
+    /* a job that took no measurable time, e.g. a cache hit */
+    fn instant(&self, kind: JobKind, name: String, path: PathBuf) {
+        let start = self.seconds(Instant::now());
+        let mut recording = self.recording.lock().unwrap_or_else(|error| error.into_inner());
+
+        recording.jobs.push(Job {
+            kind,
+            name,
+            path: Some(path),
+            start,
+            duration: 0.0,
+            lane: 0,
+            success: true,
+        });
+    }
+}
+
+impl Reporter for TimingRecorder<'_> {
+    fn report(&self, event: Event) {
+        match &event {
+            Event::DependencyFetchStarted { name, .. } => {
+                self.start(JobKind::Fetch, PathBuf::from(name), name.clone());
+            }
+            Event::DependencyFetchFinished { name, path, fresh: false } => {
+                self.finish(JobKind::Fetch, PathBuf::from(name), Some(path.clone()), true);
+            }
+            Event::DependencyFetchFinished { name, path, fresh: true } => {
+                self.instant(JobKind::FetchCached, name.clone(), path.clone());
+            }
+            Event::CompileStarted { source, object, .. } => {
+                self.start(JobKind::Compile, object.clone(), source.display().to_string());
+            }
+            Event::CompileFinished { source, object, success } => {
+                self.finish(JobKind::Compile, object.clone(), Some(source.clone()), *success);
+            }
+            Event::CacheHit { source, .. } => {
+                self.instant(JobKind::CacheHit, source.display().to_string(), source.clone());
+            }
+            Event::GenerateStarted { name, .. } => {
+                self.start(JobKind::Generate, PathBuf::from(name), name.clone());
+            }
+            Event::GenerateFinished { name, success } => {
+                self.finish(JobKind::Generate, PathBuf::from(name), None, *success);
+            }
+            Event::LinkStarted { name, output, .. } => {
+                self.start(JobKind::Link, output.clone(), name.clone());
+            }
+            Event::LinkFinished { output, success, .. } => {
+                self.finish(JobKind::Link, output.clone(), Some(output.clone()), *success);
+            }
+            Event::PostLinkStarted { name, output, .. } => {
+                self.start(JobKind::PostLink, output.clone(), name.clone());
+            }
+            Event::PostLinkFinished { output, success, .. } => {
+                self.finish(JobKind::PostLink, output.clone(), Some(output.clone()), *success);
+            }
+            Event::TimeReport { source, phases } => {
+                let mut recording = self.recording.lock().unwrap_or_else(|error| error.into_inner());
+                recording.time_report.insert(source.clone(), phases.clone());
+            }
+            _ => {}
+        }
+
+        self.reporter.report(event);
+    }
+}
+
+/* Puts every job into the first lane that is free when it starts */
+fn assign_lanes(jobs: &mut [Job]) {
+    let mut lane_ends: Vec<f64> = vec![];
+
+    for job in jobs {
+        let lane = match lane_ends.iter().position(|end| *end <= job.start) {
+            Some(lane) => lane,
+            None => {
+                lane_ends.push(0.0);
+                lane_ends.len() - 1
+            }
+        };
+
+        lane_ends[lane] = job.start + job.duration;
+        job.lane = lane;
+    }
+}
+
+/**
+    Parses the table GCC writes to stderr with -ftime-report, e.g.
+    ` phase parsing                      :   0.01 ( 50%)   0.00 (  0%)   0.02 ( 40%)  1326k ( 87%)`
+
+    @return: wall-clock seconds per phase
+ */
+pub fn parse_time_report(output: &str) -> BTreeMap<String, f64> {
+    let mut phases = BTreeMap::new();
+
+    for (name, wall) in output.lines().filter_map(time_report_phase) {
+        if name != "TOTAL" {
+            *phases.entry(name.to_string()).or_insert(0.0) += wall;
+        }
+    }
+
+    phases
+}
+
+/** Whether a line of compiler output belongs to the -ftime-report table, which is reported parsed instead */
+pub fn is_time_report_line(line: &str) -> bool {
+    let line = line.trim();
+
+    line.is_empty() ||
+        line.starts_with("Time variable") ||
+        line.starts_with("Execution times") ||
+        time_report_phase(line).is_some()
+}
+
+/* the phase name and its wall time, skipping the usr and sys times, percentages and memory */
+fn time_report_phase(line: &str) -> Option<(&str, f64)> {
+    let (name, columns) = line.split_once(" : ")?;
+
+    let times: Vec<f64> = columns
+        .split_whitespace()
+        .filter_map(|column| column.parse::<f64>().ok())
+        .collect();
+
+    let name = name.trim();
+    let wall = *times.get(2)?;
+
+    (!name.is_empty()).then_some((name, wall))
+}
+
+impl Timings {
+    /** Writes timings.json and timings.html into the directory */
+    pub fn write(&self, directory: &Path) -> Result<(PathBuf, PathBuf)> {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Failed to create directory {:?}", directory))?;
+
+        let json_path = directory.join("timings.json");
+        fs::write(&json_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {:?}", &json_path))?;
+
+        let html_path = directory.join("timings.html");
+        fs::write(&html_path, self.html())
+            .with_context(|| format!("Failed to write {:?}", &html_path))?;
+
+        Ok((json_path, html_path))
+    }
+
+    fn html(&self) -> String {
+        let lanes = self.jobs.iter().map(|job| job.lane + 1).max().unwrap_or(1);
+        /* keep short builds readable */
+        let scale = 100.0 / self.duration.max(0.001);
+
+        let mut bars = String::new();
+        for job in &self.jobs {
+            bars.push_str(&format!(
+                "<div class=\"job {kind}{failed}\" style=\"left:{left:.3}%;width:{width:.3}%;top:{top}px\" title=\"{title}\"></div>\n",
+                kind = kind_class(job.kind),
+                failed = if job.success { "" } else { " failed" },
+                left = job.start * scale,
+                width = job.duration * scale,
+                top = job.lane * 24,
+                title = escape(&format!("{} ({}) {:.3}s", job.name, kind_class(job.kind), job.duration)),
+            ));
+        }
+
+        let mut slowest = String::new();
+        for job in &self.slowest {
+            slowest.push_str(&format!("<tr><td>{}</td><td>{:.3}</td></tr>\n", escape(&job.name), job.duration));
+        }
+
+        let mut phases: BTreeMap<&str, f64> = BTreeMap::new();
+        for file_phases in self.time_report.values() {
+            for (phase, seconds) in file_phases {
+                *phases.entry(phase.as_str()).or_insert(0.0) += seconds;
+            }
+        }
+        let mut phases: Vec<(&str, f64)> = phases.into_iter().collect();
+        phases.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut time_report = String::new();
+        for (phase, seconds) in phases {
+            time_report.push_str(&format!("<tr><td>{}</td><td>{:.3}</td></tr>\n", escape(phase), seconds));
+        }
+        if time_report.is_empty() {
+            time_report.push_str("<tr><td colspan=\"2\">Build with --time-report to collect compiler phases</td></tr>\n");
+        }
+
+        let count = |kind: JobKind| self.jobs.iter().filter(|job| job.kind == kind).count();
+
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>corge-rs build timings</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+.timeline {{ position: relative; height: {height}px; border: 1px solid #ccc; background: #fafafa; }}
+.job {{ position: absolute; height: 20px; min-width: 2px; border-radius: 2px; }}
+.fetch {{ background: #8e44ad; }} .fetch-cached {{ background: #d2b4de; }}
+.compile {{ background: #2e86c1; }} .cache-hit {{ background: #aed6f1; }}
+.generate {{ background: #f39c12; }} .link {{ background: #28b463; }} .post-link {{ background: #82e0aa; }} .failed {{ background: #e74c3c; }}
+table {{ border-collapse: collapse; margin-top: 1em; }}
+td, th {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; }}
+</style>
+</head>
+<body>
+<h1>Build timings</h1>
+<p>Profile {build_mode}{target}, {duration:.3}s: {compiles} compiled, {cache_hits} cached, {links} linked, {fetches} fetched</p>
+<p><span class="job fetch" style="position:static;display:inline-block;width:12px"></span> fetch
+<span class="job compile" style="position:static;display:inline-block;width:12px"></span> compile
+<span class="job cache-hit" style="position:static;display:inline-block;width:12px"></span> cache hit
+<span class="job generate" style="position:static;display:inline-block;width:12px"></span> generate
+<span class="job link" style="position:static;display:inline-block;width:12px"></span> link
+<span class="job post-link" style="position:static;display:inline-block;width:12px"></span> post-link
+<span class="job failed" style="position:static;display:inline-block;width:12px"></span> failed</p>
+<div class="timeline">
+{bars}</div>
+<h2>Slowest translation units</h2>
+<table>
+<tr><th>Source</th><th>Seconds</th></tr>
+{slowest}</table>
+<h2>Compiler phases</h2>
+<table>
+<tr><th>Phase</th><th>Seconds</th></tr>
+{time_report}</table>
+</body>
+</html>
+"#,
+            height = lanes * 24,
+            build_mode = escape(&self.build_mode),
+            target = self.target.as_deref().map(|target| format!(" for {}", escape(target))).unwrap_or_default(),
+            duration = self.duration,
+            compiles = count(JobKind::Compile),
+            cache_hits = count(JobKind::CacheHit),
+            links = count(JobKind::Link),
+            fetches = count(JobKind::Fetch),
+        )
+    }
+}
+
+fn kind_class(kind: JobKind) -> &'static str {
+    match kind {
+        JobKind::Fetch => "fetch",
+        JobKind::FetchCached => "fetch-cached",
+        JobKind::Compile => "compile",
+        JobKind::CacheHit => "cache-hit",
+        JobKind::Generate => "generate",
+        JobKind::Link => "link",
+        JobKind::PostLink => "post-link",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME_REPORT: &str = "\
+Time variable                                   usr           sys          wall           GGC
+ phase setup                        :   0.00 (  0%)   0.00 (  0%)   0.01 ( 20%)  1326k ( 87%)
+ phase parsing                      :   0.01 ( 50%)   0.00 (  0%)   0.02 ( 40%)   183k ( 12%)
+ phase parsing                      :   0.00 (  0%)   0.01 (100%)   0.01 ( 20%)     0  (  0%)
+ TOTAL                              :   0.02          0.01          0.05          1521k
+src/main.c:4:9: warning: unused variable 'x' [-Wunused-variable]
+";
+
+    #[test]
+    fn parse_time_report_sums_wall_time_per_phase() {
+        let phases = parse_time_report(TIME_REPORT);
+
+        assert_eq!(phases.len(), 2);
+        assert_eq!(phases["phase setup"], 0.01);
+        assert!((phases["phase parsing"] - 0.03).abs() < 1e-9);
+    }
+
+    #[test]
+    fn time_report_lines_are_recognized() {
+        let lines: Vec<bool> = TIME_REPORT.lines().map(is_time_report_line).collect();
+
+        assert_eq!(lines, [true, true, true, true, true, false]);
+        assert!(is_time_report_line(""));
+        assert!(is_time_report_line("Execution times (seconds)"));
+        assert!(!is_time_report_line("ld: cannot find -lfoo"));
+    }
+
+    #[test]
+    fn overlapping_jobs_get_separate_lanes() {
+        let job = |start: f64, duration: f64| Job {
+            kind: JobKind::Compile,
+            name: String::new(),
+            path: None,
+            start,
+            duration,
+            lane: 0,
+            success: true,
+        };
+
+        let mut jobs = vec![job(0.0, 2.0), job(1.0, 2.0), job(2.0, 1.0), job(2.5, 1.0)];
+        assign_lanes(&mut jobs);
+
+        let lanes: Vec<usize> = jobs.iter().map(|job| job.lane).collect();
+        assert_eq!(lanes, [0, 1, 0, 2]);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::config::{Toolchain, ValueOrigin};
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+
+/* the host's build variables, a cross toolchain takes its own CORGE_<TOOLCHAIN>_ ones */
+const GENERIC_VARIABLES: [&str; 5] = ["CC", "AR", "CPPFLAGS", "CFLAGS", "LDFLAGS"];
+
+#[derive(Debug, Clone)]
+pub struct Sourced<T> {
+    pub value: T,
+    pub origin: ValueOrigin,
+}
+
+impl<T> Sourced<T> {
+    fn new(value: T, origin: ValueOrigin) -> Self {
+        Self {
+            value,
+            origin,
+        }
+    }
+}
+
+/**
+    A toolchain after the environment has been layered on top of it,
+    remembering where every value comes from.
+ */
+#[derive(Debug, Clone)]
+pub struct EffectiveToolchain {
+    pub name: String,
+    pub base: Toolchain,
+    pub compiler: Sourced<String>,
+    pub archiver: Sourced<String>,
+    pub compiler_flags: Vec<Sourced<String>>,
+    pub linker_flags: Vec<Sourced<String>>,
+    pub sysroot: Option<Sourced<String>>,
+}
+
+impl EffectiveToolchain {
+    pub fn toolchain(&self) -> Toolchain {
+        Toolchain {
+            family: self.base.family,
+            compiler: self.compiler.value.clone(),
+            archiver: self.archiver.value.clone(),
+            compiler_flags: self.compiler_flags.iter().map(|flag| flag.value.clone()).collect(),
+            linker_flags: self.linker_flags.iter().map(|flag| flag.value.clone()).collect(),
+            sysroot: self.sysroot.as_ref().map(|sysroot| sysroot.value.clone()),
+            post_link: self.base.post_link.clone(),
+        }
+    }
+}
+
+impl Display for EffectiveToolchain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "toolchain: {}", self.name)?;
+        writeln!(f, "compiler: {} ({})", self.compiler.value, self.compiler.origin)?;
+        writeln!(f, "archiver: {} ({})", self.archiver.value, self.archiver.origin)?;
+
+        match &self.sysroot {
+            Some(sysroot) => writeln!(f, "sysroot: {} ({})", sysroot.value, sysroot.origin)?,
+            None => writeln!(f, "sysroot: none")?,
+        }
+
+        for (title, flags) in [("compiler flags", &self.compiler_flags), ("linker flags", &self.linker_flags)] {
+            if flags.is_empty() {
+                writeln!(f, "{}: none", title)?;
+                continue;
+            }
+
+            writeln!(f, "{}:", title)?;
+            for flag in flags {
+                writeln!(f, "  {} ({})", flag.value, flag.origin)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/**
+    Layers environment variables on top of a toolchain, lowest precedence first:
+    1. the toolchain from build.yaml (or the built-in default)
+    2. CC, AR, CFLAGS, CPPFLAGS, LDFLAGS, only for the built-in default, which builds for the host
+    3. CORGE_<TOOLCHAIN>_CC, _AR, _CFLAGS, _CPPFLAGS, _LDFLAGS, e.g. CORGE_PIC24_CC
+
+    Tools are replaced, flags are appended. A toolchain given on the command line is used as is.
+ */
+pub struct ToolchainEnvironment {
+    variables: HashMap<String, String>,
+}
+
+impl ToolchainEnvironment {
+    pub fn new(variables: HashMap<String, String>) -> Self {
+        Self {
+            variables,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(env::vars().collect())
+    }
+
+    pub fn apply(&self, name: &str, toolchain: Toolchain, origin: ValueOrigin) -> EffectiveToolchain {
+        let mut effective = EffectiveToolchain {
+            name: name.to_string(),
+            compiler: Sourced::new(toolchain.compiler.clone(), origin.clone()),
+            archiver: Sourced::new(toolchain.archiver.clone(), origin.clone()),
+            compiler_flags: toolchain.compiler_flags
+                .iter()
+                .map(|flag| Sourced::new(flag.clone(), origin.clone()))
+                .collect(),
+            linker_flags: toolchain.linker_flags
+                .iter()
+                .map(|flag| Sourced::new(flag.clone(), origin.clone()))
+                .collect(),
+            sysroot: toolchain.sysroot
+                .clone()
+                .map(|sysroot| Sourced::new(sysroot, origin.clone())),
+            base: toolchain,
+        };
+
+        if origin == ValueOrigin::CommandLine {
+            return effective;
+        }
+
+        let prefix = format!("CORGE_{}_", variable_name(name));
+
+        /* a host compiler and its flags would break a toolchain of build.yaml or one derived from --target */
+        let prefixes = if origin == ValueOrigin::BuiltIn {
+            vec!["", prefix.as_str()]
+        } else {
+            for variable in GENERIC_VARIABLES.iter().filter(|variable| self.variable(variable).is_some()) {
+                log::warn!("{} is ignored for toolchain '{}', set {}{} to apply it", variable, name, prefix, variable);
+            }
+
+            vec![prefix.as_str()]
+        };
+
+        for prefix in prefixes {
+            if let Some(compiler) = self.variable(&format!("{}CC", prefix)) {
+                effective.compiler = compiler;
+            }
+
+            if let Some(archiver) = self.variable(&format!("{}AR", prefix)) {
+                effective.archiver = archiver;
+            }
+
+            for variable in ["CPPFLAGS", "CFLAGS"] {
+                effective.compiler_flags.extend(self.flags(&format!("{}{}", prefix, variable)));
+            }
+
+            effective.linker_flags.extend(self.flags(&format!("{}LDFLAGS", prefix)));
+        }
+
+        effective
+    }
+
+    fn variable(&self, variable: &str) -> Option<Sourced<String>> {
+        self.variables
+            .get(variable)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| Sourced::new(value.to_string(), ValueOrigin::Environment(variable.to_string())))
+    }
+
+    fn flags(&self, variable: &str) -> Vec<Sourced<String>> {
+        self.variable(variable)
+            .map(|flags| {
+                flags.value
+                    .split_whitespace()
+                    .map(|flag| Sourced::new(flag.to_string(), flags.origin.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/* "arm-none" -> "ARM_NONE" */
+fn variable_name(toolchain_name: &str) -> String {
+    toolchain_name
+        .chars()
+        .map(|char| if char.is_ascii_alphanumeric() { char.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment(variables: &[(&str, &str)]) -> ToolchainEnvironment {
+        ToolchainEnvironment::new(variables.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect())
+    }
+
+    fn toolchain() -> Toolchain {
+        Toolchain {
+            compiler: "arm-none-eabi-gcc".to_string(),
+            archiver: "arm-none-eabi-ar".to_string(),
+            compiler_flags: vec!["-mthumb".to_string()],
+            linker_flags: vec!["-nostdlib".to_string()],
+            sysroot: Some("sysroots/arm".to_string()),
+            ..Toolchain::default()
+        }
+    }
+
+    fn values(flags: &[Sourced<String>]) -> Vec<(&str, String)> {
+        flags.iter().map(|flag| (flag.value.as_str(), flag.origin.to_string())).collect()
+    }
+
+    #[test]
+    fn prefixed_variables_win_over_generic_ones_for_the_host_toolchain() {
+        let environment = environment(&[
+            ("CC", "clang"),
+            ("CFLAGS", "-O1 -pipe"),
+            ("CORGE_DEFAULT_CC", "gcc-13"),
+            ("CORGE_DEFAULT_CFLAGS", "-Wall"),
+            ("LDFLAGS", " "),
+        ]);
+
+        let effective = environment.apply("default", Toolchain::default(), ValueOrigin::BuiltIn);
+
+        assert_eq!(effective.compiler.value, "gcc-13");
+        assert_eq!(effective.compiler.origin, ValueOrigin::Environment("CORGE_DEFAULT_CC".to_string()));
+        assert_eq!(effective.archiver.origin, ValueOrigin::BuiltIn);
+        assert_eq!(values(&effective.compiler_flags), [
+            ("-O1", "environment CFLAGS".to_string()),
+            ("-pipe", "environment CFLAGS".to_string()),
+            ("-Wall", "environment CORGE_DEFAULT_CFLAGS".to_string()),
+        ]);
+        assert!(effective.linker_flags.is_empty());
+    }
+
+    #[test]
+    fn build_yaml_toolchain_only_takes_its_prefixed_variables() {
+        let environment = environment(&[
+            ("CC", "cc"),
+            ("CFLAGS", "-march=native"),
+            ("CORGE_ARM_NONE_AR", "llvm-ar"),
+            ("CORGE_ARM_NONE_LDFLAGS", "-Wl,--gc-sections"),
+        ]);
+
+        let effective = environment.apply("arm-none", toolchain(), ValueOrigin::BuildYaml);
+
+        assert_eq!(effective.compiler.value, "arm-none-eabi-gcc");
+        assert_eq!(effective.compiler.origin, ValueOrigin::BuildYaml);
+        assert_eq!(effective.archiver.value, "llvm-ar");
+        assert_eq!(values(&effective.compiler_flags), [("-mthumb", "build.yaml".to_string())]);
+        assert_eq!(values(&effective.linker_flags), [
+            ("-nostdlib", "build.yaml".to_string()),
+            ("-Wl,--gc-sections", "environment CORGE_ARM_NONE_LDFLAGS".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn target_triple_toolchain_ignores_generic_variables() {
+        let environment = environment(&[("CC", "cc"), ("CPPFLAGS", "-I/usr/include")]);
+        let toolchain = Toolchain::for_triple("aarch64-linux-gnu");
+
+        let effective = environment.apply("default", toolchain, ValueOrigin::TargetTriple);
+
+        assert_eq!(effective.compiler.value, "aarch64-linux-gnu-gcc");
+        assert_eq!(effective.compiler.origin, ValueOrigin::TargetTriple);
+        assert!(effective.compiler_flags.is_empty());
+    }
+
+    #[test]
+    fn command_line_toolchain_ignores_the_environment() {
+        let environment = environment(&[("CC", "cc"), ("CORGE_CUSTOM_CC", "clang"), ("CORGE_CUSTOM_CFLAGS", "-g")]);
+
+        let effective = environment.apply("custom", toolchain(), ValueOrigin::CommandLine);
+
+        assert_eq!(effective.compiler.value, "arm-none-eabi-gcc");
+        assert_eq!(effective.compiler.origin, ValueOrigin::CommandLine);
+        assert_eq!(values(&effective.compiler_flags), [("-mthumb", "command line".to_string())]);
+    }
+
+    #[test]
+    fn sysroot_has_the_origin_of_the_toolchain() {
+        let effective = environment(&[]).apply("arm", toolchain(), ValueOrigin::BuildYaml);
+        let sysroot = effective.sysroot.unwrap();
+
+        assert_eq!(sysroot.value, "sysroots/arm");
+        assert_eq!(sysroot.origin, ValueOrigin::BuildYaml);
+
+        let mut toolchain = Toolchain::for_triple("aarch64-linux-gnu");
+        toolchain.sysroot = Some("/opt/aarch64".to_string());
+
+        let effective = environment(&[]).apply("default", toolchain, ValueOrigin::TargetTriple);
+        assert_eq!(effective.sysroot.unwrap().origin, ValueOrigin::TargetTriple);
+    }
+
+    #[test]
+    fn variable_name_of_toolchains() {
+        assert_eq!(variable_name("arm-none"), "ARM_NONE");
+        assert_eq!(variable_name("pic24"), "PIC24");
+    }
+}
@@ -0,0 +1,266 @@
+use crate::config::{CompilerFamily, Toolchain};
+use crate::std_command_ext::ExecuteCommand;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+
+/** Flags whose support is checked once per compiler and remembered */
+const PROBED_FLAGS: &[&str] = &[
+    "-fPIC",
+    "-Og",
+    "-Oz",
+    "-Ofast",
+    "-fdiagnostics-color=always",
+];
+
+/**
+    What a compiler is and what it targets.
+    Probed once per build and cached on disk until the compiler binary, its flags or its sysroot change.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ToolchainInfo {
+    pub compiler_path: PathBuf,
+    pub family: CompilerFamily,
+    pub version: Option<String>,
+    pub target: String,
+    pub supported_flags: Vec<String>,
+}
+
+impl ToolchainInfo {
+    pub fn supports(&self, flag: &str) -> bool {
+        self.supported_flags.iter().any(|supported| supported == flag)
+    }
+}
+
+pub struct ToolchainProbe {
+    cache_path: PathBuf,
+}
+
+impl ToolchainProbe {
+    pub fn new(cache_path: PathBuf) -> Self {
+        Self {
+            cache_path,
+        }
+    }
+
+    pub fn probe(&self, toolchain: &Toolchain) -> Result<ToolchainInfo> {
+        let compiler_path = find_executable(&toolchain.compiler)
+            .ok_or_else(|| anyhow::anyhow!("Compiler '{}' was not found", &toolchain.compiler))?;
+
+        let modified = fs::metadata(&compiler_path)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("Failed to read metadata of compiler {:?}", &compiler_path))?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+
+        /* flags like --target=<triple> and the sysroot change what the compiler targets and accepts */
+        let mut probe_args = toolchain.compiler_flags.clone();
+        if let Some(sysroot) = &toolchain.sysroot {
+            probe_args.push(format!("--sysroot={}", sysroot));
+        }
+
+        let cache_file = self.cache_path.join(format!("{}.json", cache_key(&compiler_path, modified, &probe_args)));
+
+        if let Some(info) = read_cached(&cache_file) {
+            log::debug!("Using cached toolchain information for {:?}", &compiler_path);
+            return Ok(info);
+        }
+
+        log::info!("Probing compiler {:?}", &compiler_path);
+        let info = self.detect(&compiler_path, &probe_args)
+            .with_context(|| format!("Failed to probe compiler {:?}", &compiler_path))?;
+
+        fs::create_dir_all(&self.cache_path)
+            .with_context(|| format!("Failed to create directory {:?}", &self.cache_path))?;
+        fs::write(&cache_file, serde_json::to_string_pretty(&info)?)
+            .with_context(|| format!("Failed to write toolchain cache {:?}", &cache_file))?;
+
+        Ok(info)
+    }
+
+    fn detect(&self, compiler_path: &Path, probe_args: &[String]) -> Result<ToolchainInfo> {
+        /* tcc doesn't understand --version */
+        let version_output = Command::new(compiler_path)
+            .arg("--version")
+            .execute(true)
+            .or_else(|_| Command::new(compiler_path).arg("-v").execute(true))?;
+
+        let first_line = version_output.lines().next().unwrap_or_default();
+        let family = detect_family(compiler_path, first_line);
+        let version = detect_version(first_line);
+
+        let target = Command::new(compiler_path)
+            .args(probe_args)
+            .arg("-dumpmachine")
+            .execute(true)
+            .map(|stdout| stdout.trim().to_string())
+            .ok()
+            .filter(|target| !target.is_empty())
+            .unwrap_or_else(|| target_lexicon::HOST.to_string());
+
+        fs::create_dir_all(&self.cache_path)
+            .with_context(|| format!("Failed to create directory {:?}", &self.cache_path))?;
+
+        let supported_flags = PROBED_FLAGS
+            .iter()
+            .filter(|flag| self.accepts_flag(compiler_path, probe_args, flag))
+            .map(|flag| flag.to_string())
+            .collect();
+
+        Ok(ToolchainInfo {
+            compiler_path: compiler_path.to_path_buf(),
+            family,
+            version,
+            target,
+            supported_flags,
+        })
+    }
+
+    /* Compiles an empty translation unit with the flag, treating warnings about it as rejection */
+    fn accepts_flag(&self, compiler_path: &Path, probe_args: &[String], flag: &str) -> bool {
+        let probe_object = self.cache_path.join("probe.o");
+
+        let accepted = Command::new(compiler_path)
+            .args(probe_args)
+            .arg("-Werror")
+            .arg(flag)
+            .arg("-x")
+            .arg("c")
+            .arg("-c")
+            .arg("-")
+            .arg("-o")
+            .arg(&probe_object)
+            .execute(true)
+            .is_ok();
+
+        let _ = fs::remove_file(&probe_object);
+
+        accepted
+    }
+}
+
+fn detect_family(compiler_path: &Path, version_line: &str) -> CompilerFamily {
+    let version_line = version_line.to_lowercase();
+    let compiler_name = compiler_path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if compiler_name.contains("clang-cl") {
+        CompilerFamily::ClangCl
+    } else if version_line.contains("sdcc") {
+        CompilerFamily::Sdcc
+    } else if version_line.contains("xc16") {
+        CompilerFamily::Xc16
+    } else if version_line.contains("clang") {
+        CompilerFamily::Clang
+    } else if version_line.contains("intel") || version_line.contains("icx") {
+        CompilerFamily::Icx
+    } else if version_line.contains("tcc") {
+        CompilerFamily::Tcc
+    } else if version_line.contains("gcc") || version_line.contains("free software foundation") {
+        CompilerFamily::Gcc
+    } else {
+        CompilerFamily::Unknown
+    }
+}
+
+/* The first dotted number on the version line, e.g. "12.2.0", without a distribution suffix like "-1ubuntu1" */
+fn detect_version(version_line: &str) -> Option<String> {
+    version_line
+        .split(|char: char| char.is_whitespace() || char == '(' || char == ')')
+        .map(|word| word.split_once('-').map_or(word, |(version, _)| version))
+        .find(|word| {
+            word.contains('.') &&
+            word.split('.').all(|part| !part.is_empty() && part.chars().all(|char| char.is_ascii_digit()))
+        })
+        .map(str::to_string)
+}
+
+fn cache_key(compiler_path: &Path, modified: u128, probe_args: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}-{}", compiler_path.display(), modified));
+
+    for arg in probe_args {
+        hasher.update([0]);
+        hasher.update(arg);
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn read_cached(cache_file: &Path) -> Option<ToolchainInfo> {
+    let content = fs::read_to_string(cache_file).ok()?;
+
+    serde_json::from_str(&content).ok()
+}
+
+/** Resolves a program name the way the shell would, through PATH */
+pub fn find_executable(program: &str) -> Option<PathBuf> {
+    let program_path = Path::new(program);
+
+    if program_path.components().count() > 1 {
+        return program_path.is_file().then(|| program_path.to_path_buf());
+    }
+
+    let paths = env::var_os("PATH")?;
+
+    env::split_paths(&paths)
+        .flat_map(|directory| {
+            let candidate = directory.join(program);
+            let windows_candidate = directory.join(format!("{}.exe", program));
+
+            [candidate, windows_candidate]
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_is_the_first_dotted_number() {
+        assert_eq!(detect_version("gcc (Debian 12.2.0-14) 12.2.0").as_deref(), Some("12.2.0"));
+        assert_eq!(detect_version("clang version 17.0.6").as_deref(), Some("17.0.6"));
+        assert_eq!(detect_version("tcc version 0.9.27 (x86_64 Linux)").as_deref(), Some("0.9.27"));
+        assert_eq!(detect_version("no version here"), None);
+    }
+
+    #[test]
+    fn distribution_suffix_is_stripped() {
+        assert_eq!(detect_version("Ubuntu clang version 14.0.0-1ubuntu1").as_deref(), Some("14.0.0"));
+        assert_eq!(detect_version("gcc (Ubuntu 11.4.0-1ubuntu1~22.04) 11.4.0").as_deref(), Some("11.4.0"));
+    }
+
+    #[test]
+    fn family_comes_from_the_version_line_or_the_name() {
+        assert_eq!(detect_family(Path::new("cc"), "gcc (GCC) 13.2.1"), CompilerFamily::Gcc);
+        assert_eq!(detect_family(Path::new("cc"), "Ubuntu clang version 14.0.0-1ubuntu1"), CompilerFamily::Clang);
+        assert_eq!(detect_family(Path::new("clang-cl"), "clang version 17.0.6"), CompilerFamily::ClangCl);
+        assert_eq!(detect_family(Path::new("cc"), "something else"), CompilerFamily::Unknown);
+    }
+
+    #[test]
+    fn cache_key_covers_the_probe_arguments() {
+        let compiler = Path::new("/usr/bin/clang");
+        let host = cache_key(compiler, 1, &[]);
+        let cross = cache_key(compiler, 1, &["--target=aarch64-linux-gnu".to_string()]);
+        let sysroot = cache_key(compiler, 1, &["--target=aarch64-linux-gnu".to_string(), "--sysroot=/opt".to_string()]);
+
+        assert_ne!(host, cross);
+        assert_ne!(cross, sysroot);
+        assert_eq!(cross, cache_key(compiler, 1, &["--target=aarch64-linux-gnu".to_string()]));
+        assert_ne!(host, cache_key(compiler, 2, &[]));
+    }
+}
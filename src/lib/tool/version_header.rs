@@ -0,0 +1,298 @@
+use crate::std_command_ext::ExecuteCommand;
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/* the headers are included as <corge_version/<name>.h> */
+const HEADER_DIRECTORY: &str = "corge_version";
+
+/** The commit a project or dependency is built from */
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitState {
+    pub commit: String,
+    /// Tracked files of the project directory differ from the commit.
+    pub dirty: bool,
+    /// Commit time in seconds since the epoch.
+    pub timestamp: u64,
+}
+
+impl GitState {
+    /** The state of the repository containing the path, None outside of a repository or without git */
+    pub fn read(path: &Path) -> Option<Self> {
+        let head = Command::new("git")
+            .args(["log", "-1", "--format=%H %ct"])
+            .current_dir(path)
+            .execute(true)
+            .ok()?;
+
+        let (commit, timestamp) = head.trim().split_once(' ')?;
+
+        /* untracked files and changes elsewhere in the repository don't make the project dirty */
+        let status = Command::new("git")
+            .args(["status", "--porcelain", "--untracked-files=no", "--", "."])
+            .current_dir(path)
+            .execute(true)
+            .ok()?;
+
+        Some(Self {
+            commit: commit.to_string(),
+            dirty: !status.trim().is_empty(),
+            timestamp: timestamp.parse().ok()?,
+        })
+    }
+}
+
+/** The build the headers describe */
+#[derive(Debug, Clone)]
+pub struct BuildInfo {
+    pub profile: String,
+    pub toolchain: String,
+    pub target: String,
+    /// SOURCE_DATE_EPOCH, which replaces the commit time so builds are reproducible.
+    pub source_date_epoch: Option<u64>,
+}
+
+impl BuildInfo {
+    pub fn new(profile: &str, toolchain: &str, target: &str) -> Self {
+        Self {
+            profile: profile.to_string(),
+            toolchain: toolchain.to_string(),
+            target: target.to_string(),
+            source_date_epoch: std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|epoch| epoch.trim().parse().ok()),
+        }
+    }
+}
+
+/**
+    Writes <corge_version/<name>.h> for the project and every dependency into the output directory,
+    which goes on the include path of every source.
+    A header is only rewritten when its content changes, so the sources including it aren't recompiled every build.
+ */
+pub struct VersionHeaderWriter {
+    output_path: PathBuf,
+    build: BuildInfo,
+}
+
+impl VersionHeaderWriter {
+    pub fn new(output_path: &Path, build: BuildInfo) -> Self {
+        Self {
+            output_path: output_path.to_path_buf(),
+            build,
+        }
+    }
+
+    /** @return: path of the header */
+    pub fn write(&self, name: &str, version: &str, git: Option<&GitState>) -> Result<PathBuf> {
+        let header_path = self.output_path.join(HEADER_DIRECTORY).join(format!("{}.h", name));
+        let header = self.header(name, version, git)?;
+
+        if fs::read_to_string(&header_path).is_ok_and(|existing| existing == header) {
+            return Ok(header_path);
+        }
+
+        if let Some(parent) = header_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+
+        log::info!("Writing version header {:?}", &header_path);
+
+        fs::write(&header_path, header)
+            .with_context(|| format!("Failed to write {:?}", &header_path))?;
+
+        Ok(header_path)
+    }
+
+    fn header(&self, name: &str, version: &str, git: Option<&GitState>) -> Result<String> {
+        let prefix = macro_prefix(name);
+        let semver = Semver::parse(version).unwrap_or_else(|| {
+            log::warn!("Version '{}' of '{}' is not MAJOR.MINOR.PATCH, its components are 0", version, name);
+            Semver::default()
+        });
+
+        let timestamp = self.build.source_date_epoch
+            .or(git.map(|git| git.timestamp))
+            .unwrap_or_default();
+
+        let mut header = String::new();
+        writeln!(header, "/* Generated by corge-rs from build.yaml and git, do not edit */")?;
+        writeln!(header, "#ifndef CORGE_VERSION_{}_H", prefix)?;
+        writeln!(header, "#define CORGE_VERSION_{}_H", prefix)?;
+        writeln!(header)?;
+        writeln!(header, "#define {}_NAME {}", prefix, c_string(name))?;
+        writeln!(header, "#define {}_VERSION {}", prefix, c_string(version))?;
+        writeln!(header, "#define {}_VERSION_MAJOR {}", prefix, semver.major)?;
+        writeln!(header, "#define {}_VERSION_MINOR {}", prefix, semver.minor)?;
+        writeln!(header, "#define {}_VERSION_PATCH {}", prefix, semver.patch)?;
+        writeln!(header, "#define {}_VERSION_PRE {}", prefix, c_string(&semver.pre))?;
+        match semver.number() {
+            Some(number) => {
+                writeln!(header, "/* MAJOR * 1000000 + MINOR * 1000 + PATCH, for #if comparisons */")?;
+                writeln!(header, "#define {}_VERSION_NUMBER {}", prefix, number)?;
+            }
+            None => log::warn!(
+                "Version '{}' of '{}' has a minor or patch of 1000 or more or is too large, VERSION_NUMBER is left out",
+                version, name
+            ),
+        }
+        writeln!(header)?;
+        writeln!(header, "/* empty outside of a git repository */")?;
+        writeln!(header, "#define {}_GIT_COMMIT {}", prefix, c_string(git.map(|git| git.commit.as_str()).unwrap_or_default()))?;
+        writeln!(header, "#define {}_GIT_DIRTY {}", prefix, u8::from(git.is_some_and(|git| git.dirty)))?;
+        writeln!(header)?;
+        writeln!(header, "#define {}_PROFILE {}", prefix, c_string(&self.build.profile))?;
+        writeln!(header, "#define {}_TOOLCHAIN {}", prefix, c_string(&self.build.toolchain))?;
+        writeln!(header, "#define {}_TARGET {}", prefix, c_string(&self.build.target))?;
+        writeln!(header, "/* SOURCE_DATE_EPOCH, or the commit time; 0 if neither is known */")?;
+        writeln!(header, "#define {}_TIMESTAMP {}", prefix, timestamp)?;
+        writeln!(header)?;
+        writeln!(header, "#endif")?;
+
+        Ok(header)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Semver {
+    major: u64,
+    minor: u64,
+    patch: u64,
+    pre: String,
+}
+
+impl Semver {
+    /* "1.2.3", "1.2.3-beta.1", "1.2.3-beta.1+build.5"; build metadata isn't kept */
+    fn parse(version: &str) -> Option<Self> {
+        let version = version.split_once('+').map_or(version, |(version, _)| version);
+        let (core, pre) = version.split_once('-').unwrap_or((version, ""));
+
+        let mut components = core.split('.').map(|component| component.parse::<u64>().ok());
+        let semver = Self {
+            major: components.next()??,
+            minor: components.next()??,
+            patch: components.next()??,
+            pre: pre.to_string(),
+        };
+
+        components.next().is_none().then_some(semver)
+    }
+
+    /*
+        None when a minor or patch of 1000 or more would make it collide with another version, e.g. 1.1000.0 with 2.0.0,
+        or when it doesn't fit in the intmax_t #if evaluates in; the header leaves VERSION_NUMBER out then
+     */
+    fn number(&self) -> Option<u64> {
+        if self.minor >= 1_000 || self.patch >= 1_000 {
+            return None;
+        }
+
+        self.major
+            .checked_mul(1_000_000)?
+            .checked_add(self.minor.checked_mul(1_000)?)?
+            .checked_add(self.patch)
+            .filter(|number| i64::try_from(*number).is_ok())
+    }
+}
+
+/* "my-app" -> "MY_APP" */
+fn macro_prefix(name: &str) -> String {
+    let prefix: String = name
+        .chars()
+        .map(|char| if char.is_ascii_alphanumeric() { char.to_ascii_uppercase() } else { '_' })
+        .collect();
+
+    if prefix.starts_with(|char: char| char.is_ascii_digit()) {
+        format!("_{}", prefix)
+    } else {
+        prefix
+    }
+}
+
+fn c_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn writer() -> VersionHeaderWriter {
+        VersionHeaderWriter::new(Path::new("version"), BuildInfo {
+            profile: "release".to_string(),
+            toolchain: "gcc".to_string(),
+            target: "x86_64-pc-linux-gnu".to_string(),
+            source_date_epoch: Some(1_700_000_000),
+        })
+    }
+
+    #[test]
+    fn semver_parse() {
+        let semver = Semver::parse("1.22.333-beta.1+build.5").unwrap();
+        assert_eq!((semver.major, semver.minor, semver.patch, semver.pre.as_str()), (1, 22, 333, "beta.1"));
+
+        let semver = Semver::parse("0.1.0").unwrap();
+        assert_eq!((semver.major, semver.minor, semver.patch, semver.pre.as_str()), (0, 1, 0, ""));
+
+        for version in ["1.2", "1.2.3.4", "1.x.3", "", "v1.2.3"] {
+            assert!(Semver::parse(version).is_none(), "{}", version);
+        }
+    }
+
+    #[test]
+    fn semver_number() {
+        assert_eq!(Semver::parse("1.22.333").unwrap().number(), Some(1_022_333));
+        assert_eq!(Semver::parse("18446744073709551615.0.0").unwrap().number(), None);
+        assert_eq!(Semver::parse("9223372036854.775.808").unwrap().number(), None);
+        assert_eq!(Semver::parse("9223372036854.775.807").unwrap().number(), Some(i64::MAX as u64));
+        assert_eq!(Semver::parse("2.0.999").unwrap().number(), Some(2_000_999));
+        assert_eq!(Semver::parse("1.1000.0").unwrap().number(), None);
+        assert_eq!(Semver::parse("2.0.1000").unwrap().number(), None);
+    }
+
+    #[test]
+    fn macro_prefix_is_a_c_identifier() {
+        assert_eq!(macro_prefix("my-app"), "MY_APP");
+        assert_eq!(macro_prefix("2048.game"), "_2048_GAME");
+    }
+
+    #[test]
+    fn header_describes_the_build() {
+        let git = GitState {
+            commit: "0123abcd".to_string(),
+            dirty: true,
+            timestamp: 1_600_000_000,
+        };
+        let header = writer().header("my-app", "1.2.3-rc.1", Some(&git)).unwrap();
+
+        for line in [
+            "#ifndef CORGE_VERSION_MY_APP_H",
+            "#define MY_APP_VERSION \"1.2.3-rc.1\"",
+            "#define MY_APP_VERSION_PRE \"rc.1\"",
+            "#define MY_APP_VERSION_NUMBER 1002003",
+            "#define MY_APP_GIT_COMMIT \"0123abcd\"",
+            "#define MY_APP_GIT_DIRTY 1",
+            "#define MY_APP_TIMESTAMP 1700000000",
+        ] {
+            assert!(header.lines().any(|header_line| header_line == line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn header_leaves_out_an_overflowing_version_number() {
+        let header = writer().header("app", "18446744073709551615.0.0", None).unwrap();
+
+        assert!(!header.contains("VERSION_NUMBER"));
+        assert!(!header.contains("MAJOR * 1000000"));
+        assert!(header.contains("#define APP_GIT_COMMIT \"\""));
+    }
+
+    #[test]
+    fn header_leaves_out_an_ambiguous_version_number() {
+        let header = writer().header("app", "1.1000.0", None).unwrap();
+
+        assert!(!header.contains("VERSION_NUMBER"));
+        assert!(header.contains("#define APP_VERSION_MINOR 1000"));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::config::{Config, Workspace};
+use crate::tool::configuration_parser::ConfigurationParser;
+use crate::tool::files_fetcher;
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub path: PathBuf,
+    pub config: Config,
+}
+
+/**
+    Expands the workspace member globs and orders members so that
+    every member comes after the members it depends on.
+ */
+pub struct WorkspaceResolver {
+    root_path: PathBuf,
+    workspace: Workspace,
+}
+
+impl WorkspaceResolver {
+    pub fn new(root_path: PathBuf, workspace: Workspace) -> Self {
+        Self {
+            root_path,
+            workspace,
+        }
+    }
+
+    pub fn resolve(&self) -> Result<Vec<Member>> {
+        let mut member_paths = vec![];
+
+        for pattern in &self.workspace.members {
+            let full_pattern = files_fetcher::project_pattern(&self.root_path, pattern);
+
+            let paths = glob::glob(&full_pattern)
+                .with_context(|| format!("Invalid workspace member pattern '{}'", pattern))?;
+
+            for path in paths {
+                let path = path.with_context(|| format!("Failed to expand workspace member pattern '{}'", pattern))?;
+
+                if path.join("build.yaml").is_file() && !member_paths.contains(&path) {
+                    member_paths.push(path);
+                }
+            }
+        }
+
+        member_paths.sort();
+
+        let mut members = vec![];
+        for member_path in member_paths {
+            let config = ConfigurationParser::new(member_path.clone())
+                .parse()
+                .with_context(|| format!("Failed to parse workspace member {:?}", &member_path))?;
+
+            if config.workspace.is_some() {
+                anyhow::bail!("Workspace member {:?} must not declare a nested workspace", &member_path);
+            }
+
+            if let Some(duplicate) = members.iter().find(|member: &&Member| member.name == config.project.name) {
+                anyhow::bail!(
+                    "Workspace members {:?} and {:?} have the same project name '{}'",
+                    &duplicate.path, &member_path, &config.project.name
+                );
+            }
+
+            members.push(Member {
+                name: config.project.name.clone(),
+                path: member_path,
+                config,
+            });
+        }
+
+        sort_by_dependencies(members)
+    }
+}
+
+/**
+    Keeps only the requested members and the members they transitively depend on.
+    An empty selection keeps every member.
+ */
+pub fn select(members: Vec<Member>, packages: &[String]) -> Result<Vec<Member>> {
+    if packages.is_empty() {
+        return Ok(members);
+    }
+
+    for package in packages {
+        if !members.iter().any(|member| &member.name == package) {
+            anyhow::bail!("Package '{}' is not a member of the workspace", package);
+        }
+    }
+
+    let mut selected: HashSet<String> = packages.iter().cloned().collect();
+
+    /* members are in dependency order, so walking backwards visits dependents first */
+    for member in members.iter().rev() {
+        if selected.contains(&member.name) {
+            for dependency in member.config.all_dependencies() {
+                selected.insert(dependency.name.clone());
+            }
+        }
+    }
+
+    Ok(members.into_iter().filter(|member| selected.contains(&member.name)).collect())
+}
+
+pub fn member_paths(members: &[Member]) -> HashMap<String, PathBuf> {
+    members
+        .iter()
+        .map(|member| (member.name.clone(), member.path.clone()))
+        .collect()
+}
+
+fn sort_by_dependencies(members: Vec<Member>) -> Result<Vec<Member>> {
+    let names: HashSet<&str> = members.iter().map(|member| member.name.as_str()).collect();
+    let index: HashMap<&str, &Member> = members.iter().map(|member| (member.name.as_str(), member)).collect();
+
+    let mut order: Vec<String> = vec![];
+    let mut visiting: Vec<String> = vec![];
+
+    for member in &members {
+        visit(member, &names, &index, &mut visiting, &mut order)?;
+    }
+
+    let mut members_by_name: HashMap<String, Member> = members
+        .into_iter()
+        .map(|member| (member.name.clone(), member))
+        .collect();
+
+    Ok(order
+        .into_iter()
+        .filter_map(|name| members_by_name.remove(&name))
+        .collect())
+}
+
+fn visit(
+    member: &Member,
+    names: &HashSet<&str>,
+    index: &HashMap<&str, &Member>,
+    visiting: &mut Vec<String>,
+    order: &mut Vec<String>,
+) -> Result<()> {
+    if order.contains(&member.name) {
+        return Ok(());
+    }
+
+    if visiting.contains(&member.name) {
+        visiting.push(member.name.clone());
+        anyhow::bail!("Workspace members have a dependency cycle: {}", visiting.join(" -> "));
+    }
+
+    visiting.push(member.name.clone());
+
+    /* conditional dependencies are ordered too, the target isn't known yet */
+    for dependency in member.config.all_dependencies() {
+        if names.contains(dependency.name.as_str()) {
+            visit(index[dependency.name.as_str()], names, index, visiting, order)?;
+        }
+    }
+
+    visiting.pop();
+    order.push(member.name.clone());
+
+    Ok(())
+}